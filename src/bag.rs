@@ -0,0 +1,599 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::ops::Deref;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use byteorder::{ReadBytesExt, LE};
+use memmap2::Mmap;
+
+use crate::cursor::Cursor;
+use crate::mcap::{McapFile, McapIndex, McapMessages, MCAP_MAGIC};
+use crate::record::{
+    decompress_into, read_record, Connection, Header, Record, RecordError, BAG_MAGIC,
+    OP_BAG_HEADER, OP_CHUNK, OP_CHUNK_INFO, OP_CONNECTION, OP_INDEX_DATA, OP_MESSAGE_DATA,
+};
+
+enum BagData {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl Deref for BagData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            BagData::Mapped(mmap) => mmap,
+            BagData::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// An indexed bag or MCAP file held in memory or mapped from a file
+pub struct Bag {
+    data: BagData,
+    chunks_pos: u64,
+    index_pos: u64,
+    conn_count: u32,
+    chunk_count: u32,
+    mcap: Option<McapIndex>,
+}
+
+impl Bag {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        // The file is only ever read, same as rosbag-rs
+        let mmap = unsafe { Mmap::map(&file)? };
+        Self::parse(BagData::Mapped(mmap))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::parse(BagData::Owned(bytes.to_vec()))
+    }
+
+    pub fn from_vec(bytes: Vec<u8>) -> Result<Self> {
+        Self::parse(BagData::Owned(bytes))
+    }
+
+    /// Read a bag starting at the current position of `reader`
+    pub fn from_reader<R: Read + Seek>(mut reader: R) -> Result<Self> {
+        let start = reader.stream_position()?;
+        let end = reader.seek(io::SeekFrom::End(0))?;
+        reader.seek(io::SeekFrom::Start(start))?;
+        let mut bytes = Vec::with_capacity((end - start) as usize);
+        reader.read_to_end(&mut bytes)?;
+        Self::from_vec(bytes)
+    }
+
+    fn parse(data: BagData) -> Result<Self> {
+        if data.starts_with(MCAP_MAGIC) {
+            let mcap = McapIndex::parse(&data)?;
+            // There are no ROS1 records to read
+            let end = data.len() as u64;
+            return Ok(Self {
+                chunks_pos: end,
+                index_pos: end,
+                conn_count: mcap.channels.len() as u32,
+                chunk_count: mcap.chunk_indexes.len() as u32,
+                mcap: Some(mcap),
+                data,
+            });
+        }
+        if !data.starts_with(BAG_MAGIC) {
+            bail!("Not a rosbag 2.0 or MCAP file");
+        }
+        let header = read_record(&data, BAG_MAGIC.len() as u64)?;
+        if header.op != OP_BAG_HEADER {
+            bail!("Expected bag header record, found op 0x{:02x}", header.op);
+        }
+        let index_pos = header.header.u64("index_pos")?;
+        if index_pos == 0 || index_pos > data.len() as u64 {
+            bail!("Bag has no index, it may still be recording or be truncated");
+        }
+        let conn_count = header.header.u32("conn_count")?;
+        let chunk_count = header.header.u32("chunk_count")?;
+        let chunks_pos = header.offset + header.len;
+
+        Ok(Self {
+            data,
+            chunks_pos,
+            index_pos,
+            conn_count,
+            chunk_count,
+            mcap: None,
+        })
+    }
+
+    /// The MCAP index and data if this is an MCAP file
+    pub fn mcap(&self) -> Option<McapFile<'_>> {
+        self.mcap.as_ref().map(|index| McapFile {
+            index,
+            data: &self.data,
+        })
+    }
+
+    pub fn conn_count(&self) -> u32 {
+        self.conn_count
+    }
+
+    pub fn chunk_count(&self) -> u32 {
+        self.chunk_count
+    }
+
+    /// Connection and chunk info records of the index section, none for MCAP files
+    pub fn index_records(&self) -> IndexRecords<'_> {
+        IndexRecords {
+            records: Records {
+                data: &self.data,
+                pos: self.index_pos,
+                end: self.data.len() as u64,
+            },
+        }
+    }
+
+    /// Chunk and index data records between the bag header and the index section,
+    /// none for MCAP files
+    pub fn chunk_records(&self) -> ChunkRecords<'_> {
+        ChunkRecords {
+            records: Records {
+                data: &self.data,
+                pos: self.chunks_pos,
+                end: self.index_pos,
+            },
+        }
+    }
+
+    /// All messages in file order, one chunk is decompressed at a time
+    pub fn messages(&self) -> BagMessages<'_> {
+        BagMessages {
+            chunk_records: self.chunk_records(),
+            chunk: Cow::Borrowed(&[]),
+            pos: 0,
+            mcap: self.mcap().map(|mcap| mcap.messages()),
+        }
+    }
+}
+
+struct Records<'a> {
+    data: &'a [u8],
+    pos: u64,
+    end: u64,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.end {
+            return None;
+        }
+        match read_record(self.data, self.pos) {
+            Ok(record) => {
+                self.pos += record.len;
+                Some(Ok(record))
+            }
+            Err(e) => {
+                // Without a valid record length there is no way to continue
+                self.pos = self.end;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+pub enum IndexRecord<'a> {
+    Connection(Connection),
+    ChunkInfo(ChunkInfo<'a>),
+}
+
+pub struct IndexRecords<'a> {
+    records: Records<'a>,
+}
+
+impl<'a> Iterator for IndexRecords<'a> {
+    type Item = Result<IndexRecord<'a>, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.records.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(match record.op {
+            OP_CONNECTION => Connection::from_record(&record).map(IndexRecord::Connection),
+            OP_CHUNK_INFO => ChunkInfo::from_record(&record).map(IndexRecord::ChunkInfo),
+            op => Err(RecordError::UnknownOp(op)),
+        })
+    }
+}
+
+pub struct ChunkInfo<'a> {
+    pub chunk_pos: u64,
+    pub start_time: u64,
+    pub end_time: u64,
+    data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkInfoEntry {
+    pub conn_id: u32,
+    pub count: u32,
+}
+
+impl<'a> ChunkInfo<'a> {
+    fn from_record(record: &Record<'a>) -> Result<Self, RecordError> {
+        Ok(Self {
+            chunk_pos: record.header.u64("chunk_pos")?,
+            start_time: record.header.time("start_time")?,
+            end_time: record.header.time("end_time")?,
+            data: record.data,
+        })
+    }
+
+    /// Number of messages per connection in the chunk
+    pub fn entries(&self) -> impl Iterator<Item = ChunkInfoEntry> + 'a {
+        self.data.chunks_exact(8).map(|entry| {
+            let mut cursor = Cursor::new(entry);
+            ChunkInfoEntry {
+                conn_id: cursor.next_u32().unwrap(),
+                count: cursor.next_u32().unwrap(),
+            }
+        })
+    }
+}
+
+pub enum ChunkRecord<'a> {
+    Chunk(Chunk<'a>),
+    IndexData(IndexData<'a>),
+}
+
+pub struct ChunkRecords<'a> {
+    records: Records<'a>,
+}
+
+impl<'a> Iterator for ChunkRecords<'a> {
+    type Item = Result<ChunkRecord<'a>, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.records.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(match record.op {
+            OP_CHUNK => Chunk::from_record(&record).map(ChunkRecord::Chunk),
+            OP_INDEX_DATA => IndexData::from_record(&record).map(ChunkRecord::IndexData),
+            op => Err(RecordError::UnknownOp(op)),
+        })
+    }
+}
+
+/// A chunk is only decompressed when its messages are needed
+pub struct Chunk<'a> {
+    pub offset: u64,
+    pub compression: &'a str,
+    pub size: u32,
+    data: &'a [u8],
+}
+
+impl<'a> Chunk<'a> {
+    fn from_record(record: &Record<'a>) -> Result<Self, RecordError> {
+        Ok(Self {
+            offset: record.offset,
+            compression: record.header.str("compression")?,
+            size: record.header.u32("size")?,
+            data: record.data,
+        })
+    }
+
+    pub fn decompress(&self) -> Result<ChunkData<'a>, RecordError> {
+        if self.compression == "none" {
+            return Ok(ChunkData {
+                data: Cow::Borrowed(self.data),
+            });
+        }
+        let mut data = Vec::with_capacity(self.size as usize);
+        decompress_into(self.compression, self.data, &mut data)?;
+        if data.len() != self.size as usize {
+            return Err(RecordError::Decompression(format!(
+                "expected {} bytes, got {}",
+                self.size,
+                data.len()
+            )));
+        }
+        Ok(ChunkData {
+            data: Cow::Owned(data),
+        })
+    }
+}
+
+pub struct ChunkData<'a> {
+    data: Cow<'a, [u8]>,
+}
+
+impl ChunkData<'_> {
+    /// The decompressed chunk data
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn messages(&self) -> MessageRecords<'_> {
+        MessageRecords {
+            records: Records {
+                data: &self.data,
+                pos: 0,
+                end: self.data.len() as u64,
+            },
+        }
+    }
+}
+
+pub enum MessageRecord<'a> {
+    Connection(Connection),
+    MessageData(MessageData<'a>),
+}
+
+pub struct MessageData<'a> {
+    pub conn_id: u32,
+    pub time: u64,
+    pub data: &'a [u8],
+}
+
+pub struct MessageRecords<'a> {
+    records: Records<'a>,
+}
+
+impl<'a> Iterator for MessageRecords<'a> {
+    type Item = Result<MessageRecord<'a>, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.records.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(match record.op {
+            OP_CONNECTION => Connection::from_record(&record).map(MessageRecord::Connection),
+            OP_MESSAGE_DATA => MessageData::from_record(&record).map(MessageRecord::MessageData),
+            op => Err(RecordError::UnknownOp(op)),
+        })
+    }
+}
+
+impl<'a> MessageData<'a> {
+    fn from_record(record: &Record<'a>) -> Result<Self, RecordError> {
+        Ok(Self {
+            conn_id: record.header.u32("conn")?,
+            time: record.header.time("time")?,
+            data: record.data,
+        })
+    }
+}
+
+pub struct IndexData<'a> {
+    pub conn_id: u32,
+    pub count: u32,
+    data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IndexDataEntry {
+    pub time: u64,
+    /// Offset of the message record in the decompressed chunk data
+    pub offset: u32,
+}
+
+impl<'a> IndexData<'a> {
+    fn from_record(record: &Record<'a>) -> Result<Self, RecordError> {
+        Ok(Self {
+            conn_id: record.header.u32("conn")?,
+            count: record.header.u32("count")?,
+            data: record.data,
+        })
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = IndexDataEntry> + 'a {
+        self.data.chunks_exact(12).map(|entry| {
+            let mut cursor = Cursor::new(entry);
+            IndexDataEntry {
+                time: cursor.next_time().unwrap(),
+                offset: cursor.next_u32().unwrap(),
+            }
+        })
+    }
+}
+
+pub struct BagMessages<'a> {
+    chunk_records: ChunkRecords<'a>,
+    chunk: Cow<'a, [u8]>,
+    pos: u64,
+    mcap: Option<McapMessages<'a>>,
+}
+
+impl Iterator for BagMessages<'_> {
+    type Item = Result<Message, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(messages) = &mut self.mcap {
+            return messages.next();
+        }
+        loop {
+            while self.pos < self.chunk.len() as u64 {
+                let record = match read_record(&self.chunk, self.pos) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        self.pos = self.chunk.len() as u64;
+                        return Some(Err(e));
+                    }
+                };
+                self.pos += record.len;
+                if record.op == OP_MESSAGE_DATA {
+                    return Some(MessageData::from_record(&record).map(|message| Message {
+                        conn_id: message.conn_id,
+                        time: message.time,
+                        data: message.data.to_vec(),
+                    }));
+                }
+            }
+
+            match self.chunk_records.next()? {
+                Ok(ChunkRecord::Chunk(chunk)) => match chunk.decompress() {
+                    Ok(data) => {
+                        self.chunk = data.data;
+                        self.pos = 0;
+                    }
+                    Err(e) => return Some(Err(e)),
+                },
+                Ok(ChunkRecord::IndexData(_)) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub conn_id: u32,
+    pub time: u64,
+    pub data: Vec<u8>,
+}
+
+/// Sequential reader for non-seekable sources such as stdin.
+/// The index section is not needed, connections are picked up from the chunks as they appear.
+pub struct BagStream<R: Read> {
+    reader: R,
+    connections: BTreeMap<u32, Connection>,
+    chunk: Vec<u8>,
+    pos: u64,
+}
+
+impl<R: Read> BagStream<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; BAG_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != BAG_MAGIC {
+            bail!("Not a rosbag 2.0 stream");
+        }
+        Ok(Self {
+            reader,
+            connections: BTreeMap::new(),
+            chunk: vec![],
+            pos: 0,
+        })
+    }
+
+    pub fn connection(&self, conn_id: u32) -> Option<&Connection> {
+        self.connections.get(&conn_id)
+    }
+
+    pub fn connections(&self) -> &BTreeMap<u32, Connection> {
+        &self.connections
+    }
+
+    /// Read the next top-level record, `None` at the end of the stream
+    fn next_record(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let header_len = match self.reader.read_u32::<LE>() {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let header = read_len(&mut self.reader, header_len)?;
+        let data_len = self.reader.read_u32::<LE>()?;
+        let data = read_len(&mut self.reader, data_len)?;
+        Ok(Some((header, data)))
+    }
+
+    pub fn next_message(&mut self) -> Result<Option<Message>> {
+        loop {
+            // Drain the current chunk first
+            while self.pos < self.chunk.len() as u64 {
+                // A broken record skips the rest of the chunk, so iterating goes on past it
+                let record = match read_record(&self.chunk, self.pos) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        self.pos = self.chunk.len() as u64;
+                        return Err(e.into());
+                    }
+                };
+                self.pos += record.len;
+                match record.op {
+                    OP_CONNECTION => match Connection::from_record(&record) {
+                        Ok(connection) => {
+                            self.connections.insert(connection.id, connection);
+                        }
+                        Err(e) => {
+                            self.pos = self.chunk.len() as u64;
+                            return Err(e.into());
+                        }
+                    },
+                    OP_MESSAGE_DATA => {
+                        let message = match MessageData::from_record(&record) {
+                            Ok(message) => message,
+                            Err(e) => {
+                                self.pos = self.chunk.len() as u64;
+                                return Err(e.into());
+                            }
+                        };
+                        return Ok(Some(Message {
+                            conn_id: message.conn_id,
+                            time: message.time,
+                            data: message.data.to_vec(),
+                        }));
+                    }
+                    op => bail!("Unexpected record op 0x{:02x} in chunk", op),
+                }
+            }
+
+            let (header, data) = match self.next_record()? {
+                Some(record) => record,
+                None => return Ok(None),
+            };
+            let header = Header::parse(&header)?;
+            match header.op()? {
+                OP_CHUNK => {
+                    self.chunk.clear();
+                    self.pos = 0;
+                    decompress_into(header.str("compression")?, &data, &mut self.chunk)?;
+                }
+                OP_CONNECTION => {
+                    let connection = Connection::from_record(&Record {
+                        offset: 0,
+                        len: 0,
+                        op: OP_CONNECTION,
+                        header,
+                        data: &data,
+                    })?;
+                    self.connections.insert(connection.id, connection);
+                }
+                OP_MESSAGE_DATA => {
+                    return Ok(Some(Message {
+                        conn_id: header.u32("conn")?,
+                        time: header.time("time")?,
+                        data,
+                    }));
+                }
+                OP_BAG_HEADER | OP_INDEX_DATA | OP_CHUNK_INFO => {}
+                op => bail!("Unknown record op 0x{:02x}", op),
+            }
+        }
+    }
+}
+
+/// Read `len` bytes, growing the buffer as they arrive so that a corrupt length
+/// does not allocate up front
+fn read_len<R: Read>(reader: &mut R, len: u32) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+impl<R: Read> Iterator for BagStream<R> {
+    type Item = Result<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_message().transpose()
+    }
+}
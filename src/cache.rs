@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::bag::{Bag, ChunkRecord, IndexRecord};
+use crate::indexing::find_bags;
+use crate::mcap::McapFile;
+use crate::record::Connection;
+
+/// Bump when the layout of `BagSummary` changes so old cache files are ignored
+const CACHE_VERSION: u32 = 1;
+const SIDECAR_EXTENSION: &str = "rebag-index.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkInfoSummary {
+    pub chunk_pos: u64,
+    pub start_time: u64,
+    pub end_time: u64,
+    /// Message count per connection id
+    pub counts: BTreeMap<u32, u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TopicSummary {
+    pub message_count: u64,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+}
+
+/// Everything the index section of a bag tells about it, without the messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BagSummary {
+    version: u32,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Modification time in nanoseconds since the epoch
+    pub mtime: u64,
+    pub connections: Vec<Connection>,
+    pub chunk_infos: Vec<ChunkInfoSummary>,
+    pub topics: BTreeMap<String, TopicSummary>,
+}
+
+impl BagSummary {
+    pub fn read(path: &Path) -> Result<Self> {
+        let (size, mtime) = file_stamp(path)?;
+        let bag = Bag::open(path)?;
+        if let Some(mcap) = bag.mcap() {
+            return Self::read_mcap(path, size, mtime, mcap);
+        }
+
+        let mut connections = vec![];
+        let mut chunk_infos = vec![];
+        for record in bag.index_records() {
+            match record? {
+                IndexRecord::Connection(conn) => connections.push(conn),
+                IndexRecord::ChunkInfo(chunk_info) => chunk_infos.push(ChunkInfoSummary {
+                    chunk_pos: chunk_info.chunk_pos,
+                    start_time: chunk_info.start_time,
+                    end_time: chunk_info.end_time,
+                    counts: chunk_info
+                        .entries()
+                        .map(|entry| (entry.conn_id, entry.count))
+                        .collect(),
+                }),
+            }
+        }
+
+        let conn_id_to_topic: BTreeMap<u32, &str> = connections
+            .iter()
+            .map(|conn| (conn.id, conn.topic.as_str()))
+            .collect();
+        let mut topics: BTreeMap<String, TopicSummary> = connections
+            .iter()
+            .map(|conn| (conn.topic.clone(), TopicSummary::default()))
+            .collect();
+
+        // Index data records hold the time of every message, the chunks are not decompressed
+        for record in bag.chunk_records() {
+            if let ChunkRecord::IndexData(index_data) = record? {
+                let Some(topic) = conn_id_to_topic.get(&index_data.conn_id) else {
+                    continue;
+                };
+                let summary = topics.get_mut(*topic).unwrap();
+                for entry in index_data.entries() {
+                    summary.message_count += 1;
+                    summary.start_time = Some(
+                        summary
+                            .start_time
+                            .map_or(entry.time, |start| start.min(entry.time)),
+                    );
+                    summary.end_time = Some(
+                        summary
+                            .end_time
+                            .map_or(entry.time, |end| end.max(entry.time)),
+                    );
+                }
+            }
+        }
+
+        Ok(Self {
+            version: CACHE_VERSION,
+            path: path.to_path_buf(),
+            size,
+            mtime,
+            connections,
+            chunk_infos,
+            topics,
+        })
+    }
+
+    /// Same as `read` with chunk infos and message times from the chunk and message indexes
+    fn read_mcap(path: &Path, size: u64, mtime: u64, mcap: McapFile) -> Result<Self> {
+        let connections = mcap.index.connections();
+        let mut chunk_infos = vec![];
+        for chunk_index in mcap.index.chunk_indexes.iter() {
+            chunk_infos.push(ChunkInfoSummary {
+                chunk_pos: chunk_index.chunk_pos,
+                start_time: chunk_index.start_time,
+                end_time: chunk_index.end_time,
+                counts: mcap
+                    .chunk_message_times(chunk_index)?
+                    .into_iter()
+                    .map(|(channel_id, times)| (channel_id as u32, times.len() as u32))
+                    .collect(),
+            });
+        }
+
+        let mut topics: BTreeMap<String, TopicSummary> = connections
+            .iter()
+            .map(|conn| (conn.topic.clone(), TopicSummary::default()))
+            .collect();
+        for (channel_id, times) in mcap.message_times()? {
+            let Some(conn) = connections.iter().find(|conn| conn.id == channel_id as u32) else {
+                continue;
+            };
+            let summary = topics.get_mut(&conn.topic).unwrap();
+            summary.message_count += times.len() as u64;
+            for time in times {
+                summary.start_time = Some(summary.start_time.map_or(time, |start| start.min(time)));
+                summary.end_time = Some(summary.end_time.map_or(time, |end| end.max(time)));
+            }
+        }
+
+        Ok(Self {
+            version: CACHE_VERSION,
+            path: path.to_path_buf(),
+            size,
+            mtime,
+            connections,
+            chunk_infos,
+            topics,
+        })
+    }
+
+    /// Same as `get_topics`
+    pub fn get_topics(&self) -> BTreeMap<String, String> {
+        self.connections
+            .iter()
+            .map(|conn| (conn.topic.clone(), conn.message_definition.clone()))
+            .collect()
+    }
+
+    /// Same as `get_message_count`
+    pub fn get_message_count(&self) -> BTreeMap<String, u64> {
+        self.topics
+            .iter()
+            .map(|(topic, summary)| (topic.clone(), summary.message_count))
+            .collect()
+    }
+
+    pub fn start_time(&self) -> Option<u64> {
+        self.chunk_infos.iter().map(|info| info.start_time).min()
+    }
+
+    pub fn end_time(&self) -> Option<u64> {
+        self.chunk_infos.iter().map(|info| info.end_time).max()
+    }
+
+    fn is_valid_for(&self, path: &Path) -> bool {
+        match file_stamp(path) {
+            Ok((size, mtime)) => {
+                self.version == CACHE_VERSION
+                    && self.path == path
+                    && self.size == size
+                    && self.mtime == mtime
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Where bag summaries are kept between runs.
+/// A summary is reused as long as path, size and modification time of the bag match.
+#[derive(Debug, Clone)]
+pub enum IndexCache {
+    /// Always read the index from the bag
+    Disabled,
+    /// Store the summary next to the bag as `<bag>.rebag-index.json`
+    Sidecar,
+    /// Store all summaries in one directory, e.g. when the bags are on a read-only share
+    Directory(PathBuf),
+}
+
+impl IndexCache {
+    fn cache_path(&self, bag_path: &Path) -> Option<PathBuf> {
+        match self {
+            IndexCache::Disabled => None,
+            IndexCache::Sidecar => {
+                let mut file_name = bag_path.file_name()?.to_os_string();
+                file_name.push(".");
+                file_name.push(SIDECAR_EXTENSION);
+                Some(bag_path.with_file_name(file_name))
+            }
+            IndexCache::Directory(dir) => {
+                // Percent-encoded so that different paths never share a file name
+                let file_name = bag_path
+                    .to_string_lossy()
+                    .replace('%', "%25")
+                    .replace('/', "%2F")
+                    .replace('\\', "%5C")
+                    .replace(':', "%3A");
+                Some(dir.join(format!("{}.{}", file_name, SIDECAR_EXTENSION)))
+            }
+        }
+    }
+
+    pub fn summary(&self, bag_path: &Path) -> Result<BagSummary> {
+        // Different spellings of the same path should share one cache entry
+        let bag_path = &bag_path.canonicalize()?;
+        let Some(cache_path) = self.cache_path(bag_path) else {
+            return BagSummary::read(bag_path);
+        };
+
+        if let Ok(cached) = fs::read(&cache_path) {
+            if let Ok(summary) = serde_json::from_slice::<BagSummary>(&cached) {
+                if summary.is_valid_for(bag_path) {
+                    return Ok(summary);
+                }
+            }
+        }
+
+        let summary = BagSummary::read(bag_path)?;
+        // The cache is an optimization, failing to write it must not fail the read
+        if let Some(dir) = cache_path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(&cache_path, serde_json::to_vec(&summary)?);
+        Ok(summary)
+    }
+}
+
+/// Summaries of all bags in a directory, keyed by file name
+pub fn read_bag_summaries(path: &Path, cache: &IndexCache) -> Result<BTreeMap<String, BagSummary>> {
+    let mut summaries = BTreeMap::new();
+    for bag_path in find_bags(path, false)? {
+        let file_name = bag_path.file_name().unwrap().to_string_lossy().to_string();
+        summaries.insert(file_name, cache.summary(&bag_path)?);
+    }
+    Ok(summaries)
+}
+
+fn file_stamp(path: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(path)?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+    Ok((metadata.len(), mtime))
+}
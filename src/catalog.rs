@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cache::{BagSummary, IndexCache};
+use crate::indexing::find_bags;
+
+/// Summaries of all bags below a directory, for queries across bags
+pub struct Catalog {
+    pub root: PathBuf,
+    pub bags: Vec<BagSummary>,
+    /// Bags that could not be read, with the reason
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CatalogQuery {
+    /// Only bags that contain this topic
+    pub topic: Option<String>,
+    /// Only bags with at least this many messages, on `topic` if given
+    pub min_count: Option<u64>,
+    /// Only bags that overlap the window from `start` to `end`
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CatalogEntry {
+    pub path: PathBuf,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    pub size: u64,
+    /// Messages on the queried topic, or in the whole bag
+    pub message_count: u64,
+}
+
+impl Catalog {
+    pub fn build(root: &Path, cache: &IndexCache) -> Result<Self> {
+        let mut bags = vec![];
+        let mut errors = vec![];
+        // One broken bag should not hide the thousands of others
+        for bag_path in find_bags(root, true)? {
+            match cache.summary(&bag_path) {
+                Ok(summary) => bags.push(summary),
+                Err(e) => errors.push((bag_path, e.to_string())),
+            }
+        }
+        Ok(Self {
+            // Summaries carry canonical paths
+            root: root.canonicalize()?,
+            bags,
+            errors,
+        })
+    }
+
+    pub fn query(&self, query: &CatalogQuery) -> Vec<CatalogEntry> {
+        self.bags
+            .iter()
+            .filter_map(|bag| {
+                let message_count = match &query.topic {
+                    Some(topic) => bag.topics.get(topic)?.message_count,
+                    None => bag.topics.values().map(|topic| topic.message_count).sum(),
+                };
+                if query
+                    .min_count
+                    .is_some_and(|min_count| message_count < min_count)
+                {
+                    return None;
+                }
+
+                let (start_time, end_time) = match &query.topic {
+                    Some(topic) => (bag.topics[topic].start_time, bag.topics[topic].end_time),
+                    None => (bag.start_time(), bag.end_time()),
+                };
+                if query.start.is_some() || query.end.is_some() {
+                    let (Some(bag_start), Some(bag_end)) = (start_time, end_time) else {
+                        return None;
+                    };
+                    if query.start.is_some_and(|start| bag_end < start)
+                        || query.end.is_some_and(|end| bag_start > end)
+                    {
+                        return None;
+                    }
+                }
+
+                Some(CatalogEntry {
+                    path: bag
+                        .path
+                        .strip_prefix(&self.root)
+                        .unwrap_or(&bag.path)
+                        .to_path_buf(),
+                    start_time,
+                    end_time,
+                    size: bag.size,
+                    message_count,
+                })
+            })
+            .collect()
+    }
+}
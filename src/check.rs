@@ -0,0 +1,443 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::Result;
+use memmap2::Mmap;
+
+use crate::cursor::Cursor;
+use crate::record::{
+    decompress_into, read_record, Connection, Record, RecordError, BAG_MAGIC, OP_BAG_HEADER,
+    OP_CHUNK, OP_CHUNK_INFO, OP_CONNECTION, OP_INDEX_DATA, OP_MESSAGE_DATA,
+};
+
+#[derive(Debug)]
+pub struct Problem {
+    /// Offset of the record the problem was found in
+    pub offset: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub connections: usize,
+    pub chunks: usize,
+    pub messages: u64,
+    pub problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// What a chunk actually contains, to compare with its index records
+#[derive(Default)]
+struct ChunkContent {
+    /// Connection id and time of the message records by offset in the decompressed data
+    messages: BTreeMap<u64, (u32, u64)>,
+    counts: BTreeMap<u32, u32>,
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+    /// Message counts of the index data records following the chunk
+    indexed: BTreeMap<u32, u32>,
+}
+
+#[derive(Default)]
+struct Checker {
+    report: CheckReport,
+    chunks: BTreeMap<u64, ChunkContent>,
+    /// Connection ids defined by connection records, in chunks or in the index
+    defined: BTreeSet<u32>,
+    /// Connection ids used by messages and index records, with the first offset using them
+    used: BTreeMap<u32, u64>,
+}
+
+/// Verify the structure of a bag: record layout, chunk sizes, index data offsets,
+/// chunk info counts and time ranges against the actual messages, and connection ids
+pub fn check(bytes: &[u8]) -> CheckReport {
+    let mut checker = Checker::default();
+    checker.check(bytes);
+    checker.report
+}
+
+pub fn check_bag(path: &Path) -> Result<CheckReport> {
+    let file = File::open(path)?;
+    // The file is only ever read, same as `Bag::open`
+    let mmap = unsafe { Mmap::map(&file)? };
+    Ok(check(&mmap))
+}
+
+impl Checker {
+    fn problem(&mut self, offset: u64, message: String) {
+        self.report.problems.push(Problem { offset, message });
+    }
+
+    fn check(&mut self, bytes: &[u8]) {
+        if !bytes.starts_with(BAG_MAGIC) {
+            self.problem(0, "not a rosbag 2.0 file".to_string());
+            return;
+        }
+        let header_pos = BAG_MAGIC.len() as u64;
+        let header = match read_record(bytes, header_pos) {
+            Ok(record) if record.op == OP_BAG_HEADER => record,
+            Ok(record) => {
+                let message = format!("expected bag header record, found op 0x{:02x}", record.op);
+                self.problem(header_pos, message);
+                return;
+            }
+            Err(e) => {
+                self.problem(header_pos, format!("invalid bag header: {}", e));
+                return;
+            }
+        };
+        let fields = (|| -> Result<_, RecordError> {
+            Ok((
+                header.header.u64("index_pos")?,
+                header.header.u32("conn_count")?,
+                header.header.u32("chunk_count")?,
+            ))
+        })();
+        let (index_pos, conn_count, chunk_count) = match fields {
+            Ok(fields) => fields,
+            Err(e) => {
+                self.problem(header_pos, format!("invalid bag header: {}", e));
+                return;
+            }
+        };
+
+        let chunks_pos = header.offset + header.len;
+        let chunks_end = if index_pos == 0 {
+            self.problem(header_pos, "bag has no index".to_string());
+            bytes.len() as u64
+        } else if index_pos < chunks_pos || index_pos > bytes.len() as u64 {
+            let message = format!(
+                "index position {} is outside of the file of {} bytes",
+                index_pos,
+                bytes.len()
+            );
+            self.problem(header_pos, message);
+            bytes.len() as u64
+        } else {
+            index_pos
+        };
+
+        self.check_chunk_section(bytes, chunks_pos, chunks_end);
+        // Without an index section the header counts still have to match its missing records
+        self.check_index_section(bytes, chunks_end, conn_count, chunk_count);
+
+        let undefined: Vec<(u32, u64)> = self
+            .used
+            .iter()
+            .filter(|(conn_id, _)| !self.defined.contains(conn_id))
+            .map(|(conn_id, offset)| (*conn_id, *offset))
+            .collect();
+        for (conn_id, offset) in undefined {
+            self.problem(offset, format!("connection {} is not defined", conn_id));
+        }
+        self.report.connections = self.defined.len();
+        self.report.chunks = self.chunks.len();
+    }
+
+    fn use_conn(&mut self, conn_id: u32, offset: u64) {
+        self.used.entry(conn_id).or_insert(offset);
+    }
+
+    fn check_chunk_section(&mut self, bytes: &[u8], mut pos: u64, end: u64) {
+        let mut last_chunk = None;
+        while pos < end {
+            let record = match read_record(bytes, pos) {
+                Ok(record) => record,
+                Err(e) => {
+                    self.problem(
+                        pos,
+                        format!("{}, the rest of the chunk section is skipped", e),
+                    );
+                    return;
+                }
+            };
+            if pos + record.len > end {
+                self.problem(pos, "record overlaps the index section".to_string());
+            }
+            pos += record.len;
+
+            let result = match record.op {
+                OP_CHUNK => {
+                    last_chunk = Some(record.offset);
+                    self.check_chunk(&record)
+                }
+                OP_INDEX_DATA => match last_chunk {
+                    Some(chunk_pos) => self.check_index_data(&record, chunk_pos),
+                    None => {
+                        let message = "index data record before the first chunk".to_string();
+                        self.problem(record.offset, message);
+                        Ok(())
+                    }
+                },
+                OP_CONNECTION => Connection::from_record(&record).map(|conn| {
+                    self.defined.insert(conn.id);
+                }),
+                op => {
+                    let message = format!("unexpected record op 0x{:02x} in chunk section", op);
+                    self.problem(record.offset, message);
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                self.problem(record.offset, e.to_string());
+            }
+        }
+    }
+
+    fn check_chunk(&mut self, record: &Record) -> Result<(), RecordError> {
+        let compression = record.header.str("compression")?;
+        let size = record.header.u32("size")?;
+        let mut data = vec![];
+        if let Err(e) = decompress_into(compression, record.data, &mut data) {
+            self.problem(record.offset, e.to_string());
+        }
+        if data.len() != size as usize {
+            let message = format!(
+                "chunk size is {} but decompresses to {} bytes",
+                size,
+                data.len()
+            );
+            self.problem(record.offset, message);
+        }
+
+        let mut content = ChunkContent::default();
+        let mut pos = 0;
+        while pos < data.len() as u64 {
+            let message = match read_record(&data, pos) {
+                Ok(message) => message,
+                Err(e) => {
+                    let message = format!("at chunk offset {}: {}", pos, e);
+                    self.problem(record.offset, message);
+                    break;
+                }
+            };
+            pos += message.len;
+            match message.op {
+                OP_MESSAGE_DATA => {
+                    let fields = message
+                        .header
+                        .u32("conn")
+                        .and_then(|conn_id| Ok((conn_id, message.header.time("time")?)));
+                    let (conn_id, time) = match fields {
+                        Ok(fields) => fields,
+                        Err(e) => {
+                            let message = format!("at chunk offset {}: {}", message.offset, e);
+                            self.problem(record.offset, message);
+                            continue;
+                        }
+                    };
+                    self.use_conn(conn_id, record.offset);
+                    content.messages.insert(message.offset, (conn_id, time));
+                    *content.counts.entry(conn_id).or_default() += 1;
+                    content.start_time = Some(content.start_time.map_or(time, |t| t.min(time)));
+                    content.end_time = Some(content.end_time.map_or(time, |t| t.max(time)));
+                    self.report.messages += 1;
+                }
+                OP_CONNECTION => match Connection::from_record(&message) {
+                    Ok(conn) => {
+                        self.defined.insert(conn.id);
+                    }
+                    Err(e) => {
+                        let message = format!("at chunk offset {}: {}", message.offset, e);
+                        self.problem(record.offset, message);
+                    }
+                },
+                op => {
+                    let message = format!(
+                        "unexpected record op 0x{:02x} at chunk offset {}",
+                        op, message.offset
+                    );
+                    self.problem(record.offset, message);
+                }
+            }
+        }
+        self.chunks.insert(record.offset, content);
+        Ok(())
+    }
+
+    fn check_index_data(&mut self, record: &Record, chunk_pos: u64) -> Result<(), RecordError> {
+        let ver = record.header.u32("ver")?;
+        let conn_id = record.header.u32("conn")?;
+        let count = record.header.u32("count")?;
+        if ver != 1 {
+            self.problem(
+                record.offset,
+                format!("unsupported index data version {}", ver),
+            );
+            return Ok(());
+        }
+        self.use_conn(conn_id, record.offset);
+        if record.data.len() != count as usize * 12 {
+            let message = format!(
+                "index data count is {} but holds {} bytes",
+                count,
+                record.data.len()
+            );
+            self.problem(record.offset, message);
+        }
+
+        let Some(content) = self.chunks.get_mut(&chunk_pos) else {
+            let message = format!("index data of the unreadable chunk at {}", chunk_pos);
+            self.problem(record.offset, message);
+            return Ok(());
+        };
+        let mut problems = vec![];
+        let mut cursor = Cursor::new(record.data);
+        let mut entries = 0;
+        while cursor.left() >= 12 {
+            let time = cursor.next_time().map_err(|_| RecordError::OutOfBounds)?;
+            let offset = cursor.next_u32().map_err(|_| RecordError::OutOfBounds)? as u64;
+            entries += 1;
+            match content.messages.get(&offset) {
+                Some(&(message_conn, message_time)) => {
+                    if message_conn != conn_id || message_time != time {
+                        problems.push(format!(
+                            "index entry for connection {} at chunk offset {} points to a message on connection {} at time {}",
+                            conn_id, offset, message_conn, message_time
+                        ));
+                    }
+                }
+                None => problems.push(format!(
+                    "index entry for connection {} points to chunk offset {} which is not a message record",
+                    conn_id, offset
+                )),
+            }
+        }
+        *content.indexed.entry(conn_id).or_default() += entries;
+        for message in problems {
+            self.problem(record.offset, message);
+        }
+        Ok(())
+    }
+
+    fn check_index_section(
+        &mut self,
+        bytes: &[u8],
+        mut pos: u64,
+        conn_count: u32,
+        chunk_count: u32,
+    ) {
+        let mut connections = 0;
+        let mut chunk_infos = BTreeSet::new();
+        while pos < bytes.len() as u64 {
+            let record = match read_record(bytes, pos) {
+                Ok(record) => record,
+                Err(e) => {
+                    self.problem(
+                        pos,
+                        format!("{}, the rest of the index section is skipped", e),
+                    );
+                    break;
+                }
+            };
+            pos += record.len;
+            let result = match record.op {
+                OP_CONNECTION => Connection::from_record(&record).map(|conn| {
+                    connections += 1;
+                    self.defined.insert(conn.id);
+                }),
+                OP_CHUNK_INFO => self.check_chunk_info(&record).map(|chunk_pos| {
+                    chunk_infos.insert(chunk_pos);
+                }),
+                op => {
+                    let message = format!("unexpected record op 0x{:02x} in index section", op);
+                    self.problem(record.offset, message);
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                self.problem(record.offset, e.to_string());
+            }
+        }
+
+        let header_pos = BAG_MAGIC.len() as u64;
+        if connections != conn_count {
+            let message = format!(
+                "bag header counts {} connections but the index has {}",
+                conn_count, connections
+            );
+            self.problem(header_pos, message);
+        }
+        if chunk_infos.len() != chunk_count as usize {
+            let message = format!(
+                "bag header counts {} chunks but the index has {} chunk infos",
+                chunk_count,
+                chunk_infos.len()
+            );
+            self.problem(header_pos, message);
+        }
+
+        let mut problems = vec![];
+        for (chunk_pos, content) in self.chunks.iter() {
+            if !chunk_infos.contains(chunk_pos) {
+                problems.push((*chunk_pos, "chunk has no chunk info".to_string()));
+            }
+            if content.indexed != content.counts {
+                let message = format!(
+                    "index data counts {:?} messages per connection but the chunk has {:?}",
+                    content.indexed, content.counts
+                );
+                problems.push((*chunk_pos, message));
+            }
+        }
+        for (offset, message) in problems {
+            self.problem(offset, message);
+        }
+    }
+
+    /// Compare a chunk info with the chunk it points to and return the chunk position
+    fn check_chunk_info(&mut self, record: &Record) -> Result<u64, RecordError> {
+        let ver = record.header.u32("ver")?;
+        let chunk_pos = record.header.u64("chunk_pos")?;
+        let start_time = record.header.time("start_time")?;
+        let end_time = record.header.time("end_time")?;
+        let count = record.header.u32("count")?;
+        if ver != 1 {
+            self.problem(
+                record.offset,
+                format!("unsupported chunk info version {}", ver),
+            );
+            return Ok(chunk_pos);
+        }
+
+        let mut counts = BTreeMap::new();
+        let mut cursor = Cursor::new(record.data);
+        for _ in 0..count {
+            let conn_id = cursor.next_u32().map_err(|_| RecordError::OutOfBounds)?;
+            let count = cursor.next_u32().map_err(|_| RecordError::OutOfBounds)?;
+            self.use_conn(conn_id, record.offset);
+            counts.insert(conn_id, count);
+        }
+
+        let Some(content) = self.chunks.get(&chunk_pos) else {
+            let message = format!("chunk info points to {} which is not a chunk", chunk_pos);
+            self.problem(record.offset, message);
+            return Ok(chunk_pos);
+        };
+        let mut problems = vec![];
+        if counts != content.counts {
+            problems.push(format!(
+                "chunk info counts {:?} messages per connection but the chunk at {} has {:?}",
+                counts, chunk_pos, content.counts
+            ));
+        }
+        if let (Some(first), Some(last)) = (content.start_time, content.end_time) {
+            if first != start_time || last != end_time {
+                problems.push(format!(
+                    "chunk info time range {}..{} does not match the messages {}..{} of the chunk at {}",
+                    start_time, end_time, first, last, chunk_pos
+                ));
+            }
+        }
+        for message in problems {
+            self.problem(record.offset, message);
+        }
+        Ok(chunk_pos)
+    }
+}
@@ -0,0 +1,474 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+use rebag::cache::IndexCache;
+use rebag::downsample::DownsampleRule;
+use rebag::export::{ArrayMode, BytesFormat, JsonOptions, NonFiniteFormat, TimeFormat};
+use rebag::mcap::{McapCompression, McapOptions};
+use rebag::selection::TopicSelection;
+use rebag::split::{parse_size, SplitCriterion};
+use rebag::time::{parse_duration, parse_offset, parse_time};
+use rebag::trim::TimeBound;
+use rebag::writer::{Compression, WriterOptions};
+
+#[derive(Parser)]
+#[command(
+    name = "rebag",
+    about = "Inspect and rewrite ROS1 bags, reading MCAP files as well"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// List the topics of a bag with their message definitions
+    Topics {
+        /// Path to the bag or MCAP file or a directory of them, `-` streams a bag from stdin
+        bag: PathBuf,
+        #[command(flatten)]
+        topics: TopicArgs,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Show type, md5sum, publishing node and latching of every connection
+    Connections {
+        /// Path to the bag or MCAP file or a directory of them, `-` streams a bag from stdin
+        bag: PathBuf,
+        #[command(flatten)]
+        topics: TopicArgs,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Count the messages per topic
+    Count {
+        /// Path to the bag or MCAP file or a directory of them, `-` streams a bag from stdin
+        bag: PathBuf,
+        #[command(flatten)]
+        topics: TopicArgs,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Print the decoded messages of topics, or one JSON object per message with --jsonl
+    Echo {
+        /// Path to the bag or MCAP file, `-` streams a bag from stdin
+        bag: PathBuf,
+        #[command(flatten)]
+        topics: TopicArgs,
+        /// Write JSON Lines with topic, record time and message
+        #[arg(long)]
+        jsonl: bool,
+        #[command(flatten)]
+        json: JsonArgs,
+    },
+    /// Message rates per topic and the gaps between messages, from the record times
+    Rates {
+        /// Path to the bag, `-` streams a bag from stdin
+        bag: PathBuf,
+        #[command(flatten)]
+        topics: TopicArgs,
+        /// Report gaps longer than this many seconds
+        #[arg(long, value_name = "SECONDS", value_parser = parse_duration)]
+        gap: Option<u64>,
+        /// Report gaps longer than this multiple of the median period [default: 3 without --gap]
+        #[arg(long, value_name = "K")]
+        gap_factor: Option<f64>,
+        /// Print JSON instead of tables
+        #[arg(long)]
+        json: bool,
+    },
+    /// Latency between the header stamp and the record time of messages with a header
+    Latency {
+        /// Path to the bag, `-` streams a bag from stdin
+        bag: PathBuf,
+        #[command(flatten)]
+        topics: TopicArgs,
+        /// Flag messages stamped more than this many seconds before their record time
+        #[arg(long, value_name = "SECONDS", value_parser = parse_duration, default_value = "1")]
+        max_latency: u64,
+        /// Number of histogram bins per topic
+        #[arg(long, default_value_t = 10)]
+        bins: usize,
+        /// Write the latency of every message to a CSV file
+        #[arg(long, value_name = "FILE")]
+        csv: Option<PathBuf>,
+        /// Print JSON instead of tables
+        #[arg(long)]
+        json: bool,
+    },
+    /// Find bags below a directory by topic, message count or time
+    Catalog {
+        dir: PathBuf,
+        /// Only bags that contain this topic
+        #[arg(long)]
+        topic: Option<String>,
+        /// Only bags with at least this many messages, on --topic if given
+        #[arg(long)]
+        min_count: Option<u64>,
+        /// Only bags that end after this time, as seconds since the epoch or UTC date and time
+        #[arg(long, value_parser = parse_time)]
+        start: Option<u64>,
+        /// Only bags that start before this time
+        #[arg(long, value_parser = parse_time)]
+        end: Option<u64>,
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Copy the messages matching field-level expressions to a new bag
+    Filter {
+        /// Path to the bag, `-` streams a bag from stdin
+        input: PathBuf,
+        output: PathBuf,
+        /// Expressions like `/odom: twist.twist.linear.x > 5.0` or `$time < 1692370845`.
+        /// A message is kept if any expression for its topic matches.
+        #[arg(value_name = "EXPRESSIONS")]
+        expressions: Vec<String>,
+        /// Drop messages on topics that no expression applies to
+        #[arg(long)]
+        drop_other: bool,
+        /// Keep every nth message of topics, like `/velodyne_points:5`
+        #[arg(long, value_name = "TOPICS:N", value_parser = DownsampleRule::every)]
+        every: Vec<DownsampleRule>,
+        /// Keep at most this many messages per second of topics, like `/camera/**:2`
+        #[arg(long, value_name = "TOPICS:HZ", value_parser = DownsampleRule::max_rate)]
+        max_rate: Vec<DownsampleRule>,
+        #[command(flatten)]
+        writer: WriterArgs,
+    },
+    /// Rewrite the chunks of bags with bz2 or lz4 compression
+    Compress {
+        #[arg(required = true)]
+        bags: Vec<PathBuf>,
+        #[command(flatten)]
+        compression: CompressionArgs,
+        /// Write to this file instead of replacing the bag, only for a single bag
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Rewrite the chunks of bags without compression
+    Decompress {
+        #[arg(required = true)]
+        bags: Vec<PathBuf>,
+        /// Write to this file instead of replacing the bag, only for a single bag
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Convert a bag to MCAP with ros1msg schemas, one channel per connection
+    Convert {
+        /// Bag to convert, `-` reads from stdin
+        input: PathBuf,
+        /// MCAP file to write, like `out.mcap`
+        output: PathBuf,
+        #[command(flatten)]
+        mcap: McapArgs,
+    },
+    /// Export the messages of a topic for analysis outside ROS
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Merge bags into one bag ordered by record time
+    Merge {
+        /// Bags or directories whose bags are parts of one recording
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Prefix the topics of an input, given once per input in the same order
+        #[arg(long)]
+        prefix: Vec<String>,
+        #[command(flatten)]
+        writer: WriterArgs,
+    },
+    /// Copy a bag with renamed topics and header frame ids
+    Remap {
+        /// Bag to read, `-` reads from stdin
+        input: PathBuf,
+        output: PathBuf,
+        /// Topic renames like `/old:=/new` or `re:^/camera/(.*):=/cam/$1`, the first matching one applies
+        #[arg(value_name = "RULES")]
+        rules: Vec<String>,
+        /// Rename the `frame_id` of all headers, like `base_link:=base_footprint`
+        #[arg(long, value_name = "RULE")]
+        frame: Vec<String>,
+        #[command(flatten)]
+        writer: WriterArgs,
+    },
+    /// Copy a bag with topics dropped and message fields zeroed or replaced
+    Redact {
+        /// Bag to read, `-` reads from stdin
+        input: PathBuf,
+        output: PathBuf,
+        /// Rules like `drop /camera/*`, `zero /gps/fix:latitude,longitude` or `replace frame_id`
+        /// The output is removed if a rule matches no topic or field
+        #[arg(value_name = "RULES")]
+        rules: Vec<String>,
+        /// Read more rules from a file, one per line
+        #[arg(long, value_name = "FILE")]
+        rules_file: Option<PathBuf>,
+        #[command(flatten)]
+        writer: WriterArgs,
+    },
+    /// Copy a bag with shifted record times, optionally shifting header stamps or using them as record times
+    Restamp {
+        /// Bag to read, `-` reads from stdin
+        input: PathBuf,
+        output: PathBuf,
+        /// Add this to all record times, like `-3.5`, `+250ms` or `2h`
+        #[arg(long, allow_hyphen_values = true, value_parser = parse_offset, conflicts_with = "start")]
+        offset: Option<i64>,
+        /// Shift the record times so the bag starts at this time
+        #[arg(long, value_parser = parse_time)]
+        start: Option<u64>,
+        /// Shift the stamps of all headers inside messages by the same offset
+        #[arg(long)]
+        shift_headers: bool,
+        /// Use the header stamp as record time of messages that start with a header
+        #[arg(long)]
+        header_time: bool,
+        #[command(flatten)]
+        writer: WriterArgs,
+    },
+    /// Copy the messages of a time window, keeping connections and latched messages from before it
+    Trim {
+        input: PathBuf,
+        output: PathBuf,
+        /// Start of the window, `+12.5s` after the bag start, `-30s` before its end or an absolute time
+        #[arg(long, allow_hyphen_values = true, value_parser = TimeBound::parse)]
+        start: Option<TimeBound>,
+        /// End of the window, included, in the same forms as the start
+        #[arg(long, allow_hyphen_values = true, value_parser = TimeBound::parse)]
+        end: Option<TimeBound>,
+        #[command(flatten)]
+        writer: WriterArgs,
+    },
+    /// Split a bag into parts by duration, size, message count or at a trigger topic
+    Split {
+        /// Bag to split, `-` reads from stdin
+        input: PathBuf,
+        /// Parts are named like this path with the part number appended, defaults to the input
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        split: SplitArgs,
+        #[command(flatten)]
+        writer: WriterArgs,
+    },
+    /// Verify the structure and index of bags, fails if any problem is found
+    Check {
+        /// Bags or directories to search for bags recursively
+        #[arg(required = true)]
+        bags: Vec<PathBuf>,
+    },
+    /// Recover the readable records of a truncated or corrupt bag
+    Salvage {
+        bag: PathBuf,
+        /// Write the recovered messages to a new bag
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ExportFormat {
+    /// CSV with the record time and a column per field, nested fields named like `pose.position.x`
+    Csv {
+        #[command(flatten)]
+        export: ExportArgs,
+        /// Write variable length arrays as JSON into one cell (serialize) or a row per element (explode)
+        #[arg(long, default_value = "serialize")]
+        arrays: ArrayMode,
+    },
+    /// JSON Lines with topic, record time and message per line
+    Jsonl {
+        #[command(flatten)]
+        export: ExportArgs,
+        #[command(flatten)]
+        json: JsonArgs,
+    },
+    /// NumPy npz with the record time and an array per field, or npy with a single field
+    Numpy {
+        #[command(flatten)]
+        export: ExportArgs,
+        /// Numeric fields like `linear_acceleration.x,y,z`, fixed size arrays give 2-D arrays
+        #[arg(long, required = true, value_name = "FIELDS")]
+        fields: Vec<String>,
+    },
+    /// Parquet with the record time as timestamp, nested messages as structs and arrays as lists
+    Parquet {
+        #[command(flatten)]
+        export: ExportArgs,
+    },
+    /// Arrow IPC file with the same schema as parquet
+    Arrow {
+        #[command(flatten)]
+        export: ExportArgs,
+    },
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Bag or MCAP file to read, `-` streams a bag from stdin
+    pub input: PathBuf,
+    /// Topic to export. Except for jsonl a pattern has to match a single topic
+    /// and all its messages must have the same type.
+    pub topic: String,
+    /// File to write, stdout if not given
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct JsonArgs {
+    /// Write byte arrays like image data as base64, hex or array of numbers
+    #[arg(long, default_value = "base64")]
+    pub bytes: BytesFormat,
+    /// Write NaN and infinite floats as null or as strings like "NaN"
+    #[arg(long, value_name = "FORMAT", default_value = "null")]
+    pub non_finite: NonFiniteFormat,
+    /// Write times and durations as nanos, seconds or iso, durations are seconds with iso
+    #[arg(long, default_value = "nanos")]
+    pub time: TimeFormat,
+}
+
+impl JsonArgs {
+    pub fn options(&self) -> JsonOptions {
+        JsonOptions {
+            bytes: self.bytes,
+            non_finite: self.non_finite,
+            time: self.time,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct CacheArgs {
+    /// Cache bag indexes next to the bags and reuse them on later runs
+    #[arg(long)]
+    pub cache: bool,
+    /// Cache bag indexes in this directory instead of next to the bags
+    #[arg(long, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl CacheArgs {
+    pub fn index_cache(&self) -> IndexCache {
+        match (&self.cache_dir, self.cache) {
+            (Some(dir), _) => IndexCache::Directory(dir.clone()),
+            (None, true) => IndexCache::Sidecar,
+            (None, false) => IndexCache::Disabled,
+        }
+    }
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+pub struct CompressionArgs {
+    /// Compress with bz2, the default
+    #[arg(long)]
+    pub bz2: bool,
+    /// Compress with lz4
+    #[arg(long)]
+    pub lz4: bool,
+}
+
+impl CompressionArgs {
+    pub fn compression(&self) -> Compression {
+        if self.lz4 {
+            Compression::Lz4
+        } else {
+            Compression::Bz2
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct WriterArgs {
+    /// Compression of the written chunks: none, bz2 or lz4
+    #[arg(long, default_value = "none")]
+    pub compression: Compression,
+    /// Uncompressed size of the written chunks in KiB
+    #[arg(long, value_name = "KIB", default_value_t = 768)]
+    pub chunk_size: usize,
+}
+
+impl WriterArgs {
+    pub fn options(&self) -> WriterOptions {
+        WriterOptions {
+            compression: self.compression,
+            chunk_size: self.chunk_size * 1024,
+        }
+    }
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+pub struct SplitArgs {
+    /// Record time span of a part, like `60s` or `10min`
+    #[arg(long, value_parser = parse_duration)]
+    pub duration: Option<u64>,
+    /// Size of a part, like `500MB` or `1GiB`
+    #[arg(long, value_parser = parse_size)]
+    pub size: Option<u64>,
+    /// Number of messages per part
+    #[arg(long)]
+    pub count: Option<u64>,
+    /// Start a new part at every message on this topic
+    #[arg(long, value_name = "TOPIC")]
+    pub on_topic: Option<String>,
+}
+
+impl SplitArgs {
+    pub fn criterion(&self) -> SplitCriterion {
+        match (self.duration, self.size, self.count, &self.on_topic) {
+            (Some(duration), _, _, _) => SplitCriterion::Duration(duration),
+            (_, Some(size), _, _) => SplitCriterion::Size(size),
+            (_, _, Some(count), _) => SplitCriterion::Count(count),
+            (_, _, _, Some(topic)) => SplitCriterion::OnTopic(topic.clone()),
+            _ => unreachable!("clap requires one split criterion"),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct McapArgs {
+    /// Compression of the MCAP chunks: none, lz4 or zstd
+    #[arg(
+        long = "mcap-compression",
+        value_name = "COMPRESSION",
+        default_value = "zstd"
+    )]
+    pub compression: McapCompression,
+    /// Uncompressed size of the MCAP chunks in KiB
+    #[arg(long = "mcap-chunk-size", value_name = "KIB", default_value_t = 768)]
+    pub chunk_size: usize,
+}
+
+impl McapArgs {
+    pub fn options(&self) -> McapOptions {
+        McapOptions {
+            compression: self.compression,
+            chunk_size: self.chunk_size * 1024,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct TopicArgs {
+    /// Topics, globs like `/perception/**`, regexes like `re:^/camera` or exclusions like `!/tf`.
+    /// All topics if none are given.
+    #[arg(value_name = "TOPICS")]
+    pub topics: Vec<String>,
+}
+
+impl TopicArgs {
+    pub fn selection(&self) -> Result<TopicSelection> {
+        TopicSelection::parse(&self.topics)
+    }
+}
@@ -0,0 +1,362 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use arrow::array::{
+    ArrayRef, BinaryArray, BooleanArray, DurationNanosecondArray, FixedSizeListArray, Float32Array,
+    Float64Array, Int16Array, Int32Array, Int64Array, Int8Array, ListArray, RecordBatch,
+    StringArray, StructArray, TimestampNanosecondArray, UInt16Array, UInt32Array, UInt64Array,
+    UInt8Array,
+};
+use arrow::buffer::{NullBuffer, OffsetBuffer};
+use arrow::datatypes::{DataType, Field as ArrowField, Fields, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::writer::FileWriter;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+use crate::export::{check_connection, ExportReport};
+use crate::indexing::MessageSource;
+use crate::message_decoder::{MessageDecoder, Value, ROS2_DURATION_TYPE, ROS2_TIME_TYPE};
+use crate::message_parser::{Field, Repeated};
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+
+/// A batch is written as a row group once it has this many messages
+const BATCH_ROWS: usize = 64 * 1024;
+/// Bounds the memory for large messages like images, counted in serialized bytes
+const BATCH_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnarFormat {
+    Parquet,
+    /// The Arrow IPC file format, also known as Feather v2
+    ArrowIpc,
+}
+
+/// Arrow schema of an exported topic, the record time followed by the message fields.
+/// Nested messages become structs, arrays become lists and byte arrays binary columns.
+pub fn message_schema(decoder: &MessageDecoder) -> Result<Schema> {
+    if decoder
+        .fields()
+        .iter()
+        .any(|field| field.field_name == "time")
+    {
+        bail!("The field time collides with the record time column");
+    }
+    let time = ArrowField::new("time", timestamp_type(), false);
+    Ok(Schema::new(
+        [Arc::new(time)]
+            .into_iter()
+            .chain(message_fields(decoder, decoder.fields()).iter().cloned())
+            .collect::<Fields>(),
+    ))
+}
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()))
+}
+
+fn message_fields(decoder: &MessageDecoder, fields: &[Field]) -> Fields {
+    fields
+        .iter()
+        .map(|field| ArrowField::new(&field.field_name, field_type(decoder, field), true))
+        .collect()
+}
+
+fn field_type(decoder: &MessageDecoder, field: &Field) -> DataType {
+    let bytes = matches!(field.field_type.as_str(), "uint8" | "byte" | "char");
+    let item = || {
+        Arc::new(ArrowField::new_list_field(
+            value_type(decoder, &field.field_type),
+            true,
+        ))
+    };
+    match field.field_repeat {
+        Repeated::None => value_type(decoder, &field.field_type),
+        _ if bytes => DataType::Binary,
+        // `[]` is parsed as fixed length 0
+        Repeated::Fixed(len) if len > 0 => DataType::FixedSizeList(item(), len as i32),
+        _ => DataType::List(item()),
+    }
+}
+
+fn value_type(decoder: &MessageDecoder, field_type: &str) -> DataType {
+    if let Some(fields) = decoder.type_fields(field_type) {
+        return DataType::Struct(message_fields(decoder, fields));
+    }
+    match field_type {
+        "bool" => DataType::Boolean,
+        "int8" | "byte" => DataType::Int8,
+        "uint8" | "char" => DataType::UInt8,
+        "int16" => DataType::Int16,
+        "uint16" => DataType::UInt16,
+        "int32" => DataType::Int32,
+        "uint32" => DataType::UInt32,
+        "int64" => DataType::Int64,
+        "uint64" => DataType::UInt64,
+        "float32" => DataType::Float32,
+        "float64" => DataType::Float64,
+        "time" | ROS2_TIME_TYPE => timestamp_type(),
+        "duration" | ROS2_DURATION_TYPE => DataType::Duration(TimeUnit::Nanosecond),
+        // The decoder resolves all types, everything else is a string
+        _ => DataType::Utf8,
+    }
+}
+
+fn int(value: &Value) -> Option<i64> {
+    match value {
+        Value::Int(value) => Some(*value),
+        Value::UInt(value) => Some(*value as i64),
+        _ => None,
+    }
+}
+
+fn uint(value: &Value) -> Option<u64> {
+    match value {
+        Value::Int(value) => Some(*value as u64),
+        Value::UInt(value) => Some(*value),
+        _ => None,
+    }
+}
+
+macro_rules! primitive_array {
+    ($array:ty, $values:expr, $convert:expr) => {
+        Arc::new(<$array>::from_iter(
+            $values.iter().map(|value| value.and_then($convert)),
+        )) as ArrayRef
+    };
+}
+
+fn nulls(values: &[Option<&Value>]) -> Option<NullBuffer> {
+    if values.iter().all(Option::is_some) {
+        return None;
+    }
+    Some(
+        values
+            .iter()
+            .map(Option::is_some)
+            .collect::<Vec<_>>()
+            .into(),
+    )
+}
+
+/// Column of `data_type` from decoded values, None where a message or array is missing
+fn build_array(data_type: &DataType, values: &[Option<&Value>]) -> Result<ArrayRef> {
+    Ok(match data_type {
+        DataType::Boolean => primitive_array!(BooleanArray, values, |value| match value {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }),
+        DataType::Int8 => primitive_array!(Int8Array, values, |value| int(value).map(|v| v as i8)),
+        DataType::UInt8 => {
+            primitive_array!(UInt8Array, values, |value| uint(value).map(|v| v as u8))
+        }
+        DataType::Int16 => {
+            primitive_array!(Int16Array, values, |value| int(value).map(|v| v as i16))
+        }
+        DataType::UInt16 => {
+            primitive_array!(UInt16Array, values, |value| uint(value).map(|v| v as u16))
+        }
+        DataType::Int32 => {
+            primitive_array!(Int32Array, values, |value| int(value).map(|v| v as i32))
+        }
+        DataType::UInt32 => {
+            primitive_array!(UInt32Array, values, |value| uint(value).map(|v| v as u32))
+        }
+        DataType::Int64 => primitive_array!(Int64Array, values, int),
+        DataType::UInt64 => primitive_array!(UInt64Array, values, uint),
+        DataType::Float32 => primitive_array!(Float32Array, values, |value| value
+            .as_f64()
+            .map(|v| v as f32)),
+        DataType::Float64 => primitive_array!(Float64Array, values, Value::as_f64),
+        DataType::Utf8 => Arc::new(StringArray::from_iter(values.iter().map(
+            |value| match value {
+                Some(Value::String(value)) => Some(value.as_str()),
+                _ => None,
+            },
+        ))),
+        DataType::Binary => Arc::new(BinaryArray::from_iter(values.iter().map(
+            |value| match value {
+                Some(Value::Bytes(bytes)) => Some(bytes.as_slice()),
+                _ => None,
+            },
+        ))),
+        DataType::Timestamp(_, timezone) => Arc::new(
+            TimestampNanosecondArray::from_iter(values.iter().map(|value| match value {
+                Some(Value::Time(time)) => Some(*time as i64),
+                _ => None,
+            }))
+            .with_timezone_opt(timezone.clone()),
+        ),
+        DataType::Duration(_) => primitive_array!(DurationNanosecondArray, values, |value| {
+            match value {
+                Value::Duration(duration) => Some(*duration),
+                _ => None,
+            }
+        }),
+        DataType::Struct(fields) => {
+            if fields.is_empty() {
+                return Ok(Arc::new(StructArray::new_empty_fields(
+                    values.len(),
+                    nulls(values),
+                )));
+            }
+            let children = fields
+                .iter()
+                .map(|field| {
+                    let field_values: Vec<Option<&Value>> = values
+                        .iter()
+                        .map(|value| value.and_then(|value| value.get(field.name())))
+                        .collect();
+                    build_array(field.data_type(), &field_values)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Arc::new(StructArray::try_new(
+                fields.clone(),
+                children,
+                nulls(values),
+            )?)
+        }
+        DataType::List(item) => {
+            let arrays: Vec<&[Value]> = values.iter().map(|value| elements(*value)).collect();
+            let items: Vec<Option<&Value>> = arrays.iter().copied().flatten().map(Some).collect();
+            Arc::new(ListArray::try_new(
+                item.clone(),
+                OffsetBuffer::from_lengths(arrays.iter().map(|array| array.len())),
+                build_array(item.data_type(), &items)?,
+                nulls(values),
+            )?)
+        }
+        DataType::FixedSizeList(item, len) => {
+            let len = *len as usize;
+            // Missing arrays still take their slots
+            let items: Vec<Option<&Value>> = values
+                .iter()
+                .flat_map(|value| {
+                    let array = elements(*value);
+                    (0..len).map(move |i| array.get(i))
+                })
+                .collect();
+            Arc::new(FixedSizeListArray::try_new(
+                item.clone(),
+                len as i32,
+                build_array(item.data_type(), &items)?,
+                nulls(values),
+            )?)
+        }
+        data_type => bail!("Cannot export {} columns", data_type),
+    })
+}
+
+fn elements(value: Option<&Value>) -> &[Value] {
+    match value {
+        Some(Value::Array(values)) => values,
+        _ => &[],
+    }
+}
+
+fn record_batch(schema: &SchemaRef, messages: &[(u64, Value)]) -> Result<RecordBatch> {
+    let time =
+        TimestampNanosecondArray::from_iter_values(messages.iter().map(|(time, _)| *time as i64))
+            .with_timezone("UTC");
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(time)];
+    for field in schema.fields().iter().skip(1) {
+        let values: Vec<Option<&Value>> = messages
+            .iter()
+            .map(|(_, message)| message.get(field.name()))
+            .collect();
+        columns.push(build_array(field.data_type(), &values)?);
+    }
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+enum BatchWriter<W: Write + Send> {
+    Parquet(ArrowWriter<W>),
+    ArrowIpc(FileWriter<W>),
+}
+
+impl<W: Write + Send> BatchWriter<W> {
+    fn new(format: ColumnarFormat, writer: W, schema: SchemaRef) -> Result<Self> {
+        Ok(match format {
+            ColumnarFormat::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                    .build();
+                BatchWriter::Parquet(ArrowWriter::try_new(writer, schema, Some(properties))?)
+            }
+            ColumnarFormat::ArrowIpc => {
+                BatchWriter::ArrowIpc(FileWriter::try_new(writer, &schema)?)
+            }
+        })
+    }
+
+    /// Parquet gets a row group per batch
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            BatchWriter::Parquet(writer) => {
+                writer.write(batch)?;
+                writer.flush()?;
+            }
+            BatchWriter::ArrowIpc(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<W> {
+        Ok(match self {
+            BatchWriter::Parquet(writer) => writer.into_inner()?,
+            BatchWriter::ArrowIpc(mut writer) => {
+                writer.finish()?;
+                writer.into_inner()?
+            }
+        })
+    }
+}
+
+/// Write the messages of `topic` as Parquet or Arrow IPC with the schema of `message_schema`.
+/// Messages are written in batches so only one batch is held in memory.
+pub fn export_columnar<W: Write + Send>(
+    source: MessageSource,
+    topic: &str,
+    format: ColumnarFormat,
+    writer: W,
+) -> Result<ExportReport> {
+    let mut report = ExportReport::default();
+    let mut writer = Some(writer);
+    let mut batch_writer: Option<(Connection, MessageDecoder, SchemaRef, BatchWriter<W>)> = None;
+    let mut batch = vec![];
+    let mut batch_bytes = 0;
+
+    let topics = TopicSelection::parse(&[topic])?;
+    source.read_messages(&topics, |connection, time, data| {
+        if batch_writer.is_none() {
+            let decoder = MessageDecoder::for_connection(connection)?;
+            let schema = Arc::new(message_schema(&decoder)?);
+            let new = BatchWriter::new(format, writer.take().unwrap(), schema.clone())?;
+            batch_writer = Some((connection.clone(), decoder, schema, new));
+        }
+        let (first, decoder, schema, batch_writer) = batch_writer.as_mut().unwrap();
+        check_connection(first, connection)?;
+
+        batch.push((time, decoder.decode(data)?));
+        batch_bytes += data.len();
+        if batch.len() >= BATCH_ROWS || batch_bytes >= BATCH_BYTES {
+            batch_writer.write(&record_batch(schema, &batch)?)?;
+            batch.clear();
+            batch_bytes = 0;
+        }
+        report.messages += 1;
+        report.rows += 1;
+        Ok(())
+    })?;
+
+    let Some((_, _, schema, mut batch_writer)) = batch_writer else {
+        bail!("No messages on {}", topic);
+    };
+    if !batch.is_empty() {
+        batch_writer.write(&record_batch(&schema, &batch)?)?;
+    }
+    batch_writer.finish()?.flush()?;
+    Ok(report)
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::bag::{Bag, ChunkRecord, IndexRecord};
+use crate::record::BAG_MAGIC;
+use crate::writer::{
+    write_bag_header, write_chunk, write_chunk_info, write_connection, write_index_data,
+    Compression, WrittenChunk, BAG_HEADER_LEN,
+};
+
+/// Rewrite the chunks of `bag` with `compression` into `writer`.
+/// Chunk boundaries, connections, message times and index data stay the same,
+/// only the chunk positions in the chunk infos change.
+pub fn recompress<W: Write + Seek>(
+    bag: &Bag,
+    mut writer: W,
+    compression: Compression,
+) -> Result<W> {
+    if bag.mcap().is_some() {
+        bail!("Can only recompress ROS1 bags, not MCAP files");
+    }
+    writer.write_all(BAG_MAGIC)?;
+    write_bag_header(&mut writer, 0, 0, 0)?;
+    let mut pos = (BAG_MAGIC.len() + BAG_HEADER_LEN) as u64;
+
+    // Old chunk position to new chunk position
+    let mut chunk_pos = BTreeMap::new();
+    for record in bag.chunk_records() {
+        match record? {
+            ChunkRecord::Chunk(chunk) => {
+                chunk_pos.insert(chunk.offset, pos);
+                pos += write_chunk(&mut writer, compression, chunk.decompress()?.bytes())?;
+            }
+            ChunkRecord::IndexData(index_data) => {
+                let entries: Vec<_> = index_data
+                    .entries()
+                    .map(|entry| (entry.time, entry.offset))
+                    .collect();
+                pos += write_index_data(&mut writer, index_data.conn_id, &entries)?;
+            }
+        }
+    }
+
+    let index_pos = pos;
+    let mut conn_count = 0;
+    let mut chunk_count = 0;
+    for record in bag.index_records() {
+        match record? {
+            IndexRecord::Connection(connection) => {
+                pos += write_connection(&mut writer, &connection)?;
+                conn_count += 1;
+            }
+            IndexRecord::ChunkInfo(chunk_info) => {
+                let Some(new_pos) = chunk_pos.get(&chunk_info.chunk_pos) else {
+                    bail!(
+                        "Chunk info points to {} which is not a chunk",
+                        chunk_info.chunk_pos
+                    );
+                };
+                let chunk = WrittenChunk {
+                    pos: *new_pos,
+                    start_time: chunk_info.start_time,
+                    end_time: chunk_info.end_time,
+                    counts: chunk_info
+                        .entries()
+                        .map(|entry| (entry.conn_id, entry.count))
+                        .collect(),
+                };
+                pos += write_chunk_info(&mut writer, &chunk)?;
+                chunk_count += 1;
+            }
+        }
+    }
+
+    writer.seek(SeekFrom::Start(BAG_MAGIC.len() as u64))?;
+    write_bag_header(&mut writer, index_pos, conn_count, chunk_count)?;
+    writer.seek(SeekFrom::Start(pos))?;
+    writer.flush()?;
+    Ok(writer)
+}
+
+/// Recompress `input` into `output`, or replace `input` if no output is given
+pub fn recompress_bag(input: &Path, output: Option<&Path>, compression: Compression) -> Result<()> {
+    if let Some(output) = output {
+        let bag = Bag::open(input)?;
+        recompress(&bag, BufWriter::new(File::create(output)?), compression)?;
+        return Ok(());
+    }
+
+    // Written next to the input so the rename does not cross file systems
+    let mut file_name = input.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    let tmp = input.with_file_name(file_name);
+    let result = Bag::open(input).and_then(|bag| {
+        let file = File::create(&tmp)?;
+        recompress(&bag, BufWriter::new(file), compression)?;
+        // The input stays mapped until the bag is dropped here, before it is replaced
+        Ok(())
+    });
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, input)?;
+    Ok(())
+}
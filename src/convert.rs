@@ -0,0 +1,74 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::indexing::MessageSource;
+use crate::mcap::McapWriter;
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+
+#[derive(Debug, Default)]
+pub struct ConvertReport {
+    pub schemas: usize,
+    pub channels: usize,
+    pub messages: u64,
+}
+
+/// MCAP channel metadata of the `ros1` profile for a bag connection
+fn channel_metadata(connection: &Connection) -> BTreeMap<String, String> {
+    let mut metadata = BTreeMap::new();
+    if let Some(caller_id) = &connection.caller_id {
+        metadata.insert("callerid".to_string(), caller_id.clone());
+    }
+    let latching = if connection.latching { "1" } else { "0" };
+    metadata.insert("latching".to_string(), latching.to_string());
+    // Not part of the profile, kept so the bag connection can be restored
+    metadata.insert("md5sum".to_string(), connection.md5sum.clone());
+    metadata
+}
+
+/// Write the messages of a bag to MCAP with a `ros1msg` schema per message type
+/// and a channel per connection. Bags have no publish time, it is the record time as well.
+pub fn bag_to_mcap<W: Write>(
+    source: MessageSource,
+    writer: &mut McapWriter<W>,
+) -> Result<ConvertReport> {
+    let mut schema_ids = BTreeMap::new();
+    // Channel id and sequence number of the last message per connection
+    let mut channels = BTreeMap::<u32, (u16, u32)>::new();
+    let mut messages = 0;
+    source.read_messages(&TopicSelection::all(), |connection, time, data| {
+        let (channel_id, sequence) = match channels.entry(connection.id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let schema_id = *schema_ids
+                    .entry((connection.tp.clone(), connection.message_definition.clone()))
+                    .or_insert_with(|| {
+                        writer.add_schema(
+                            &connection.tp,
+                            "ros1msg",
+                            connection.message_definition.as_bytes(),
+                        )
+                    });
+                let channel_id = writer.add_channel(
+                    schema_id,
+                    &connection.topic,
+                    "ros1",
+                    channel_metadata(connection),
+                );
+                entry.insert((channel_id, 0))
+            }
+        };
+        *sequence += 1;
+        writer.write_message(*channel_id, *sequence, time, time, data)?;
+        messages += 1;
+        Ok(())
+    })?;
+    Ok(ConvertReport {
+        schemas: schema_ids.len(),
+        channels: channels.len(),
+        messages,
+    })
+}
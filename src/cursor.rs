@@ -5,7 +5,7 @@ use byteorder::{ByteOrder, LE};
 /// A chunk of data consists of two parts: <data_len><data_value>
 /// This can be applied in multiple places: header, data or field
 /// This chunk is not related to the Chunk record in a bag
-struct Cursor<'a> {
+pub struct Cursor<'a> {
     data: &'a [u8],
     pos: u64,
 }
@@ -34,6 +34,10 @@ impl<'a> Cursor<'a> {
         self.data.len() as u64
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     pub fn left(&self) -> u64 {
         self.data.len() as u64 - self.pos()
     }
@@ -59,11 +63,13 @@ impl<'a> Cursor<'a> {
         Ok(LE::read_u32(self.next_bytes(4)?))
     }
 
-    /*
+    pub fn next_u16(&mut self) -> Result<u16, OutOfBounds> {
+        Ok(LE::read_u16(self.next_bytes(2)?))
+    }
+
     pub fn next_u64(&mut self) -> Result<u64, OutOfBounds> {
-        Ok(LE::read_u64(self.next_bytes(4)?))
+        Ok(LE::read_u64(self.next_bytes(8)?))
     }
-    */
 
     pub fn next_time(&mut self) -> Result<u64, OutOfBounds> {
         let s = self.next_u32()? as u64;
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+
+use crate::bag::{Bag, BagMessages, Message};
+use crate::indexing::get_connections;
+use crate::record::{Connection, RecordError};
+use crate::selection::TopicSelection;
+use crate::time::NANOS_PER_SEC;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decimation {
+    /// Keep the first and then every nth message
+    Every(u64),
+    /// Keep at most this many messages per second
+    MaxRate(f64),
+}
+
+/// Reduces the messages of the topics matching a selector, like `/camera/**:2`
+#[derive(Debug, Clone)]
+pub struct DownsampleRule {
+    pub topics: TopicSelection,
+    pub decimation: Decimation,
+}
+
+impl DownsampleRule {
+    /// Parse `TOPICS:N` to keep every nth message
+    pub fn every(spec: &str) -> Result<Self> {
+        let (topics, n) = split_spec(spec)?;
+        let n = n
+            .parse::<u64>()
+            .ok()
+            .filter(|&n| n > 0)
+            .with_context(|| format!("Invalid count '{}', expected a positive integer", n))?;
+        Ok(Self {
+            topics,
+            decimation: Decimation::Every(n),
+        })
+    }
+
+    /// Parse `TOPICS:HZ` to keep at most that many messages per second
+    pub fn max_rate(spec: &str) -> Result<Self> {
+        let (topics, rate) = split_spec(spec)?;
+        let rate = rate
+            .parse::<f64>()
+            .ok()
+            .filter(|&rate| rate > 0.0 && rate.is_finite())
+            .with_context(|| format!("Invalid rate '{}', expected a positive number", rate))?;
+        Ok(Self {
+            topics,
+            decimation: Decimation::MaxRate(rate),
+        })
+    }
+}
+
+fn split_spec(spec: &str) -> Result<(TopicSelection, &str)> {
+    // Topics contain no colons, but regexes might
+    let Some((topics, value)) = spec.rsplit_once(':') else {
+        bail!("Invalid downsampling '{}', expected TOPICS:VALUE", spec);
+    };
+    Ok((TopicSelection::parse(&[topics])?, value))
+}
+
+#[derive(Debug, Default)]
+struct TopicState {
+    // Index of the applying rule, None if the topic is not downsampled
+    rule: Option<usize>,
+    seen: u64,
+    /// Earliest time of the next kept message for `MaxRate`
+    next_time: Option<u64>,
+}
+
+/// Decides per topic which messages to keep, topics without a rule keep all messages
+#[derive(Debug, Default)]
+pub struct Downsampler {
+    /// The first matching rule applies to a topic
+    rules: Vec<DownsampleRule>,
+    topics: BTreeMap<String, TopicState>,
+}
+
+impl Downsampler {
+    pub fn new(rules: Vec<DownsampleRule>) -> Self {
+        Self {
+            rules,
+            topics: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Messages have to be passed in time order for `MaxRate`
+    pub fn keep(&mut self, topic: &str, time: u64) -> bool {
+        if !self.topics.contains_key(topic) {
+            let state = TopicState {
+                rule: self
+                    .rules
+                    .iter()
+                    .position(|rule| rule.topics.matches(topic)),
+                ..Default::default()
+            };
+            self.topics.insert(topic.to_string(), state);
+        }
+        let state = self.topics.get_mut(topic).unwrap();
+        let Some(rule) = state.rule else {
+            return true;
+        };
+
+        let seen = state.seen;
+        state.seen += 1;
+        match self.rules[rule].decimation {
+            Decimation::Every(n) => seen.is_multiple_of(n),
+            Decimation::MaxRate(rate) => {
+                let period = ((NANOS_PER_SEC as f64 / rate) as u64).max(1);
+                match state.next_time {
+                    Some(next_time) if time < next_time => false,
+                    next_time => {
+                        // Stay on the grid of the first kept message so the rate does not drift
+                        let next_time = next_time.unwrap_or(time);
+                        state.next_time = Some(time + period - (time - next_time) % period);
+                        true
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Iterator adapter that drops the messages a `Downsampler` does not keep
+pub struct Downsampled<I> {
+    messages: I,
+    topics: BTreeMap<u32, String>,
+    downsampler: Downsampler,
+}
+
+impl<I> Downsampled<I> {
+    /// `connections` resolve the topics of the messages, messages on other connections are kept
+    pub fn new(messages: I, connections: &[Connection], downsampler: Downsampler) -> Self {
+        Self {
+            messages,
+            topics: connections
+                .iter()
+                .map(|connection| (connection.id, connection.topic.clone()))
+                .collect(),
+            downsampler,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<Message, RecordError>>> Iterator for Downsampled<I> {
+    type Item = Result<Message, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let message = match self.messages.next()? {
+                Ok(message) => message,
+                Err(e) => return Some(Err(e)),
+            };
+            let keep = match self.topics.get(&message.conn_id) {
+                Some(topic) => self.downsampler.keep(topic, message.time),
+                None => true,
+            };
+            if keep {
+                return Some(Ok(message));
+            }
+        }
+    }
+}
+
+/// The messages of a bag in file order, reduced by `downsampler`
+pub fn downsample(bag: &Bag, downsampler: Downsampler) -> Result<Downsampled<BagMessages<'_>>> {
+    let connections = get_connections(bag, &TopicSelection::all())?;
+    Ok(Downsampled::new(bag.messages(), &connections, downsampler))
+}
@@ -0,0 +1,579 @@
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use crate::indexing::MessageSource;
+use crate::message_decoder::{MessageDecoder, Value};
+use crate::message_parser::{Field, Repeated};
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+use crate::time::{format_time_nanos, NANOS_PER_SEC};
+
+/// How variable length arrays are flattened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArrayMode {
+    /// The whole array as JSON in one cell
+    #[default]
+    Serialize,
+    /// A row per element with the other fields repeated, shorter arrays leave their cells empty
+    Explode,
+}
+
+impl fmt::Display for ArrayMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArrayMode::Serialize => write!(f, "serialize"),
+            ArrayMode::Explode => write!(f, "explode"),
+        }
+    }
+}
+
+impl FromStr for ArrayMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "serialize" => ArrayMode::Serialize,
+            "explode" => ArrayMode::Explode,
+            _ => bail!("Unknown array mode '{}', expected serialize or explode", s),
+        })
+    }
+}
+
+/// Flattens decoded messages of one type into cells under dotted column names like
+/// `pose.pose.position.x`. Fixed length arrays get a column per element like `covariance.0`.
+pub struct Flattener {
+    decoder: MessageDecoder,
+    arrays: ArrayMode,
+    columns: Vec<String>,
+}
+
+impl Flattener {
+    pub fn new(decoder: MessageDecoder, arrays: ArrayMode) -> Self {
+        let mut flattener = Self {
+            decoder,
+            arrays,
+            columns: vec![],
+        };
+        let mut columns = vec![];
+        flattener.field_columns(flattener.decoder.fields(), "", true, &mut columns);
+        flattener.columns = columns;
+        flattener
+    }
+
+    pub fn decoder(&self) -> &MessageDecoder {
+        &self.decoder
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Cells of a message in column order, more than one row if its arrays are exploded
+    pub fn rows(&self, message: &Value) -> Vec<Vec<String>> {
+        let mut rows = vec![];
+        let mut index = 0;
+        loop {
+            let mut row = Vec::with_capacity(self.columns.len());
+            let len = self.field_cells(self.decoder.fields(), Some(message), Some(index), &mut row);
+            rows.push(row);
+            index += 1;
+            if index >= len {
+                return rows;
+            }
+        }
+    }
+
+    /// Byte arrays like image data are always serialized, exploding them would give a row per byte
+    fn explodes(&self, field: &Field) -> bool {
+        self.arrays == ArrayMode::Explode
+            && !matches!(field.field_type.as_str(), "uint8" | "byte" | "char")
+    }
+
+    /// `explode` is false within the elements of an exploded array, their arrays are serialized
+    fn field_columns(
+        &self,
+        fields: &[Field],
+        prefix: &str,
+        explode: bool,
+        columns: &mut Vec<String>,
+    ) {
+        for field in fields {
+            let name = format!("{}{}", prefix, field.field_name);
+            match field.field_repeat {
+                Repeated::None => self.value_columns(&field.field_type, &name, explode, columns),
+                Repeated::Fixed(len) if len > 0 => {
+                    for i in 0..len {
+                        let name = format!("{}.{}", name, i);
+                        self.value_columns(&field.field_type, &name, explode, columns);
+                    }
+                }
+                // `[]` is parsed as fixed length 0
+                _ if explode && self.explodes(field) => {
+                    self.value_columns(&field.field_type, &name, false, columns)
+                }
+                _ => columns.push(name),
+            }
+        }
+    }
+
+    fn value_columns(
+        &self,
+        field_type: &str,
+        name: &str,
+        explode: bool,
+        columns: &mut Vec<String>,
+    ) {
+        match self.decoder.type_fields(field_type) {
+            Some(fields) => self.field_columns(fields, &format!("{}.", name), explode, columns),
+            None => columns.push(name.to_string()),
+        }
+    }
+
+    /// Push the cells of `fields` for the element `index` of exploded arrays, empty cells if
+    /// `message` is missing. Returns the number of rows the exploded arrays need.
+    fn field_cells(
+        &self,
+        fields: &[Field],
+        message: Option<&Value>,
+        index: Option<usize>,
+        cells: &mut Vec<String>,
+    ) -> usize {
+        let mut rows = 1;
+        for field in fields {
+            let value = message.and_then(|message| message.get(&field.field_name));
+            match field.field_repeat {
+                Repeated::None => {
+                    rows = rows.max(self.value_cells(&field.field_type, value, index, cells))
+                }
+                Repeated::Fixed(len) if len > 0 => {
+                    for i in 0..len as usize {
+                        let element = value.and_then(|value| element(value, i));
+                        rows = rows.max(self.value_cells(
+                            &field.field_type,
+                            element.as_deref(),
+                            index,
+                            cells,
+                        ));
+                    }
+                }
+                _ => match index {
+                    Some(index) if self.explodes(field) => {
+                        rows = rows.max(value.map_or(0, array_len));
+                        let element = value.and_then(|value| element(value, index));
+                        self.value_cells(&field.field_type, element.as_deref(), None, cells);
+                    }
+                    _ => cells.push(value.map(serialize).unwrap_or_default()),
+                },
+            }
+        }
+        rows
+    }
+
+    fn value_cells(
+        &self,
+        field_type: &str,
+        value: Option<&Value>,
+        index: Option<usize>,
+        cells: &mut Vec<String>,
+    ) -> usize {
+        match self.decoder.type_fields(field_type) {
+            Some(fields) => self.field_cells(fields, value, index, cells),
+            None => {
+                cells.push(value.map(cell).unwrap_or_default());
+                1
+            }
+        }
+    }
+}
+
+fn element(array: &Value, index: usize) -> Option<Cow<'_, Value>> {
+    match array {
+        Value::Array(values) => values.get(index).map(Cow::Borrowed),
+        Value::Bytes(bytes) => bytes
+            .get(index)
+            .map(|byte| Cow::Owned(Value::UInt(*byte as u64))),
+        _ => None,
+    }
+}
+
+fn array_len(array: &Value) -> usize {
+    match array {
+        Value::Array(values) => values.len(),
+        Value::Bytes(bytes) => bytes.len(),
+        _ => 0,
+    }
+}
+
+/// A primitive as text, times as nanoseconds
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Bool(value) => value.to_string(),
+        Value::Int(value) => value.to_string(),
+        Value::UInt(value) => value.to_string(),
+        // Debug switches to exponents for very small and large values
+        Value::Float(value) => format!("{:?}", value),
+        Value::String(value) => value.clone(),
+        Value::Time(value) => value.to_string(),
+        Value::Duration(value) => value.to_string(),
+        value => serialize(value),
+    }
+}
+
+/// Arrays in CSV cells keep their bytes as numbers so they read back without decoding
+const CSV_JSON: JsonOptions = JsonOptions {
+    bytes: BytesFormat::Array,
+    non_finite: NonFiniteFormat::Null,
+    time: TimeFormat::Nanos,
+};
+
+fn serialize(value: &Value) -> String {
+    CSV_JSON.to_json(value).to_string()
+}
+
+/// How byte arrays like image data are written to JSON
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BytesFormat {
+    #[default]
+    Base64,
+    Hex,
+    /// An array of numbers
+    Array,
+}
+
+impl FromStr for BytesFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "base64" => BytesFormat::Base64,
+            "hex" => BytesFormat::Hex,
+            "array" => BytesFormat::Array,
+            _ => bail!(
+                "Unknown bytes format '{}', expected base64, hex or array",
+                s
+            ),
+        })
+    }
+}
+
+/// How NaN and infinite floats are written to JSON, which has no literals for them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonFiniteFormat {
+    #[default]
+    Null,
+    /// `"NaN"`, `"Infinity"` and `"-Infinity"` as understood by most JSON parsers
+    String,
+}
+
+impl FromStr for NonFiniteFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "null" => NonFiniteFormat::Null,
+            "string" => NonFiniteFormat::String,
+            _ => bail!("Unknown non-finite format '{}', expected null or string", s),
+        })
+    }
+}
+
+/// How times and durations are written to JSON
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// Integer nanoseconds, exact
+    #[default]
+    Nanos,
+    /// Float seconds, microsecond precision for times since the epoch
+    Seconds,
+    /// RFC 3339 UTC strings for times, float seconds for durations
+    Iso,
+}
+
+impl FromStr for TimeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "nanos" => TimeFormat::Nanos,
+            "seconds" => TimeFormat::Seconds,
+            "iso" => TimeFormat::Iso,
+            _ => bail!(
+                "Unknown time format '{}', expected nanos, seconds or iso",
+                s
+            ),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOptions {
+    pub bytes: BytesFormat,
+    pub non_finite: NonFiniteFormat,
+    pub time: TimeFormat,
+}
+
+impl JsonOptions {
+    /// JSON of a decoded value with fields in definition order
+    pub fn to_json(&self, value: &Value) -> serde_json::Value {
+        match value {
+            Value::Bool(value) => (*value).into(),
+            Value::Int(value) => (*value).into(),
+            Value::UInt(value) => (*value).into(),
+            Value::Float(value) => self.float(*value),
+            Value::String(value) => value.as_str().into(),
+            Value::Time(value) => self.time(*value),
+            Value::Duration(value) => match self.time {
+                TimeFormat::Nanos => (*value).into(),
+                TimeFormat::Seconds | TimeFormat::Iso => (*value as f64 / 1e9).into(),
+            },
+            Value::Bytes(bytes) => match self.bytes {
+                BytesFormat::Base64 => BASE64_STANDARD.encode(bytes).into(),
+                BytesFormat::Hex => bytes
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<String>()
+                    .into(),
+                BytesFormat::Array => bytes
+                    .iter()
+                    .map(|byte| serde_json::Value::from(*byte))
+                    .collect(),
+            },
+            Value::Array(values) => values.iter().map(|value| self.to_json(value)).collect(),
+            Value::Message(fields) => serde_json::Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), self.to_json(value)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// A time since the epoch like the record time
+    pub fn time(&self, time: u64) -> serde_json::Value {
+        match self.time {
+            TimeFormat::Nanos => time.into(),
+            TimeFormat::Seconds => (time as f64 / 1e9).into(),
+            TimeFormat::Iso => format_time_nanos(time).into(),
+        }
+    }
+
+    fn float(&self, value: f64) -> serde_json::Value {
+        match self.non_finite {
+            _ if value.is_finite() => value.into(),
+            NonFiniteFormat::Null => serde_json::Value::Null,
+            NonFiniteFormat::String if value.is_nan() => "NaN".into(),
+            NonFiniteFormat::String if value > 0.0 => "Infinity".into(),
+            NonFiniteFormat::String => "-Infinity".into(),
+        }
+    }
+}
+
+/// Call `on_message` with connection, record time and decoded message of every selected message
+fn read_decoded<F>(source: MessageSource, topics: &TopicSelection, mut on_message: F) -> Result<()>
+where
+    F: FnMut(&Connection, u64, Value) -> Result<()>,
+{
+    let mut decoders = BTreeMap::new();
+    source.read_messages(topics, |connection, time, data| {
+        let decoder = match decoders.entry(connection.id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(MessageDecoder::for_connection(connection)?),
+        };
+        on_message(connection, time, decoder.decode(data)?)
+    })
+}
+
+/// Write a JSON object with `topic`, `time` and the decoded `message` per line
+/// for the messages of all selected topics, in file order
+pub fn export_jsonl<W: Write>(
+    source: MessageSource,
+    topics: &TopicSelection,
+    options: JsonOptions,
+    mut writer: W,
+) -> Result<ExportReport> {
+    let mut report = ExportReport::default();
+    read_decoded(source, topics, |connection, time, message| {
+        let line = serde_json::json!({
+            "topic": connection.topic,
+            "time": options.time(time),
+            "message": options.to_json(&message),
+        });
+        // Serialized first so a closed pipe shows up as an io error
+        let mut line = serde_json::to_vec(&line)?;
+        line.push(b'\n');
+        writer.write_all(&line)?;
+        report.messages += 1;
+        report.rows += 1;
+        Ok(())
+    })?;
+    writer.flush()?;
+    Ok(report)
+}
+
+/// Write the messages of all selected topics as `echo_text`, each after a line with topic and record time
+pub fn echo<W: Write>(
+    source: MessageSource,
+    topics: &TopicSelection,
+    mut writer: W,
+) -> Result<ExportReport> {
+    let mut report = ExportReport::default();
+    read_decoded(source, topics, |connection, time, message| {
+        let text = echo_text(&message);
+        writeln!(
+            writer,
+            "--- {} {}",
+            connection.topic,
+            format_time_nanos(time)
+        )?;
+        writer.write_all(text.as_bytes())?;
+        report.messages += 1;
+        report.rows += 1;
+        Ok(())
+    })?;
+    writer.flush()?;
+    Ok(report)
+}
+
+/// A decoded message as indented `name: value` lines like `rostopic echo`.
+/// Times are seconds, byte arrays show only their length.
+pub fn echo_text(message: &Value) -> String {
+    let mut text = String::new();
+    if let Value::Message(fields) = message {
+        write_fields(fields, 0, &mut text);
+    }
+    text
+}
+
+fn write_fields(fields: &[(String, Value)], indent: usize, text: &mut String) {
+    for (name, value) in fields {
+        text.push_str(&"  ".repeat(indent));
+        text.push_str(name);
+        text.push(':');
+        write_value(value, indent, text);
+    }
+}
+
+fn write_value(value: &Value, indent: usize, text: &mut String) {
+    match value {
+        Value::Message(fields) => {
+            text.push('\n');
+            write_fields(fields, indent + 1, text);
+        }
+        Value::Array(values)
+            if values
+                .iter()
+                .any(|value| matches!(value, Value::Message(_))) =>
+        {
+            text.push('\n');
+            for value in values {
+                text.push_str(&"  ".repeat(indent));
+                text.push_str("  -");
+                write_value(value, indent + 1, text);
+            }
+        }
+        value => {
+            text.push(' ');
+            text.push_str(&inline_text(value));
+            text.push('\n');
+        }
+    }
+}
+
+fn inline_text(value: &Value) -> String {
+    match value {
+        Value::String(value) => serde_json::Value::from(value.as_str()).to_string(),
+        Value::Time(value) => format!("{}.{:09}", value / NANOS_PER_SEC, value % NANOS_PER_SEC),
+        Value::Duration(value) => format!("{}", *value as f64 / 1e9),
+        Value::Bytes(bytes) => format!("<{} bytes>", bytes.len()),
+        Value::Array(values) => format!(
+            "[{}]",
+            values
+                .iter()
+                .map(inline_text)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::Message(_) => unreachable!("messages are written as nested lines"),
+        value => cell(value),
+    }
+}
+
+/// All exported messages need the topic and type of the first one, a pattern that matches
+/// several topics would mix them without telling them apart
+pub(crate) fn check_connection(first: &Connection, connection: &Connection) -> Result<()> {
+    if connection.topic != first.topic {
+        bail!(
+            "Both {} and {} match, can only export one topic",
+            first.topic,
+            connection.topic
+        );
+    }
+    if connection.tp != first.tp {
+        bail!(
+            "{} has messages of types {} and {}, can only export one type",
+            connection.topic,
+            first.tp,
+            connection.tp
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    pub messages: u64,
+    pub rows: u64,
+}
+
+/// Write the messages of `topic` as CSV with the record time in nanoseconds as first column
+/// and a column per flattened field. A pattern has to match a single topic, whose messages
+/// all have the same type.
+pub fn export_csv<W: Write>(
+    source: MessageSource,
+    topic: &str,
+    arrays: ArrayMode,
+    writer: W,
+) -> Result<ExportReport> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut report = ExportReport::default();
+    let mut flattener: Option<(Connection, Flattener)> = None;
+
+    let topics = TopicSelection::parse(&[topic])?;
+    source.read_messages(&topics, |connection, time, data| {
+        if flattener.is_none() {
+            let new = Flattener::new(MessageDecoder::for_connection(connection)?, arrays);
+            if new.columns().iter().any(|column| column == "time") {
+                bail!("The field time collides with the record time column");
+            }
+            writer.write_record(
+                ["time"]
+                    .into_iter()
+                    .chain(new.columns().iter().map(String::as_str)),
+            )?;
+            flattener = Some((connection.clone(), new));
+        }
+        let (first, flattener) = flattener.as_ref().unwrap();
+        check_connection(first, connection)?;
+
+        let time = time.to_string();
+        for row in flattener.rows(&flattener.decoder().decode(data)?) {
+            writer.write_record([&time].into_iter().chain(row.iter()))?;
+            report.rows += 1;
+        }
+        report.messages += 1;
+        Ok(())
+    })?;
+
+    if report.messages == 0 {
+        bail!("No messages on {}", topic);
+    }
+    writer.flush()?;
+    Ok(report)
+}
@@ -0,0 +1,438 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Seek, Write};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::downsample::Downsampler;
+use crate::indexing::MessageSource;
+use crate::message_decoder::{MessageDecoder, Value};
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+use crate::time::NANOS_PER_SEC;
+use crate::writer::BagWriter;
+
+/// A predicate over the messages of the selected topics.
+///
+/// `/odom: twist.twist.linear.x > 5.0 and header.frame_id == "odom"` applies to `/odom` only,
+/// without the topic prefix an expression applies to all topics.
+/// Fields are referenced by their path, `$topic` is the topic name and `$time` the record time
+/// in seconds since the epoch. Comparisons are `== != < <= > >=`, combined with `and`, `or`, `not`
+/// (or `&&`, `||`, `!`) and parentheses.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub topics: TopicSelection,
+    expr: Expr,
+    source: String,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self> {
+        // A topic selector never contains whitespace, an expression before `: ` always does
+        let (topics, expression) = match source.split_once(": ") {
+            Some((topics, expression)) if !topics.trim().contains(char::is_whitespace) => {
+                (TopicSelection::parse(&[topics.trim()])?, expression)
+            }
+            _ => (TopicSelection::all(), source),
+        };
+        let tokens =
+            tokenize(expression).map_err(|e| anyhow!("Invalid filter '{}': {}", source, e))?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser
+            .parse()
+            .map_err(|e| anyhow!("Invalid filter '{}': {}", source, e))?;
+        Ok(Self {
+            topics,
+            expr,
+            source: source.to_string(),
+        })
+    }
+
+    /// Whether the message data has to be decoded to evaluate the filter
+    pub fn uses_fields(&self) -> bool {
+        self.expr.uses_fields()
+    }
+
+    /// `message` is only needed if the filter `uses_fields`
+    pub fn matches(&self, topic: &str, time: u64, message: Option<&Value>) -> bool {
+        let context = Context {
+            topic,
+            time,
+            message,
+        };
+        matches!(self.expr.eval(&context), Operand::Bool(true))
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Decides for every message whether it is kept, given a set of filters
+pub struct MessageFilter {
+    filters: Vec<Filter>,
+    /// Keep messages on topics that no filter applies to
+    keep_other: bool,
+    decoders: BTreeMap<u32, MessageDecoder>,
+    /// Messages per topic that could not be decoded, their fields never match
+    undecodable: BTreeMap<String, u64>,
+    /// Reduces the messages that pass the filters
+    downsampler: Downsampler,
+}
+
+impl MessageFilter {
+    pub fn new(filters: Vec<Filter>, keep_other: bool) -> Self {
+        Self {
+            filters,
+            keep_other,
+            decoders: BTreeMap::new(),
+            undecodable: BTreeMap::new(),
+            downsampler: Downsampler::default(),
+        }
+    }
+
+    pub fn with_downsampler(self, downsampler: Downsampler) -> Self {
+        Self {
+            downsampler,
+            ..self
+        }
+    }
+
+    /// A message is kept if any filter that applies to its topic matches
+    /// and the downsampler keeps it
+    pub fn keep(&mut self, connection: &Connection, time: u64, data: &[u8]) -> Result<bool> {
+        Ok(self.matches(connection, time, data)? && self.downsampler.keep(&connection.topic, time))
+    }
+
+    fn matches(&mut self, connection: &Connection, time: u64, data: &[u8]) -> Result<bool> {
+        let filters: Vec<&Filter> = self
+            .filters
+            .iter()
+            .filter(|filter| filter.topics.matches(&connection.topic))
+            .collect();
+        if filters.is_empty() {
+            return Ok(self.keep_other);
+        }
+
+        let message = if filters.iter().any(|filter| filter.uses_fields()) {
+            let decoder = match self.decoders.entry(connection.id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(MessageDecoder::for_connection(connection)?),
+            };
+            match decoder.decode(data) {
+                Ok(message) => Some(message),
+                Err(_) => {
+                    *self
+                        .undecodable
+                        .entry(connection.topic.clone())
+                        .or_default() += 1;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        // Filters on fields do not match a message that cannot be decoded, even negated ones,
+        // filters on only $topic and $time still apply
+        Ok(filters.iter().any(|filter| {
+            (message.is_some() || !filter.uses_fields())
+                && filter.matches(&connection.topic, time, message.as_ref())
+        }))
+    }
+
+    pub fn undecodable(&self) -> &BTreeMap<String, u64> {
+        &self.undecodable
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    Field(String),
+    Topic,
+    Time,
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+}
+
+struct Context<'a> {
+    topic: &'a str,
+    time: u64,
+    message: Option<&'a Value>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand<'a> {
+    Number(f64),
+    String(&'a str),
+    Bool(bool),
+    /// Missing fields and values that cannot be compared, like arrays
+    Invalid,
+}
+
+impl Expr {
+    fn uses_fields(&self) -> bool {
+        match self {
+            Expr::Field(_) => true,
+            Expr::Neg(expr) | Expr::Not(expr) => expr.uses_fields(),
+            Expr::And(a, b) | Expr::Or(a, b) | Expr::Compare(a, _, b) => {
+                a.uses_fields() || b.uses_fields()
+            }
+            _ => false,
+        }
+    }
+
+    fn eval<'a>(&'a self, context: &Context<'a>) -> Operand<'a> {
+        match self {
+            Expr::Number(number) => Operand::Number(*number),
+            Expr::String(string) => Operand::String(string),
+            Expr::Bool(value) => Operand::Bool(*value),
+            Expr::Topic => Operand::String(context.topic),
+            Expr::Time => Operand::Number(context.time as f64 / NANOS_PER_SEC as f64),
+            Expr::Field(path) => match context.message.and_then(|message| message.get(path)) {
+                Some(Value::Bool(value)) => Operand::Bool(*value),
+                Some(Value::String(string)) => Operand::String(string),
+                Some(value) => value.as_f64().map_or(Operand::Invalid, Operand::Number),
+                None => Operand::Invalid,
+            },
+            Expr::Neg(expr) => match expr.eval(context) {
+                Operand::Number(number) => Operand::Number(-number),
+                _ => Operand::Invalid,
+            },
+            Expr::Not(expr) => match expr.eval(context) {
+                Operand::Bool(value) => Operand::Bool(!value),
+                _ => Operand::Invalid,
+            },
+            Expr::And(a, b) => Operand::Bool(
+                a.eval(context) == Operand::Bool(true) && b.eval(context) == Operand::Bool(true),
+            ),
+            Expr::Or(a, b) => Operand::Bool(
+                a.eval(context) == Operand::Bool(true) || b.eval(context) == Operand::Bool(true),
+            ),
+            Expr::Compare(a, op, b) => {
+                let ordering = match (a.eval(context), b.eval(context)) {
+                    (Operand::Number(a), Operand::Number(b)) => a.partial_cmp(&b),
+                    (Operand::String(a), Operand::String(b)) => Some(a.cmp(b)),
+                    (Operand::Bool(a), Operand::Bool(b)) => Some(a.cmp(&b)),
+                    _ => None,
+                };
+                // Comparisons with a missing field or of different types are false
+                Operand::Bool(ordering.is_some_and(|ordering| match op {
+                    CompareOp::Eq => ordering.is_eq(),
+                    CompareOp::Ne => ordering.is_ne(),
+                    CompareOp::Lt => ordering.is_lt(),
+                    CompareOp::Le => ordering.is_le(),
+                    CompareOp::Gt => ordering.is_gt(),
+                    CompareOp::Ge => ordering.is_ge(),
+                }))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    String(String),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(number) => write!(f, "{}", number),
+            Token::String(string) => write!(f, "{:?}", string),
+            Token::Ident(ident) => write!(f, "{}", ident),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 13] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "(", ")", "-", "=",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() || c == '.' && rest[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            let number = rest[..end].replace('_', "");
+            match number.parse() {
+                Ok(number) => tokens.push(Token::Number(number)),
+                Err(_) => bail!("invalid number '{}'", &rest[..end]),
+            }
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .map_or(rest.len(), |end| end + 1);
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c == '"' || c == '\'' {
+            let Some(end) = rest[1..].find(c) else {
+                bail!("unterminated string");
+            };
+            tokens.push(Token::String(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            bail!("unexpected character '{}'", c);
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn parse(&mut self) -> Result<Expr> {
+        let expr = self.or()?;
+        match self.tokens.get(self.pos) {
+            Some(token) => bail!("unexpected '{}'", token),
+            None => Ok(expr),
+        }
+    }
+
+    fn peek_is(&self, alternatives: &[&str]) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token::Symbol(symbol)) => alternatives.contains(symbol),
+            Some(Token::Ident(ident)) => alternatives.contains(&ident.as_str()),
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek_is(&["or", "||"]) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while self.peek_is(&["and", "&&"]) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.peek_is(&["not", "!"]) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.primary()?;
+        let op = match self.tokens.get(self.pos) {
+            Some(Token::Symbol("==")) => CompareOp::Eq,
+            Some(Token::Symbol("!=")) => CompareOp::Ne,
+            Some(Token::Symbol("<")) => CompareOp::Lt,
+            Some(Token::Symbol("<=")) => CompareOp::Le,
+            Some(Token::Symbol(">")) => CompareOp::Gt,
+            Some(Token::Symbol(">=")) => CompareOp::Ge,
+            Some(Token::Symbol("=")) => bail!("use '==' to compare"),
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Compare(Box::new(left), op, Box::new(self.primary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            bail!("unexpected end of expression");
+        };
+        self.pos += 1;
+        Ok(match token {
+            Token::Number(number) => Expr::Number(number),
+            Token::String(string) => Expr::String(string),
+            Token::Symbol("-") => Expr::Neg(Box::new(self.primary()?)),
+            Token::Symbol("(") => {
+                let expr = self.or()?;
+                if !self.peek_is(&[")"]) {
+                    bail!("missing ')'");
+                }
+                self.pos += 1;
+                expr
+            }
+            Token::Ident(ident) => match ident.as_str() {
+                "true" => Expr::Bool(true),
+                "false" => Expr::Bool(false),
+                "$topic" => Expr::Topic,
+                "$time" => Expr::Time,
+                "and" | "or" | "not" => bail!("unexpected '{}'", ident),
+                ident if ident.starts_with('$') => {
+                    bail!("unknown variable '{}', expected $topic or $time", ident)
+                }
+                _ => Expr::Field(ident),
+            },
+            token => bail!("unexpected '{}'", token),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FilterReport {
+    pub kept: u64,
+    pub dropped: u64,
+    /// Messages per topic that could not be decoded to evaluate field expressions
+    pub undecodable: BTreeMap<String, u64>,
+}
+
+/// Copy the messages that `filter` keeps to `writer`
+pub fn filter_messages<W: Write + Seek>(
+    source: MessageSource,
+    writer: &mut BagWriter<W>,
+    filter: &mut MessageFilter,
+) -> Result<FilterReport> {
+    let mut report = FilterReport::default();
+    let mut conn_ids = BTreeMap::new();
+    source.read_messages(&TopicSelection::all(), |connection, time, data| {
+        if !filter.keep(connection, time, data)? {
+            report.dropped += 1;
+            return Ok(());
+        }
+        let conn_id = *conn_ids
+            .entry(connection.id)
+            .or_insert_with(|| writer.add_connection(connection));
+        writer.write_message(conn_id, time, data)?;
+        report.kept += 1;
+        Ok(())
+    })?;
+    report.undecodable = filter.undecodable().clone();
+    Ok(report)
+}
@@ -1,119 +1,369 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::path::Path;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
-use rosbag::{ChunkRecord, IndexRecord, MessageRecord, RosBag};
+use anyhow::{bail, Result};
 
-pub fn read_bags(path: &Path) -> HashMap<String, RosBag> {
-    let paths = fs::read_dir(path).unwrap();
+use crate::bag::{Bag, BagStream, ChunkRecord, IndexRecord, MessageRecord};
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+
+/// Paths of all bags and MCAP files in a directory, sorted, including subdirectories if `recursive` is set
+pub fn find_bags(path: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
     let mut bag_paths = vec![];
 
-    for path in paths {
-        let p = path.unwrap().path();
-        if let Some(bag) = p.extension() {
-            if bag.to_str().unwrap() == "bag" {
-                bag_paths.push(p);
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let p = entry.path();
+        // Symlinked directories are not followed to avoid cycles
+        if entry.file_type()?.is_dir() {
+            if recursive {
+                bag_paths.extend(find_bags(&p, true)?);
             }
+        } else if p
+            .extension()
+            .is_some_and(|extension| extension == "bag" || extension == "mcap")
+        {
+            bag_paths.push(p);
         }
     }
 
-    let mut bags = HashMap::<String, RosBag>::new();
+    bag_paths.sort();
+    Ok(bag_paths)
+}
+
+pub fn read_bags(path: &Path) -> Result<HashMap<String, Bag>> {
+    let mut bags = HashMap::<String, Bag>::new();
 
-    for bag_path in bag_paths {
-        let bag_path_str = bag_path.file_name().unwrap().to_str().unwrap();
-        let bag = RosBag::new(bag_path_str).unwrap();
+    for bag_path in find_bags(path, false)? {
+        let bag_path_str = bag_path.file_name().unwrap().to_string_lossy().to_string();
+        let bag = Bag::open(&bag_path)?;
 
-        bags.insert(bag_path_str.to_string(), bag);
+        bags.insert(bag_path_str, bag);
     }
-    bags
+    Ok(bags)
 }
 
-pub fn read_bag(path: &Path) -> RosBag {
-    let bag_path_str = path.to_str().unwrap();
-    RosBag::new(bag_path_str).unwrap()
+pub fn read_bag(path: &Path) -> Result<Bag> {
+    Bag::open(path)
 }
 
-pub fn get_messages(bag: &RosBag, topic: &str) -> Vec<Vec<u8>> {
-    let mut conn_id = u32::max_value();
-    let mut messages = vec![];
-
-    // Get connection id from topic name
+/// Connections with their message type, md5sum, caller id and latching flag
+pub fn get_connections(bag: &Bag, topics: &TopicSelection) -> Result<Vec<Connection>> {
+    if let Some(mcap) = bag.mcap() {
+        let mut connections = mcap.index.connections();
+        connections.retain(|conn| topics.matches(&conn.topic));
+        return Ok(connections);
+    }
+    let mut connections = vec![];
     for record in bag.index_records() {
-        match record {
-            // connection records always come first so conn_id can be read before the first chunk info appears
-            Ok(IndexRecord::Connection(conn)) => {
-                if conn.topic == topic {
-                    conn_id = conn.id;
-                    break;
+        match record? {
+            IndexRecord::Connection(conn) => {
+                if topics.matches(&conn.topic) {
+                    connections.push(conn);
                 }
             }
-            Ok(IndexRecord::ChunkInfo(_)) => {}
-            Err(_) => todo!(),
+            // connection records always come first so there are no more after the first chunk info
+            IndexRecord::ChunkInfo(_) => break,
+        }
+    }
+    Ok(connections)
+}
+
+/// Ids of the connections whose topic is selected
+fn get_conn_ids(bag: &Bag, topics: &TopicSelection) -> Result<BTreeSet<u32>> {
+    let conn_ids: BTreeSet<u32> = get_connections(bag, topics)?
+        .iter()
+        .map(|conn| conn.id)
+        .collect();
+
+    if conn_ids.is_empty() {
+        bail!("No topic matches {}", topics)
+    }
+    Ok(conn_ids)
+}
+
+pub fn get_messages(bag: &Bag, topics: &TopicSelection) -> Result<Vec<Vec<u8>>> {
+    get_conn_ids(bag, topics)?;
+    let mut messages = vec![];
+    read_messages(bag, topics, |_, _, data| {
+        messages.push(data.to_vec());
+        Ok(())
+    })?;
+    Ok(messages)
+}
+
+/// A bag to read messages from, either indexed or streamed
+pub enum MessageSource {
+    Bag(Bag),
+    Stream(Box<dyn Read>),
+}
+
+impl MessageSource {
+    /// `-` streams the bag from stdin
+    pub fn open(path: &Path) -> Result<Self> {
+        if path.as_os_str() == "-" {
+            Ok(MessageSource::Stream(Box::new(io::stdin().lock())))
+        } else {
+            Ok(MessageSource::Bag(read_bag(path)?))
         }
     }
 
-    if conn_id == u32::MAX {
-        todo!("Topic not found")
+    /// Same as `read_messages`
+    pub fn read_messages<F>(self, topics: &TopicSelection, on_message: F) -> Result<()>
+    where
+        F: FnMut(&Connection, u64, &[u8]) -> Result<()>,
+    {
+        match self {
+            MessageSource::Bag(bag) => read_messages(&bag, topics, on_message),
+            MessageSource::Stream(reader) => read_stream_messages(reader, topics, on_message),
+        }
+    }
+}
+
+/// Call `on_message` with connection, record time and data of every selected message in file order
+pub fn read_messages<F>(bag: &Bag, topics: &TopicSelection, mut on_message: F) -> Result<()>
+where
+    F: FnMut(&Connection, u64, &[u8]) -> Result<()>,
+{
+    let connections: BTreeMap<u32, Connection> = get_connections(bag, topics)?
+        .into_iter()
+        .map(|conn| (conn.id, conn))
+        .collect();
+
+    if bag.mcap().is_some() {
+        for message in bag.messages() {
+            let message = message?;
+            if let Some(connection) = connections.get(&message.conn_id) {
+                on_message(connection, message.time, &message.data)?;
+            }
+        }
+        return Ok(());
     }
 
     // Chunk records contain connection and message records
     for record in bag.chunk_records() {
-        match record {
-            Ok(ChunkRecord::Chunk(chunk)) => {
+        match record? {
+            ChunkRecord::Chunk(chunk) => {
+                let chunk = chunk.decompress()?;
                 for message in chunk.messages() {
-                    match message {
-                        Ok(MessageRecord::Connection(_)) => {}
-                        Ok(MessageRecord::MessageData(message_data)) => {
-                            if message_data.conn_id == conn_id {
-                                messages.push(message_data.data.to_vec());
+                    match message? {
+                        MessageRecord::Connection(_) => {}
+                        MessageRecord::MessageData(message_data) => {
+                            if let Some(connection) = connections.get(&message_data.conn_id) {
+                                on_message(connection, message_data.time, message_data.data)?;
                             }
                         }
-                        Err(_) => todo!(),
                     }
                 }
             }
-            Ok(ChunkRecord::IndexData(_)) => {}
-            Err(_) => todo!(),
+            ChunkRecord::IndexData(_) => {}
         }
     }
+    Ok(())
+}
 
-    messages
+/// Same as `read_messages` for a bag that can only be read sequentially
+pub fn read_stream_messages<R, F>(
+    reader: R,
+    topics: &TopicSelection,
+    mut on_message: F,
+) -> Result<()>
+where
+    R: Read,
+    F: FnMut(&Connection, u64, &[u8]) -> Result<()>,
+{
+    let mut stream = BagStream::new(reader)?;
+    while let Some(message) = stream.next_message()? {
+        let Some(connection) = stream.connection(message.conn_id) else {
+            bail!("Message on unknown connection {}", message.conn_id);
+        };
+        if topics.matches(&connection.topic) {
+            on_message(connection, message.time, &message.data)?;
+        }
+    }
+    Ok(())
 }
 
-type Topic<'a> = &'a str;
-type MessageDefinition<'a> = &'a str;
-pub fn get_topics(bag: &RosBag) -> BTreeMap<Topic, MessageDefinition> {
+type Topic = String;
+type MessageDefinition = String;
+pub fn get_topics(bag: &Bag) -> Result<BTreeMap<Topic, MessageDefinition>> {
+    if bag.mcap().is_some() {
+        return Ok(get_connections(bag, &TopicSelection::all())?
+            .into_iter()
+            .map(|conn| (conn.topic, conn.message_definition))
+            .collect());
+    }
     let mut result = BTreeMap::new();
     for record in bag.index_records() {
-        match record {
-            Ok(IndexRecord::Connection(conn)) => {
+        match record? {
+            IndexRecord::Connection(conn) => {
                 result.insert(conn.topic, conn.message_definition);
             }
-            Ok(IndexRecord::ChunkInfo(_)) => {}
-            Err(_) => todo!(),
+            IndexRecord::ChunkInfo(_) => {}
         }
     }
-    result
+    Ok(result)
 }
 
-pub fn get_message_count(bag: &RosBag) -> BTreeMap<&str, u64> {
+pub fn get_message_count(bag: &Bag, topics: &TopicSelection) -> Result<BTreeMap<Topic, u64>> {
+    if bag.mcap().is_some() {
+        return Ok(get_message_times(bag, topics)?
+            .into_iter()
+            .map(|(topic, times)| (topic, times.len() as u64))
+            .collect());
+    }
     let mut conn_id_to_topic = BTreeMap::new();
     let mut count = BTreeMap::new();
     for record in bag.index_records() {
-        match record {
-            Ok(IndexRecord::Connection(conn)) => {
-                conn_id_to_topic.insert(conn.id, conn.topic);
-                count.insert(conn.topic, 0);
+        match record? {
+            IndexRecord::Connection(conn) => {
+                if topics.matches(&conn.topic) {
+                    count.insert(conn.topic.clone(), 0);
+                    conn_id_to_topic.insert(conn.id, conn.topic);
+                }
             }
-            Ok(IndexRecord::ChunkInfo(chunk_info)) => {
+            IndexRecord::ChunkInfo(chunk_info) => {
                 chunk_info.entries().for_each(|entry| {
-                    count
-                        .entry(conn_id_to_topic.get(&entry.conn_id).unwrap())
-                        .and_modify(|count| *count += entry.count as u64);
+                    if let Some(topic) = conn_id_to_topic.get(&entry.conn_id) {
+                        count
+                            .entry(topic.clone())
+                            .and_modify(|count| *count += entry.count as u64);
+                    }
                 });
             }
-            Err(_) => todo!(),
         }
     }
-    count
+    Ok(count)
+}
+
+/// Earliest record time of the bag from its chunk infos, None if it has no messages
+pub fn get_start_time(bag: &Bag) -> Result<Option<u64>> {
+    if let Some(mcap) = bag.mcap() {
+        if !mcap.index.chunk_indexes.is_empty() {
+            return Ok(mcap
+                .index
+                .chunk_indexes
+                .iter()
+                .map(|chunk| chunk.start_time)
+                .min());
+        }
+        let times = mcap.message_times()?;
+        return Ok(times.values().flatten().copied().min());
+    }
+    let mut start_time = None;
+    for record in bag.index_records() {
+        if let IndexRecord::ChunkInfo(chunk_info) = record? {
+            start_time = Some(start_time.map_or(chunk_info.start_time, |time: u64| {
+                time.min(chunk_info.start_time)
+            }));
+        }
+    }
+    Ok(start_time)
+}
+
+/// Sorted record times of the messages per topic, read from the index without the chunks
+pub fn get_message_times(bag: &Bag, topics: &TopicSelection) -> Result<BTreeMap<Topic, Vec<u64>>> {
+    let mut conn_id_to_topic = BTreeMap::new();
+    let mut times = BTreeMap::<Topic, Vec<u64>>::new();
+    for conn in get_connections(bag, topics)? {
+        times.entry(conn.topic.clone()).or_default();
+        conn_id_to_topic.insert(conn.id, conn.topic);
+    }
+    if let Some(mcap) = bag.mcap() {
+        for (channel_id, channel_times) in mcap.message_times()? {
+            if let Some(topic) = conn_id_to_topic.get(&(channel_id as u32)) {
+                times.get_mut(topic).unwrap().extend(channel_times);
+            }
+        }
+    }
+    for record in bag.chunk_records() {
+        if let ChunkRecord::IndexData(index_data) = record? {
+            if let Some(topic) = conn_id_to_topic.get(&index_data.conn_id) {
+                times
+                    .get_mut(topic)
+                    .unwrap()
+                    .extend(index_data.entries().map(|entry| entry.time));
+            }
+        }
+    }
+    times.values_mut().for_each(|times| times.sort_unstable());
+    Ok(times)
+}
+
+/// Same as `get_topics` for a bag that can only be read sequentially
+pub fn get_stream_topics<R: Read>(reader: R) -> Result<BTreeMap<Topic, MessageDefinition>> {
+    let mut stream = BagStream::new(reader)?;
+    while stream.next_message()?.is_some() {}
+    Ok(stream
+        .connections()
+        .values()
+        .map(|conn| (conn.topic.clone(), conn.message_definition.clone()))
+        .collect())
+}
+
+/// Same as `get_connections` for a bag that can only be read sequentially
+pub fn get_stream_connections<R: Read>(
+    reader: R,
+    topics: &TopicSelection,
+) -> Result<Vec<Connection>> {
+    let mut stream = BagStream::new(reader)?;
+    while stream.next_message()?.is_some() {}
+    Ok(stream
+        .connections()
+        .values()
+        .filter(|conn| topics.matches(&conn.topic))
+        .cloned()
+        .collect())
+}
+
+/// Same as `get_message_count` for a bag that can only be read sequentially
+pub fn get_stream_message_count<R: Read>(
+    reader: R,
+    topics: &TopicSelection,
+) -> Result<BTreeMap<Topic, u64>> {
+    let mut stream = BagStream::new(reader)?;
+    let mut count_per_conn = BTreeMap::<u32, u64>::new();
+    while let Some(message) = stream.next_message()? {
+        *count_per_conn.entry(message.conn_id).or_default() += 1;
+    }
+
+    let mut count = BTreeMap::new();
+    for (conn_id, conn) in stream.connections() {
+        if !topics.matches(&conn.topic) {
+            continue;
+        }
+        *count.entry(conn.topic.clone()).or_default() +=
+            count_per_conn.get(conn_id).copied().unwrap_or_default();
+    }
+    Ok(count)
+}
+
+/// Same as `get_message_times` for a bag that can only be read sequentially
+pub fn get_stream_message_times<R: Read>(
+    reader: R,
+    topics: &TopicSelection,
+) -> Result<BTreeMap<Topic, Vec<u64>>> {
+    let mut stream = BagStream::new(reader)?;
+    let mut times_per_conn = BTreeMap::<u32, Vec<u64>>::new();
+    while let Some(message) = stream.next_message()? {
+        times_per_conn
+            .entry(message.conn_id)
+            .or_default()
+            .push(message.time);
+    }
+
+    let mut times = BTreeMap::<Topic, Vec<u64>>::new();
+    for (conn_id, conn) in stream.connections() {
+        if !topics.matches(&conn.topic) {
+            continue;
+        }
+        times
+            .entry(conn.topic.clone())
+            .or_default()
+            .extend(times_per_conn.remove(conn_id).unwrap_or_default());
+    }
+    times.values_mut().for_each(|times| times.sort_unstable());
+    Ok(times)
 }
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::bag::Bag;
+use crate::indexing::{read_messages, read_stream_messages};
+use crate::message_decoder::HeaderDecoders;
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+use crate::time::NANOS_PER_SEC;
+
+/// Header stamp and record time of one message
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencySample {
+    pub topic: String,
+    pub time: u64,
+    pub stamp: u64,
+}
+
+impl LatencySample {
+    /// Record time minus header stamp in nanoseconds, negative if stamped in the future
+    pub fn latency(&self) -> i64 {
+        self.time as i64 - self.stamp as i64
+    }
+}
+
+/// Header stamps of the selected messages
+#[derive(Debug, Default, PartialEq)]
+pub struct LatencySamples {
+    /// In file order
+    pub samples: Vec<LatencySample>,
+    /// Messages per topic whose header could not be read, they have no sample
+    pub malformed: BTreeMap<String, u64>,
+}
+
+/// Collects the header stamps of messages on topics that start with a `std_msgs/Header`
+#[derive(Default)]
+struct LatencyCollector {
+    decoders: HeaderDecoders,
+    samples: LatencySamples,
+}
+
+impl LatencyCollector {
+    fn add(&mut self, connection: &Connection, time: u64, data: &[u8]) -> Result<()> {
+        // A header nested in another field does not stamp the message
+        let decoder = self.decoders.get(connection);
+        let Some(decoder) = decoder.filter(|decoder| decoder.has_header()) else {
+            return Ok(());
+        };
+        match decoder.header_stamp(data) {
+            Ok(Some(stamp)) => self.samples.samples.push(LatencySample {
+                topic: connection.topic.clone(),
+                time,
+                stamp,
+            }),
+            Ok(None) => {}
+            // One truncated message should not hide the latency of all others
+            Err(_) => {
+                *self
+                    .samples
+                    .malformed
+                    .entry(connection.topic.clone())
+                    .or_default() += 1
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Header stamps of all selected messages that have a header
+pub fn get_latency_samples(bag: &Bag, topics: &TopicSelection) -> Result<LatencySamples> {
+    let mut collector = LatencyCollector::default();
+    read_messages(bag, topics, |connection, time, data| {
+        collector.add(connection, time, data)
+    })?;
+    Ok(collector.samples)
+}
+
+/// Same as `get_latency_samples` for a bag that can only be read sequentially
+pub fn get_stream_latency_samples<R: Read>(
+    reader: R,
+    topics: &TopicSelection,
+) -> Result<LatencySamples> {
+    let mut collector = LatencyCollector::default();
+    read_stream_messages(reader, topics, |connection, time, data| {
+        collector.add(connection, time, data)
+    })?;
+    Ok(collector.samples)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramBin {
+    pub start: f64,
+    pub end: f64,
+    pub count: usize,
+}
+
+/// Latency distribution of one topic in seconds
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyStats {
+    pub topic: String,
+    pub message_count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    /// Messages stamped after their record time
+    pub future: usize,
+    /// Messages with a latency above the threshold
+    pub stale: usize,
+    pub histogram: Vec<HistogramBin>,
+}
+
+/// Per-topic latency statistics, `max_latency` in nanoseconds marks messages as stale
+pub fn latency_stats(
+    samples: &[LatencySample],
+    max_latency: u64,
+    bins: usize,
+) -> Vec<LatencyStats> {
+    let mut latencies = BTreeMap::<&str, Vec<i64>>::new();
+    for sample in samples {
+        latencies
+            .entry(&sample.topic)
+            .or_default()
+            .push(sample.latency());
+    }
+
+    let seconds = |nanos: i64| nanos as f64 / NANOS_PER_SEC as f64;
+    latencies
+        .into_iter()
+        .map(|(topic, mut latencies)| {
+            latencies.sort_unstable();
+            let percentile = |p: f64| {
+                let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+                seconds(latencies[rank.clamp(1, latencies.len()) - 1])
+            };
+            let (min, max) = (latencies[0], latencies[latencies.len() - 1]);
+
+            let bins = bins.max(1);
+            let width = ((max - min) as f64 / bins as f64).max(1.0);
+            let mut counts = vec![0; bins];
+            for latency in latencies.iter() {
+                let bin = ((latency - min) as f64 / width) as usize;
+                counts[bin.min(bins - 1)] += 1;
+            }
+            let histogram = counts
+                .into_iter()
+                .enumerate()
+                .map(|(i, count)| HistogramBin {
+                    start: seconds(min + (i as f64 * width) as i64),
+                    end: seconds(min + ((i + 1) as f64 * width) as i64),
+                    count,
+                })
+                .collect();
+
+            LatencyStats {
+                topic: topic.to_string(),
+                message_count: latencies.len(),
+                min: seconds(min),
+                max: seconds(max),
+                mean: latencies
+                    .iter()
+                    .map(|&latency| seconds(latency))
+                    .sum::<f64>()
+                    / latencies.len() as f64,
+                p50: percentile(50.0),
+                p90: percentile(90.0),
+                p99: percentile(99.0),
+                future: latencies.iter().filter(|&&latency| latency < 0).count(),
+                stale: latencies
+                    .iter()
+                    .filter(|&&latency| latency > max_latency as i64)
+                    .count(),
+                histogram,
+            }
+        })
+        .collect()
+}
+
+/// One line per message with record time, stamp and latency in seconds and a flag
+pub fn write_latency_csv<W: Write>(
+    writer: &mut W,
+    samples: &[LatencySample],
+    max_latency: u64,
+) -> Result<()> {
+    writeln!(writer, "topic,time,stamp,latency,flag")?;
+    let seconds = |nanos: u64| format!("{}.{:09}", nanos / NANOS_PER_SEC, nanos % NANOS_PER_SEC);
+    for sample in samples {
+        let latency = sample.latency();
+        let flag = if latency < 0 {
+            "future"
+        } else if latency > max_latency as i64 {
+            "stale"
+        } else {
+            ""
+        };
+        writeln!(
+            writer,
+            "{},{},{},{}{},{}",
+            sample.topic,
+            seconds(sample.time),
+            seconds(sample.stamp),
+            if latency < 0 { "-" } else { "" },
+            seconds(latency.unsigned_abs()),
+            flag
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,33 @@
+pub mod bag;
+pub mod cache;
+pub mod catalog;
+pub mod check;
+pub mod columnar;
+pub mod compress;
+pub mod convert;
+pub mod cursor;
+pub mod downsample;
+pub mod export;
+pub mod filter;
+pub mod indexing;
+pub mod latency;
+pub mod mcap;
+pub mod merge;
+pub mod message_decoder;
+pub mod message_parser;
+#[allow(dead_code)]
+pub mod message_parsing;
+pub mod numpy;
+pub mod record;
+pub mod redact;
+pub mod remap;
+pub mod restamp;
+pub mod salvage;
+pub mod selection;
+pub mod split;
+pub mod stats;
+#[cfg(test)]
+mod tests;
+pub mod time;
+pub mod trim;
+pub mod writer;
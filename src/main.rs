@@ -1,47 +1,832 @@
-mod cursor;
-mod indexing;
-mod message_parser;
-mod message_parsing;
-mod tests;
-
-use indexing::{get_message_count, get_messages, get_topics, read_bag};
-use std::env;
+mod cli;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use cli::{Cli, Command, ExportArgs, ExportFormat};
+use rebag::cache::{read_bag_summaries, IndexCache};
+use rebag::catalog::{Catalog, CatalogQuery};
+use rebag::check::check_bag;
+use rebag::columnar::{export_columnar, ColumnarFormat};
+use rebag::compress::recompress_bag;
+use rebag::convert::bag_to_mcap;
+use rebag::downsample::Downsampler;
+use rebag::export::{echo, export_csv, export_jsonl, ExportReport};
+use rebag::filter::{filter_messages, Filter, MessageFilter};
+use rebag::indexing::{
+    find_bags, get_connections, get_message_count, get_message_times, get_start_time,
+    get_stream_connections, get_stream_message_count, get_stream_message_times, get_stream_topics,
+    get_topics, read_bag, MessageSource,
+};
+use rebag::latency::{
+    get_latency_samples, get_stream_latency_samples, latency_stats, write_latency_csv,
+    LatencySamples,
+};
+use rebag::mcap::McapWriter;
+use rebag::merge::{merge, MergeInput};
+use rebag::numpy::{export_npy, export_npz};
+use rebag::redact::{redact_messages, RedactRule, Redactor};
+use rebag::remap::{parse_frame_rule, remap_messages, Remapper, TopicRule};
+use rebag::restamp::{restamp_messages, RestampOptions, Restamper};
+use rebag::salvage::salvage_bag;
+use rebag::selection::TopicSelection;
+use rebag::split::{part_path, split};
+use rebag::stats::{rate_stats, GapCriteria};
+use rebag::time::{format_time, NANOS_PER_SEC};
+use rebag::trim::trim;
+use rebag::writer::{BagWriter, Compression};
 use tabled::{
-    settings::{
-        themes::{Colorization, ColumnNames},
-        Color, Style,
-    },
-    Table,
+    settings::{themes::Colorization, Color, Style},
+    Table, Tabled,
 };
 
-fn main() {
-    let bag = read_bag(
-        env::current_dir()
-            .unwrap()
-            .as_path()
-            .join("bags/autocross_2023-08-18-17-00-45.bag")
-            .as_path(),
-    );
-
-    let topics = get_topics(&bag);
-    // println!("{}", Table::new(topics).with(Style::modern()));
-    // println!("{}", topics.get("/").unwrap());
-
-    let messages = get_messages(&bag, "/trajectory/optimized_track");
-    println!("{:?}", messages);
-    // let messages = messages
-    //     .iter()
-    //     .map(|msg| String::from_utf8(msg.to_vec()).unwrap());
-    // println!("{}", Table::new(messages));
-
-    // let message_count = get_message_count(&bag);
-    // let color_col1 = Color::BG_GREEN | Color::FG_BLACK;
-    // let color_col2 = Color::BG_MAGENTA | Color::FG_BLACK;
-    // println!(
-    //     "{}",
-    //     Table::new(message_count)
-    //         .with(ColumnNames::new(["Topic", "Message count"]))
-    //         .with(Style::psql())
-    //         .with(Colorization::columns([color_col1, color_col2]))
-    // );
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Topics { bag, topics, cache } => {
+            let selection = topics.selection()?;
+            let cache = cache.index_cache();
+            let mut topics = if is_stdin(&bag) {
+                get_stream_topics(io::stdin().lock())?
+            } else if bag.is_dir() {
+                let mut topics = BTreeMap::new();
+                for summary in read_bag_summaries(&bag, &cache)?.values() {
+                    topics.extend(summary.get_topics());
+                }
+                topics
+            } else if let IndexCache::Disabled = cache {
+                get_topics(&read_bag(&bag)?)?
+            } else {
+                cache.summary(&bag)?.get_topics()
+            };
+            topics.retain(|topic, _| selection.matches(topic));
+            println!(
+                "{}",
+                table(topics, ["Topic", "Message definition"]).with(Style::modern())
+            );
+        }
+        Command::Connections { bag, topics, cache } => {
+            let selection = topics.selection()?;
+            let cache = cache.index_cache();
+            let mut connections = if is_stdin(&bag) {
+                get_stream_connections(io::stdin().lock(), &selection)?
+            } else if bag.is_dir() {
+                read_bag_summaries(&bag, &cache)?
+                    .into_values()
+                    .flat_map(|summary| summary.connections)
+                    .filter(|conn| selection.matches(&conn.topic))
+                    .collect()
+            } else if let IndexCache::Disabled = cache {
+                get_connections(&read_bag(&bag)?, &selection)?
+            } else {
+                let summary = cache.summary(&bag)?;
+                summary
+                    .connections
+                    .into_iter()
+                    .filter(|conn| selection.matches(&conn.topic))
+                    .collect()
+            };
+            // Bags of one recording repeat the same connections with different ids
+            connections.sort_by(|a, b| {
+                (&a.topic, &a.caller_id, &a.md5sum).cmp(&(&b.topic, &b.caller_id, &b.md5sum))
+            });
+            connections.dedup_by(|a, b| {
+                (&a.topic, &a.tp, &a.md5sum, &a.caller_id, a.latching)
+                    == (&b.topic, &b.tp, &b.md5sum, &b.caller_id, b.latching)
+            });
+
+            let rows = connections.iter().map(|conn| {
+                (
+                    conn.topic.as_str(),
+                    conn.tp.as_str(),
+                    conn.md5sum.as_str(),
+                    conn.caller_id.as_deref().unwrap_or_default(),
+                    if conn.latching { "yes" } else { "no" },
+                )
+            });
+            println!(
+                "{}",
+                table(rows, ["Topic", "Type", "MD5 sum", "Caller id", "Latching",])
+                    .with(Style::psql())
+            );
+        }
+        Command::Count { bag, topics, cache } => {
+            let selection = topics.selection()?;
+            let cache = cache.index_cache();
+            let mut message_count = if is_stdin(&bag) {
+                get_stream_message_count(io::stdin().lock(), &selection)?
+            } else if bag.is_dir() {
+                let mut message_count = BTreeMap::new();
+                for summary in read_bag_summaries(&bag, &cache)?.values() {
+                    for (topic, count) in summary.get_message_count() {
+                        *message_count.entry(topic).or_default() += count;
+                    }
+                }
+                message_count
+            } else if let IndexCache::Disabled = cache {
+                get_message_count(&read_bag(&bag)?, &selection)?
+            } else {
+                cache.summary(&bag)?.get_message_count()
+            };
+            message_count.retain(|topic, _| selection.matches(topic));
+            let color_col1 = Color::BG_GREEN | Color::FG_BLACK;
+            let color_col2 = Color::BG_MAGENTA | Color::FG_BLACK;
+            println!(
+                "{}",
+                table(message_count, ["Topic", "Message count"])
+                    .with(Style::psql())
+                    .with(Colorization::columns([color_col1, color_col2]))
+            );
+        }
+        Command::Echo {
+            bag,
+            topics,
+            jsonl,
+            json,
+        } => {
+            let source = MessageSource::open(&bag)?;
+            let writer = BufWriter::new(io::stdout().lock());
+            if jsonl {
+                quiet_broken_pipe(export_jsonl(
+                    source,
+                    &topics.selection()?,
+                    json.options(),
+                    writer,
+                ))?;
+            } else {
+                quiet_broken_pipe(echo(source, &topics.selection()?, writer))?;
+            }
+        }
+        Command::Rates {
+            bag,
+            topics,
+            gap,
+            gap_factor,
+            json,
+        } => {
+            let selection = topics.selection()?;
+            let times = if is_stdin(&bag) {
+                get_stream_message_times(io::stdin().lock(), &selection)?
+            } else {
+                get_message_times(&read_bag(&bag)?, &selection)?
+            };
+            let criteria = GapCriteria {
+                max_period: gap,
+                median_factor: gap_factor.or(if gap.is_none() { Some(3.0) } else { None }),
+            };
+            let stats = rate_stats(&times, &criteria);
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                let number = |value: Option<f64>, scale: f64| {
+                    value
+                        .map(|value| format!("{:.3}", value * scale))
+                        .unwrap_or_default()
+                };
+                let rows = stats.iter().map(|stats| {
+                    (
+                        stats.topic.as_str(),
+                        stats.message_count,
+                        number(stats.mean_frequency, 1.0),
+                        format!(
+                            "{} - {}",
+                            number(stats.min_frequency, 1.0),
+                            number(stats.max_frequency, 1.0)
+                        ),
+                        number(stats.jitter, 1000.0),
+                        number(stats.longest_gap.as_ref().map(|gap| gap.duration), 1.0),
+                    )
+                });
+                println!(
+                    "{}",
+                    table(
+                        rows,
+                        [
+                            "Topic",
+                            "Messages",
+                            "Mean [Hz]",
+                            "Min - max [Hz]",
+                            "Jitter [ms]",
+                            "Longest gap [s]",
+                        ]
+                    )
+                    .with(Style::psql())
+                );
+
+                let gaps: Vec<_> = stats
+                    .iter()
+                    .flat_map(|stats| {
+                        stats.gaps.iter().map(|gap| {
+                            (
+                                stats.topic.as_str(),
+                                format_time(gap.start_time),
+                                format_time(gap.end_time),
+                                format!("{:.3}", gap.duration),
+                            )
+                        })
+                    })
+                    .collect();
+                if !gaps.is_empty() {
+                    println!(
+                        "{}",
+                        table(gaps, ["Topic", "Start", "End", "Duration [s]"]).with(Style::psql())
+                    );
+                }
+            }
+        }
+        Command::Latency {
+            bag,
+            topics,
+            max_latency,
+            bins,
+            csv,
+            json,
+        } => {
+            let selection = topics.selection()?;
+            let LatencySamples { samples, malformed } = if is_stdin(&bag) {
+                get_stream_latency_samples(io::stdin().lock(), &selection)?
+            } else {
+                get_latency_samples(&read_bag(&bag)?, &selection)?
+            };
+            for (topic, count) in malformed.iter() {
+                eprintln!(
+                    "Skipping {} messages on {} whose header cannot be read",
+                    count, topic
+                );
+            }
+            if let Some(csv) = csv {
+                write_latency_csv(
+                    &mut BufWriter::new(File::create(csv)?),
+                    &samples,
+                    max_latency,
+                )?;
+            }
+            let stats = latency_stats(&samples, max_latency, bins);
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                let millis = |seconds: f64| format!("{:.3}", seconds * 1000.0);
+                let rows = stats.iter().map(|stats| {
+                    (
+                        stats.topic.as_str(),
+                        stats.message_count,
+                        format!("{} / {}", millis(stats.min), millis(stats.max)),
+                        millis(stats.mean),
+                        format!(
+                            "{} / {} / {}",
+                            millis(stats.p50),
+                            millis(stats.p90),
+                            millis(stats.p99)
+                        ),
+                        format!("{} / {}", stats.future, stats.stale),
+                    )
+                });
+                println!(
+                    "{}",
+                    table(
+                        rows,
+                        [
+                            "Topic",
+                            "Messages",
+                            "Min / max [ms]",
+                            "Mean [ms]",
+                            "p50 / p90 / p99 [ms]",
+                            "Future / stale",
+                        ]
+                    )
+                    .with(Style::psql())
+                );
+
+                for stats in stats.iter() {
+                    let peak = stats
+                        .histogram
+                        .iter()
+                        .map(|bin| bin.count)
+                        .max()
+                        .unwrap_or(1);
+                    let rows = stats.histogram.iter().map(|bin| {
+                        (
+                            format!("{} .. {}", millis(bin.start), millis(bin.end)),
+                            bin.count,
+                            "#".repeat((bin.count * 40).div_ceil(peak)),
+                        )
+                    });
+                    println!(
+                        "{}\n{}",
+                        stats.topic,
+                        table(rows, ["Latency [ms]", "Messages", ""]).with(Style::psql())
+                    );
+                }
+            }
+        }
+        Command::Catalog {
+            dir,
+            topic,
+            min_count,
+            start,
+            end,
+            json,
+            cache,
+        } => {
+            let catalog = Catalog::build(&dir, &cache.index_cache())?;
+            for (path, error) in catalog.errors.iter() {
+                eprintln!("Skipping {}: {}", path.display(), error);
+            }
+            let entries = catalog.query(&CatalogQuery {
+                topic,
+                min_count,
+                start,
+                end,
+            });
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                let rows = entries.iter().map(|entry| {
+                    let time = |time: Option<u64>| time.map(format_time).unwrap_or_default();
+                    let duration = match (entry.start_time, entry.end_time) {
+                        (Some(start), Some(end)) => {
+                            format!("{:.1}", (end - start) as f64 / NANOS_PER_SEC as f64)
+                        }
+                        _ => String::new(),
+                    };
+                    (
+                        entry.path.display().to_string(),
+                        time(entry.start_time),
+                        time(entry.end_time),
+                        duration,
+                        entry.message_count,
+                        format!("{:.1}", entry.size as f64 / (1024.0 * 1024.0)),
+                    )
+                });
+                println!(
+                    "{}",
+                    table(
+                        rows,
+                        [
+                            "Bag",
+                            "Start",
+                            "End",
+                            "Duration [s]",
+                            "Messages",
+                            "Size [MiB]",
+                        ]
+                    )
+                    .with(Style::psql())
+                );
+            }
+        }
+        Command::Filter {
+            input,
+            output,
+            expressions,
+            drop_other,
+            every,
+            max_rate,
+            writer,
+        } => {
+            let filters = expressions
+                .iter()
+                .map(|expression| Filter::parse(expression))
+                .collect::<Result<Vec<_>>>()?;
+            let downsampler = Downsampler::new(every.into_iter().chain(max_rate).collect());
+            if filters.is_empty() && downsampler.is_empty() {
+                bail!("Give filter expressions, --every or --max-rate");
+            }
+            let source = MessageSource::open(&input)?;
+            let mut bag_writer = BagWriter::create_with_options(&output, writer.options())?;
+            let mut message_filter =
+                MessageFilter::new(filters, !drop_other).with_downsampler(downsampler);
+            let report = filter_messages(source, &mut bag_writer, &mut message_filter)?;
+            bag_writer.finish()?;
+            for (topic, count) in report.undecodable.iter() {
+                eprintln!(
+                    "Could not decode {} messages on {}, their fields did not match",
+                    count, topic
+                );
+            }
+            println!("Kept {} messages, dropped {}", report.kept, report.dropped);
+        }
+        Command::Compress {
+            bags,
+            compression,
+            output,
+        } => recompress_bags(&bags, output.as_deref(), compression.compression())?,
+        Command::Decompress { bags, output } => {
+            recompress_bags(&bags, output.as_deref(), Compression::None)?
+        }
+        Command::Convert {
+            input,
+            output,
+            mcap,
+        } => {
+            if output
+                .extension()
+                .is_none_or(|extension| extension != "mcap")
+            {
+                bail!("Can only convert to MCAP, give an output like out.mcap");
+            }
+            let source = MessageSource::open(&input)?;
+            let mut mcap_writer = McapWriter::create(&output, mcap.options())?;
+            let report = bag_to_mcap(source, &mut mcap_writer)?;
+            mcap_writer.finish()?;
+            println!(
+                "Wrote {} messages on {} channels with {} schemas",
+                report.messages, report.channels, report.schemas
+            );
+        }
+        Command::Export { format } => {
+            let (export, report) = match format {
+                ExportFormat::Csv { export, arrays } => {
+                    let source = MessageSource::open(&export.input)?;
+                    let writer = export_writer(export.output.as_deref())?;
+                    let report = export_csv(source, &export.topic, arrays, writer)?;
+                    (export, report)
+                }
+                ExportFormat::Jsonl { export, json } => {
+                    let source = MessageSource::open(&export.input)?;
+                    let writer = export_writer(export.output.as_deref())?;
+                    let topics = TopicSelection::parse(&[&export.topic])?;
+                    let result = export_jsonl(source, &topics, json.options(), writer);
+                    let Some(report) = quiet_broken_pipe(result)? else {
+                        return Ok(());
+                    };
+                    if report.messages == 0 {
+                        bail!("No messages on {}", export.topic);
+                    }
+                    (export, report)
+                }
+                ExportFormat::Numpy { export, fields } => {
+                    let source = MessageSource::open(&export.input)?;
+                    let report = match &export.output {
+                        Some(path)
+                            if path.extension().is_some_and(|extension| extension == "npz") =>
+                        {
+                            let writer = BufWriter::new(File::create(path)?);
+                            export_npz(source, &export.topic, &fields, writer)?
+                        }
+                        output => {
+                            let writer = export_writer(output.as_deref())?;
+                            export_npy(source, &export.topic, &fields, writer)?
+                        }
+                    };
+                    (export, report)
+                }
+                ExportFormat::Parquet { export } => {
+                    export_columns(export, ColumnarFormat::Parquet)?
+                }
+                ExportFormat::Arrow { export } => export_columns(export, ColumnarFormat::ArrowIpc)?,
+            };
+            if let Some(output) = &export.output {
+                println!(
+                    "Wrote {} rows of {} messages to {}",
+                    report.rows,
+                    report.messages,
+                    output.display()
+                );
+            }
+        }
+        Command::Merge {
+            inputs,
+            output,
+            prefix,
+            writer,
+        } => {
+            if !prefix.is_empty() && prefix.len() != inputs.len() {
+                bail!(
+                    "Got {} prefixes for {} inputs, give one --prefix per input",
+                    prefix.len(),
+                    inputs.len()
+                );
+            }
+            let mut merge_inputs = vec![];
+            for (i, input) in inputs.iter().enumerate() {
+                let paths = if input.is_dir() {
+                    find_bags(input, false)?
+                } else {
+                    vec![input.clone()]
+                };
+                for path in paths {
+                    merge_inputs.push(MergeInput {
+                        bag: read_bag(&path)?,
+                        prefix: prefix.get(i).cloned(),
+                    });
+                }
+            }
+            let mut bag_writer = BagWriter::create_with_options(&output, writer.options())?;
+            let report = merge(&merge_inputs, &mut bag_writer)?;
+            bag_writer.finish()?;
+            println!(
+                "Merged {} bags into {} messages on {} connections",
+                merge_inputs.len(),
+                report.messages,
+                report.connections
+            );
+        }
+        Command::Remap {
+            input,
+            output,
+            rules,
+            frame,
+            writer,
+        } => {
+            let rules = rules
+                .iter()
+                .map(|rule| TopicRule::parse(rule))
+                .collect::<Result<Vec<_>>>()?;
+            let frames = frame
+                .iter()
+                .map(|rule| parse_frame_rule(rule))
+                .collect::<Result<BTreeMap<_, _>>>()?;
+            let source = MessageSource::open(&input)?;
+            let mut bag_writer = BagWriter::create_with_options(&output, writer.options())?;
+            let report =
+                remap_messages(source, &mut bag_writer, &mut Remapper::new(rules, frames))?;
+            bag_writer.finish()?;
+            for (from, to) in report.topics.iter() {
+                println!("{} -> {}", from, to);
+            }
+            println!(
+                "Copied {} messages, renamed frame ids in {}",
+                report.messages, report.frames
+            );
+        }
+        Command::Redact {
+            input,
+            output,
+            rules,
+            rules_file,
+            writer,
+        } => {
+            let mut redact_rules = rules
+                .iter()
+                .map(|rule| RedactRule::parse(rule))
+                .collect::<Result<Vec<_>>>()?;
+            if let Some(path) = rules_file {
+                redact_rules.extend(RedactRule::parse_lines(&fs::read_to_string(path)?)?);
+            }
+            if redact_rules.is_empty() {
+                bail!("Give redaction rules or --rules-file");
+            }
+            let source = MessageSource::open(&input)?;
+            let mut bag_writer = BagWriter::create_with_options(&output, writer.options())?;
+            let report = redact_messages(source, &mut bag_writer, Redactor::new(redact_rules))?;
+            bag_writer.finish()?;
+            // A rule with a typo in a topic or field would silently leave the data in
+            if !report.unmatched.is_empty() {
+                fs::remove_file(&output)?;
+                bail!(
+                    "No topic or field matched {}, removed {}",
+                    report
+                        .unmatched
+                        .iter()
+                        .map(|rule| format!("'{}'", rule))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    output.display()
+                );
+            }
+
+            let rows = report
+                .dropped
+                .iter()
+                .map(|(topic, count)| (topic.as_str(), "(dropped)", *count))
+                .chain(
+                    report
+                        .changed
+                        .iter()
+                        .map(|((topic, field), count)| (topic.as_str(), field.as_str(), *count)),
+                );
+            println!(
+                "{}",
+                table(rows, ["Topic", "Field", "Messages"]).with(Style::psql())
+            );
+            println!("Wrote {} messages", report.messages);
+        }
+        Command::Restamp {
+            input,
+            output,
+            offset,
+            start,
+            shift_headers,
+            header_time,
+            writer,
+        } => {
+            let source = MessageSource::open(&input)?;
+            let offset = match (offset, start, &source) {
+                (Some(offset), _, _) => offset,
+                (None, Some(start), MessageSource::Bag(bag)) => match get_start_time(bag)? {
+                    Some(start_time) => start as i64 - start_time as i64,
+                    None => 0,
+                },
+                (None, Some(_), MessageSource::Stream(_)) => {
+                    bail!("--start needs the index of the bag and cannot read from stdin")
+                }
+                (None, None, _) => 0,
+            };
+            let mut restamper = Restamper::new(RestampOptions {
+                offset,
+                shift_headers,
+                use_header_time: header_time,
+            });
+            let mut bag_writer = BagWriter::create_with_options(&output, writer.options())?;
+            let report = restamp_messages(source, &mut bag_writer, &mut restamper)?;
+            bag_writer.finish()?;
+            println!(
+                "Copied {} messages shifted by {:.9} s, {} with header time",
+                report.messages,
+                offset as f64 / NANOS_PER_SEC as f64,
+                report.header_times
+            );
+        }
+        Command::Trim {
+            input,
+            output,
+            start,
+            end,
+            writer,
+        } => {
+            let bag = read_bag(&input)?;
+            let mut bag_writer = BagWriter::create_with_options(&output, writer.options())?;
+            let report = trim(&bag, start, end, &mut bag_writer)?;
+            bag_writer.finish()?;
+            println!(
+                "Kept {} messages from {} to {} and {} latched, skipped {} chunks",
+                report.messages,
+                format_time(report.start_time),
+                format_time(report.end_time),
+                report.latched,
+                report.skipped_chunks
+            );
+        }
+        Command::Split {
+            input,
+            output,
+            split: split_args,
+            writer,
+        } => {
+            let output = match output {
+                Some(output) => output,
+                None if is_stdin(&input) => bail!("--output is required when reading from stdin"),
+                None => input.clone(),
+            };
+            let source = MessageSource::open(&input)?;
+            let options = writer.options();
+            let parts = split(source, &split_args.criterion(), |index| {
+                Ok(BagWriter::create_with_options(
+                    &part_path(&output, index),
+                    options.clone(),
+                )?)
+            })?;
+            for (index, part) in parts.iter().enumerate() {
+                println!(
+                    "{}: {} messages from {} to {}, {} latched",
+                    part_path(&output, index).display(),
+                    part.messages,
+                    format_time(part.start_time),
+                    format_time(part.end_time),
+                    part.latched
+                );
+            }
+        }
+        Command::Check { bags } => {
+            let mut paths = vec![];
+            for path in bags {
+                if path.is_dir() {
+                    // MCAP files have none of the bag structure to check
+                    let bags = find_bags(&path, true)?.into_iter();
+                    paths
+                        .extend(bags.filter(|bag| bag.extension().is_some_and(|ext| ext == "bag")));
+                } else {
+                    paths.push(path);
+                }
+            }
+
+            let mut failed = 0;
+            for path in paths.iter() {
+                let report = check_bag(path)?;
+                if report.is_ok() {
+                    println!(
+                        "{}: OK, {} chunks with {} messages on {} connections",
+                        path.display(),
+                        report.chunks,
+                        report.messages,
+                        report.connections
+                    );
+                    continue;
+                }
+                failed += 1;
+                println!("{}: {} problems", path.display(), report.problems.len());
+                let rows = report
+                    .problems
+                    .iter()
+                    .map(|problem| (problem.offset, problem.message.as_str()));
+                println!("{}", table(rows, ["Offset", "Problem"]).with(Style::psql()));
+            }
+            if failed > 0 {
+                bail!("{} of {} bags failed the check", failed, paths.len());
+            }
+        }
+        Command::Salvage { bag, output } => {
+            let report = salvage_bag(&bag, output.as_deref())?;
+            if !report.skipped.is_empty() {
+                let skipped = report.skipped.iter().map(|skipped| {
+                    (
+                        skipped.offset,
+                        skipped
+                            .chunk_offset
+                            .map(|offset| offset.to_string())
+                            .unwrap_or_default(),
+                        skipped.reason.as_str(),
+                    )
+                });
+                println!(
+                    "{}",
+                    table(skipped, ["Offset", "Chunk offset", "Reason"]).with(Style::psql())
+                );
+            }
+            println!(
+                "Recovered {} messages on {} connections, skipped {} records",
+                report.messages,
+                report.connections,
+                report.skipped.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn recompress_bags(
+    bags: &[PathBuf],
+    output: Option<&Path>,
+    compression: Compression,
+) -> Result<()> {
+    if output.is_some() && bags.len() > 1 {
+        bail!("--output can only be used with a single bag");
+    }
+    for bag in bags {
+        recompress_bag(bag, output, compression)?;
+        println!(
+            "{}: {} chunks",
+            output.unwrap_or(bag).display(),
+            compression
+        );
+    }
+    Ok(())
+}
+
+/// Export `export.topic` as Parquet or Arrow IPC, handing the arguments back for the summary
+fn export_columns(
+    export: ExportArgs,
+    format: ColumnarFormat,
+) -> Result<(ExportArgs, ExportReport)> {
+    let source = MessageSource::open(&export.input)?;
+    let writer = export_writer(export.output.as_deref())?;
+    let report = export_columnar(source, &export.topic, format, writer)?;
+    Ok((export, report))
+}
+
+/// Stop quietly once the reader of stdout is gone, like `head` after enough lines
+fn quiet_broken_pipe(result: Result<ExportReport>) -> Result<Option<ExportReport>> {
+    match result {
+        Ok(report) => Ok(Some(report)),
+        Err(error)
+            if error
+                .downcast_ref::<io::Error>()
+                .is_some_and(|error| error.kind() == io::ErrorKind::BrokenPipe) =>
+        {
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// The file to export to, stdout if none is given
+fn export_writer(output: Option<&Path>) -> Result<Box<dyn Write + Send>> {
+    Ok(match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    })
+}
+
+/// A table with `columns` as header instead of the type names of the row fields
+fn table<T: Tabled, const N: usize>(
+    rows: impl IntoIterator<Item = T>,
+    columns: [&str; N],
+) -> Table {
+    let mut builder = Table::builder(rows);
+    builder.remove_record(0);
+    builder.insert_record(0, columns);
+    builder.build()
+}
+
+/// `-` reads the bag sequentially from stdin
+fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
 }
@@ -0,0 +1,921 @@
+//! Minimal MCAP support, see https://mcap.dev/spec
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::bail;
+use byteorder::{ByteOrder, WriteBytesExt, LE};
+
+use crate::bag::Message;
+use crate::cursor::Cursor;
+use crate::record::{Connection, MessageEncoding, RecordError};
+
+pub const MCAP_MAGIC: &[u8] = b"\x89MCAP0\r\n";
+
+pub const OP_HEADER: u8 = 0x01;
+pub const OP_FOOTER: u8 = 0x02;
+pub const OP_SCHEMA: u8 = 0x03;
+pub const OP_CHANNEL: u8 = 0x04;
+pub const OP_MESSAGE: u8 = 0x05;
+pub const OP_CHUNK: u8 = 0x06;
+pub const OP_MESSAGE_INDEX: u8 = 0x07;
+pub const OP_CHUNK_INDEX: u8 = 0x08;
+pub const OP_STATISTICS: u8 = 0x0b;
+pub const OP_SUMMARY_OFFSET: u8 = 0x0e;
+pub const OP_DATA_END: u8 = 0x0f;
+
+/// Same chunk size as the bag writer
+const CHUNK_THRESHOLD: usize = 768 * 1024;
+
+/// Chunks larger than this grow their buffer while decompressing
+const MAX_PREALLOCATION: u64 = 64 * 1024 * 1024;
+
+/// Compression of the chunks, named as in the `compression` field of chunk records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum McapCompression {
+    None,
+    Lz4,
+    #[default]
+    Zstd,
+}
+
+impl McapCompression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            McapCompression::None => "",
+            McapCompression::Lz4 => "lz4",
+            McapCompression::Zstd => "zstd",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "" => Some(McapCompression::None),
+            "lz4" => Some(McapCompression::Lz4),
+            "zstd" => Some(McapCompression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Decompress a chunk that should have `size` bytes. The size comes from the file, so
+    /// memory is only reserved for it up to a limit and never more than `size` is read.
+    fn decompress(&self, data: &[u8], size: u64) -> Result<Vec<u8>, RecordError> {
+        let mut out = Vec::with_capacity(size.min(MAX_PREALLOCATION) as usize);
+        let result = match self {
+            McapCompression::None => {
+                out.extend_from_slice(data);
+                Ok(0)
+            }
+            McapCompression::Lz4 => lz4::Decoder::new(data)
+                .and_then(|decoder| decoder.take(size.saturating_add(1)).read_to_end(&mut out)),
+            McapCompression::Zstd => zstd::Decoder::new(data)
+                .and_then(|decoder| decoder.take(size.saturating_add(1)).read_to_end(&mut out)),
+        };
+        result.map_err(|e| RecordError::Decompression(e.to_string()))?;
+        if out.len() as u64 != size {
+            return Err(RecordError::Decompression(format!(
+                "expected {} bytes, got {}",
+                size,
+                out.len()
+            )));
+        }
+        Ok(out)
+    }
+
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            McapCompression::None => Ok(data.to_vec()),
+            McapCompression::Lz4 => {
+                let mut encoder = lz4::EncoderBuilder::new().build(vec![])?;
+                encoder.write_all(data)?;
+                let (compressed, result) = encoder.finish();
+                result.map(|_| compressed)
+            }
+            McapCompression::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+}
+
+impl fmt::Display for McapCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            McapCompression::None => write!(f, "none"),
+            compression => write!(f, "{}", compression.name()),
+        }
+    }
+}
+
+impl FromStr for McapCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "none" => McapCompression::None,
+            "lz4" => McapCompression::Lz4,
+            "zstd" => McapCompression::Zstd,
+            _ => bail!("Unknown compression '{}', expected none, lz4 or zstd", s),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct McapOptions {
+    pub compression: McapCompression,
+    /// A chunk is written once its uncompressed size reaches this many bytes
+    pub chunk_size: usize,
+    /// Profile of the header record, e.g. `ros1`
+    pub profile: String,
+}
+
+impl Default for McapOptions {
+    fn default() -> Self {
+        Self {
+            compression: McapCompression::default(),
+            chunk_size: CHUNK_THRESHOLD,
+            profile: "ros1".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub id: u16,
+    /// Message type, e.g. `sensor_msgs/Imu`
+    pub name: String,
+    /// e.g. `ros1msg` for ROS1 message definitions
+    pub encoding: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub id: u16,
+    pub schema_id: u16,
+    pub topic: String,
+    /// e.g. `ros1` for ROS1 serialized messages
+    pub message_encoding: String,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Where a chunk is and what it holds, from the summary section
+#[derive(Debug, Clone)]
+pub struct ChunkIndex {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub chunk_pos: u64,
+    /// Length of the whole chunk record
+    pub chunk_len: u64,
+    /// Position of the message index record of every channel in the chunk
+    pub message_index_offsets: BTreeMap<u16, u64>,
+    pub message_index_len: u64,
+    pub compression: McapCompression,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+/// Writes an indexed MCAP file with chunks, message indexes and a summary section
+pub struct McapWriter<W: Write> {
+    writer: W,
+    options: McapOptions,
+    pos: u64,
+    /// CRC of everything before the data end record
+    data_crc: crc32fast::Hasher,
+    schemas: Vec<Schema>,
+    channels: Vec<Channel>,
+    // Schema and channel records are written into the first chunk that uses them
+    schema_written: Vec<bool>,
+    channel_written: Vec<bool>,
+    chunk: Vec<u8>,
+    /// Log time and offset in the uncompressed chunk of every message per channel
+    chunk_index: BTreeMap<u16, Vec<(u64, u64)>>,
+    chunk_start_time: u64,
+    chunk_end_time: u64,
+    chunk_indexes: Vec<ChunkIndex>,
+    message_counts: BTreeMap<u16, u64>,
+    start_time: u64,
+    end_time: u64,
+}
+
+impl McapWriter<BufWriter<File>> {
+    pub fn create(path: &Path, options: McapOptions) -> io::Result<Self> {
+        McapWriter::new(BufWriter::new(File::create(path)?), options)
+    }
+}
+
+impl<W: Write> McapWriter<W> {
+    pub fn new(writer: W, options: McapOptions) -> io::Result<Self> {
+        let mut mcap = Self {
+            writer,
+            options,
+            pos: 0,
+            data_crc: crc32fast::Hasher::new(),
+            schemas: vec![],
+            channels: vec![],
+            schema_written: vec![],
+            channel_written: vec![],
+            chunk: vec![],
+            chunk_index: BTreeMap::new(),
+            chunk_start_time: u64::MAX,
+            chunk_end_time: 0,
+            chunk_indexes: vec![],
+            message_counts: BTreeMap::new(),
+            start_time: u64::MAX,
+            end_time: 0,
+        };
+        mcap.emit(MCAP_MAGIC)?;
+        let mut header = vec![];
+        put_str(&mut header, &mcap.options.profile);
+        put_str(
+            &mut header,
+            concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")),
+        );
+        mcap.emit(&record(OP_HEADER, &header))?;
+        Ok(mcap)
+    }
+
+    /// Register a schema and return its id, ids start at 1 as 0 means no schema
+    pub fn add_schema(&mut self, name: &str, encoding: &str, data: &[u8]) -> u16 {
+        let id = self.schemas.len() as u16 + 1;
+        self.schemas.push(Schema {
+            id,
+            name: name.to_string(),
+            encoding: encoding.to_string(),
+            data: data.to_vec(),
+        });
+        self.schema_written.push(false);
+        id
+    }
+
+    /// Register a channel and return its id
+    pub fn add_channel(
+        &mut self,
+        schema_id: u16,
+        topic: &str,
+        message_encoding: &str,
+        metadata: BTreeMap<String, String>,
+    ) -> u16 {
+        let id = self.channels.len() as u16;
+        self.channels.push(Channel {
+            id,
+            schema_id,
+            topic: topic.to_string(),
+            message_encoding: message_encoding.to_string(),
+            metadata,
+        });
+        self.channel_written.push(false);
+        id
+    }
+
+    pub fn write_message(
+        &mut self,
+        channel_id: u16,
+        sequence: u32,
+        log_time: u64,
+        publish_time: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let channel = self.channels.get(channel_id as usize).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown channel id {}", channel_id),
+            )
+        })?;
+
+        if !self.channel_written[channel_id as usize] {
+            if let Some(i) = (channel.schema_id as usize).checked_sub(1) {
+                if !self.schema_written[i] {
+                    self.chunk
+                        .extend_from_slice(&record(OP_SCHEMA, &encode_schema(&self.schemas[i])));
+                    self.schema_written[i] = true;
+                }
+            }
+            self.chunk
+                .extend_from_slice(&record(OP_CHANNEL, &encode_channel(channel)));
+            self.channel_written[channel_id as usize] = true;
+        }
+
+        let offset = self.chunk.len() as u64;
+        let mut message = Vec::with_capacity(22 + data.len());
+        message.write_u16::<LE>(channel_id)?;
+        message.write_u32::<LE>(sequence)?;
+        message.write_u64::<LE>(log_time)?;
+        message.write_u64::<LE>(publish_time)?;
+        message.extend_from_slice(data);
+        self.chunk.extend_from_slice(&record(OP_MESSAGE, &message));
+
+        self.chunk_index
+            .entry(channel_id)
+            .or_default()
+            .push((log_time, offset));
+        self.chunk_start_time = self.chunk_start_time.min(log_time);
+        self.chunk_end_time = self.chunk_end_time.max(log_time);
+        *self.message_counts.entry(channel_id).or_default() += 1;
+
+        if self.chunk.len() >= self.options.chunk_size {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.data_crc.update(bytes);
+        self.pos += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        if self.chunk_index.is_empty() {
+            return Ok(());
+        }
+
+        let compression = self.options.compression;
+        let compressed = compression.compress(&self.chunk)?;
+        let mut chunk = Vec::with_capacity(compressed.len() + 48);
+        chunk.write_u64::<LE>(self.chunk_start_time)?;
+        chunk.write_u64::<LE>(self.chunk_end_time)?;
+        chunk.write_u64::<LE>(self.chunk.len() as u64)?;
+        chunk.write_u32::<LE>(crc32fast::hash(&self.chunk))?;
+        put_str(&mut chunk, compression.name());
+        chunk.write_u64::<LE>(compressed.len() as u64)?;
+        chunk.extend_from_slice(&compressed);
+        let chunk = record(OP_CHUNK, &chunk);
+        let chunk_pos = self.pos;
+        self.emit(&chunk)?;
+
+        let message_index_start = self.pos;
+        let mut message_index_offsets = BTreeMap::new();
+        for (channel_id, entries) in std::mem::take(&mut self.chunk_index) {
+            let mut index = Vec::with_capacity(6 + entries.len() * 16);
+            index.write_u16::<LE>(channel_id)?;
+            index.write_u32::<LE>(entries.len() as u32 * 16)?;
+            for (log_time, offset) in entries {
+                index.write_u64::<LE>(log_time)?;
+                index.write_u64::<LE>(offset)?;
+            }
+            message_index_offsets.insert(channel_id, self.pos);
+            self.emit(&record(OP_MESSAGE_INDEX, &index))?;
+        }
+
+        self.chunk_indexes.push(ChunkIndex {
+            start_time: self.chunk_start_time,
+            end_time: self.chunk_end_time,
+            chunk_pos,
+            chunk_len: chunk.len() as u64,
+            message_index_offsets,
+            message_index_len: self.pos - message_index_start,
+            compression,
+            compressed_size: compressed.len() as u64,
+            uncompressed_size: self.chunk.len() as u64,
+        });
+        self.start_time = self.start_time.min(self.chunk_start_time);
+        self.end_time = self.end_time.max(self.chunk_end_time);
+        self.chunk.clear();
+        self.chunk_start_time = u64::MAX;
+        self.chunk_end_time = 0;
+        Ok(())
+    }
+
+    /// Write the summary section and footer and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_chunk()?;
+        let mut data_end = vec![];
+        data_end.write_u32::<LE>(self.data_crc.clone().finalize())?;
+        self.emit(&record(OP_DATA_END, &data_end))?;
+
+        // Offsets in the summary are absolute, the summary is written in one piece for its CRC
+        let summary_start = self.pos;
+        let mut summary = vec![];
+        let mut groups = vec![];
+        let mut group = |summary: &mut Vec<u8>, op: u8, records: Vec<Vec<u8>>| {
+            let start = summary.len();
+            for content in records {
+                summary.extend_from_slice(&record(op, &content));
+            }
+            if summary.len() > start {
+                groups.push((
+                    op,
+                    summary_start + start as u64,
+                    (summary.len() - start) as u64,
+                ));
+            }
+        };
+        group(
+            &mut summary,
+            OP_SCHEMA,
+            self.schemas.iter().map(encode_schema).collect(),
+        );
+        group(
+            &mut summary,
+            OP_CHANNEL,
+            self.channels.iter().map(encode_channel).collect(),
+        );
+        group(&mut summary, OP_STATISTICS, vec![self.encode_statistics()?]);
+        let chunk_indexes = self
+            .chunk_indexes
+            .iter()
+            .map(encode_chunk_index)
+            .collect::<io::Result<_>>()?;
+        group(&mut summary, OP_CHUNK_INDEX, chunk_indexes);
+
+        let summary_offset_start = summary_start + summary.len() as u64;
+        for (op, start, len) in groups {
+            let mut offset = vec![op];
+            offset.write_u64::<LE>(start)?;
+            offset.write_u64::<LE>(len)?;
+            summary.extend_from_slice(&record(OP_SUMMARY_OFFSET, &offset));
+        }
+
+        // The summary CRC covers the footer up to the CRC itself
+        summary.push(OP_FOOTER);
+        summary.write_u64::<LE>(20)?;
+        summary.write_u64::<LE>(summary_start)?;
+        summary.write_u64::<LE>(summary_offset_start)?;
+        let crc = crc32fast::hash(&summary);
+        summary.write_u32::<LE>(crc)?;
+        summary.extend_from_slice(MCAP_MAGIC);
+
+        self.writer.write_all(&summary)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn encode_statistics(&self) -> io::Result<Vec<u8>> {
+        let message_count: u64 = self.message_counts.values().sum();
+        let mut statistics = vec![];
+        statistics.write_u64::<LE>(message_count)?;
+        statistics.write_u16::<LE>(self.schemas.len() as u16)?;
+        statistics.write_u32::<LE>(self.channels.len() as u32)?;
+        // Attachments and metadata records
+        statistics.write_u32::<LE>(0)?;
+        statistics.write_u32::<LE>(0)?;
+        statistics.write_u32::<LE>(self.chunk_indexes.len() as u32)?;
+        let (start_time, end_time) = if message_count == 0 {
+            (0, 0)
+        } else {
+            (self.start_time, self.end_time)
+        };
+        statistics.write_u64::<LE>(start_time)?;
+        statistics.write_u64::<LE>(end_time)?;
+        statistics.write_u32::<LE>(self.message_counts.len() as u32 * 10)?;
+        for (channel_id, count) in self.message_counts.iter() {
+            statistics.write_u16::<LE>(*channel_id)?;
+            statistics.write_u64::<LE>(*count)?;
+        }
+        Ok(statistics)
+    }
+}
+
+/// Opcode, length and content of a record
+fn record(op: u8, content: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(9 + content.len());
+    record.push(op);
+    record.extend_from_slice(&(content.len() as u64).to_le_bytes());
+    record.extend_from_slice(content);
+    record
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn encode_schema(schema: &Schema) -> Vec<u8> {
+    let mut content = schema.id.to_le_bytes().to_vec();
+    put_str(&mut content, &schema.name);
+    put_str(&mut content, &schema.encoding);
+    content.extend_from_slice(&(schema.data.len() as u32).to_le_bytes());
+    content.extend_from_slice(&schema.data);
+    content
+}
+
+fn encode_channel(channel: &Channel) -> Vec<u8> {
+    let mut content = channel.id.to_le_bytes().to_vec();
+    content.extend_from_slice(&channel.schema_id.to_le_bytes());
+    put_str(&mut content, &channel.topic);
+    put_str(&mut content, &channel.message_encoding);
+    let mut metadata = vec![];
+    for (key, value) in channel.metadata.iter() {
+        put_str(&mut metadata, key);
+        put_str(&mut metadata, value);
+    }
+    content.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+    content.extend_from_slice(&metadata);
+    content
+}
+
+fn encode_chunk_index(index: &ChunkIndex) -> io::Result<Vec<u8>> {
+    let mut content = vec![];
+    content.write_u64::<LE>(index.start_time)?;
+    content.write_u64::<LE>(index.end_time)?;
+    content.write_u64::<LE>(index.chunk_pos)?;
+    content.write_u64::<LE>(index.chunk_len)?;
+    content.write_u32::<LE>(index.message_index_offsets.len() as u32 * 10)?;
+    for (channel_id, offset) in index.message_index_offsets.iter() {
+        content.write_u16::<LE>(*channel_id)?;
+        content.write_u64::<LE>(*offset)?;
+    }
+    content.write_u64::<LE>(index.message_index_len)?;
+    put_str(&mut content, index.compression.name());
+    content.write_u64::<LE>(index.compressed_size)?;
+    content.write_u64::<LE>(index.uncompressed_size)?;
+    Ok(content)
+}
+
+/// Schemas, channels and chunk indexes of an MCAP file, read from its summary section.
+/// Files without one, e.g. from an interrupted recording, are scanned for schemas and channels.
+#[derive(Debug)]
+pub struct McapIndex {
+    pub schemas: BTreeMap<u16, Schema>,
+    pub channels: BTreeMap<u16, Channel>,
+    /// Empty for files without a summary
+    pub chunk_indexes: Vec<ChunkIndex>,
+    /// Position of the first record after the header
+    data_start: u64,
+}
+
+impl McapIndex {
+    pub fn parse(data: &[u8]) -> Result<Self, RecordError> {
+        if !data.starts_with(MCAP_MAGIC) {
+            return Err(RecordError::InvalidField("magic"));
+        }
+        let (op, _, data_start) = read_mcap_record(data, MCAP_MAGIC.len() as u64)?;
+        if op != OP_HEADER {
+            return Err(RecordError::UnknownOp(op));
+        }
+        let mut index = Self {
+            schemas: BTreeMap::new(),
+            channels: BTreeMap::new(),
+            chunk_indexes: vec![],
+            data_start,
+        };
+
+        if let Some(summary) = summary_section(data)? {
+            let mut pos = 0;
+            while pos < summary.len() as u64 {
+                let (op, content, end) = read_mcap_record(summary, pos)?;
+                index.add_record(op, content)?;
+                if op == OP_CHUNK_INDEX {
+                    index.chunk_indexes.push(parse_chunk_index(content)?);
+                }
+                pos = end;
+            }
+        }
+        // Channels do not have to be repeated in the summary
+        if index.channels.is_empty() {
+            let mut records = McapRecords::new(data, data_start);
+            while let Some((op, content)) = records.next_record()? {
+                index.add_record(op, &content)?;
+            }
+        }
+        Ok(index)
+    }
+
+    fn add_record(&mut self, op: u8, content: &[u8]) -> Result<(), RecordError> {
+        match op {
+            OP_SCHEMA => {
+                let schema = parse_schema(content)?;
+                self.schemas.insert(schema.id, schema);
+            }
+            OP_CHANNEL => {
+                let channel = parse_channel(content)?;
+                self.channels.insert(channel.id, channel);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Channels as bag connections, with the schema name as message type and the channel id as id
+    pub fn connections(&self) -> Vec<Connection> {
+        self.channels
+            .values()
+            .map(|channel| {
+                let schema = self.schemas.get(&channel.schema_id);
+                let schema_encoding = schema.map_or("", |schema| schema.encoding.as_str());
+                let encoding = match (channel.message_encoding.as_str(), schema_encoding) {
+                    ("ros1", "ros1msg") => MessageEncoding::Ros1,
+                    ("cdr", "ros2msg") => MessageEncoding::Cdr,
+                    (message_encoding, "") => MessageEncoding::Other(message_encoding.to_string()),
+                    (message_encoding, schema_encoding) => MessageEncoding::Other(format!(
+                        "{} ({})",
+                        message_encoding, schema_encoding
+                    )),
+                };
+                let metadata = |key: &str| channel.metadata.get(key).map(String::as_str);
+                // ROS2 has no latching, transient local durability keeps messages the same way
+                let latching = metadata("latching") == Some("1")
+                    || metadata("offered_qos_profiles").is_some_and(|qos| {
+                        qos.contains("durability: 1") || qos.contains("durability: transient_local")
+                    });
+                Connection {
+                    id: channel.id as u32,
+                    topic: channel.topic.clone(),
+                    tp: schema.map_or(String::new(), |schema| schema.name.clone()),
+                    md5sum: metadata("md5sum").unwrap_or("*").to_string(),
+                    message_definition: schema.map_or(String::new(), |schema| {
+                        String::from_utf8_lossy(&schema.data).into_owned()
+                    }),
+                    caller_id: metadata("callerid").map(str::to_string),
+                    latching,
+                    encoding,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The index and data of an MCAP file opened as a `Bag`
+#[derive(Clone, Copy)]
+pub struct McapFile<'a> {
+    pub index: &'a McapIndex,
+    pub data: &'a [u8],
+}
+
+impl<'a> McapFile<'a> {
+    /// All messages in file order, one chunk is decompressed at a time
+    pub fn messages(&self) -> McapMessages<'a> {
+        McapMessages {
+            records: McapRecords::new(self.data, self.index.data_start),
+        }
+    }
+
+    /// Log times of the messages per channel, read from the message indexes if there are
+    /// chunk indexes and from the messages otherwise
+    pub fn message_times(&self) -> Result<BTreeMap<u16, Vec<u64>>, RecordError> {
+        let mut times = BTreeMap::<u16, Vec<u64>>::new();
+        if self.index.chunk_indexes.is_empty() {
+            for message in self.messages() {
+                let message = message?;
+                times
+                    .entry(message.conn_id as u16)
+                    .or_default()
+                    .push(message.time);
+            }
+            return Ok(times);
+        }
+        for chunk_index in self.index.chunk_indexes.iter() {
+            for (channel_id, chunk_times) in self.chunk_message_times(chunk_index)? {
+                times.entry(channel_id).or_default().extend(chunk_times);
+            }
+        }
+        Ok(times)
+    }
+
+    /// Log times of the messages per channel in one chunk, from its message index records
+    /// or, as they are optional, from the messages of the chunk
+    pub fn chunk_message_times(
+        &self,
+        chunk_index: &ChunkIndex,
+    ) -> Result<BTreeMap<u16, Vec<u64>>, RecordError> {
+        let mut times = BTreeMap::<u16, Vec<u64>>::new();
+        if chunk_index.message_index_offsets.is_empty() {
+            let (op, content, _) = read_mcap_record(self.data, chunk_index.chunk_pos)?;
+            if op != OP_CHUNK {
+                return Err(RecordError::UnknownOp(op));
+            }
+            let records = chunk_records(content)?;
+            let mut pos = 0;
+            while pos < records.len() as u64 {
+                let (op, content, end) = read_mcap_record(&records, pos)?;
+                if op == OP_MESSAGE {
+                    let message = parse_message(content)?;
+                    times
+                        .entry(message.conn_id as u16)
+                        .or_default()
+                        .push(message.time);
+                }
+                pos = end;
+            }
+            return Ok(times);
+        }
+        for (&channel_id, &offset) in chunk_index.message_index_offsets.iter() {
+            let (op, content, _) = read_mcap_record(self.data, offset)?;
+            if op != OP_MESSAGE_INDEX {
+                return Err(RecordError::UnknownOp(op));
+            }
+            let mut cursor = Cursor::new(content);
+            cursor.next_u16()?;
+            let entries = cursor.next_chunk()?;
+            let channel_times = entries
+                .chunks_exact(16)
+                .map(|entry| LE::read_u64(&entry[..8]))
+                .collect();
+            times.insert(channel_id, channel_times);
+        }
+        Ok(times)
+    }
+}
+
+/// The summary section between the data end record and the summary offsets, if there is one
+fn summary_section(data: &[u8]) -> Result<Option<&[u8]>, RecordError> {
+    const FOOTER_LEN: usize = 1 + 8 + 20;
+    if data.len() < 2 * MCAP_MAGIC.len() + FOOTER_LEN || !data.ends_with(MCAP_MAGIC) {
+        return Ok(None);
+    }
+    let footer_pos = data.len() - MCAP_MAGIC.len() - FOOTER_LEN;
+    let (op, footer, _) = read_mcap_record(data, footer_pos as u64)?;
+    if op != OP_FOOTER {
+        return Ok(None);
+    }
+    let mut cursor = Cursor::new(footer);
+    let summary_start = cursor.next_u64()? as usize;
+    let summary_end = match cursor.next_u64()? as usize {
+        0 => footer_pos,
+        summary_offset_start => summary_offset_start,
+    };
+    if summary_start == 0 {
+        return Ok(None);
+    }
+    if summary_start > summary_end || summary_end > footer_pos {
+        return Err(RecordError::InvalidField("summary_start"));
+    }
+    Ok(Some(&data[summary_start..summary_end]))
+}
+
+/// Opcode, content and end position of the record at `pos`
+fn read_mcap_record(data: &[u8], pos: u64) -> Result<(u8, &[u8], u64), RecordError> {
+    let mut cursor = Cursor::new(data);
+    cursor.seek(pos)?;
+    let op = cursor.next_bytes(1)?[0];
+    let len = cursor.next_u64()?;
+    let content = cursor.next_bytes(len)?;
+    Ok((op, content, cursor.pos()))
+}
+
+fn next_str(cursor: &mut Cursor) -> Result<String, RecordError> {
+    let bytes = cursor.next_chunk()?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| RecordError::InvalidHeaderField)
+}
+
+fn parse_schema(content: &[u8]) -> Result<Schema, RecordError> {
+    let mut cursor = Cursor::new(content);
+    Ok(Schema {
+        id: cursor.next_u16()?,
+        name: next_str(&mut cursor)?,
+        encoding: next_str(&mut cursor)?,
+        data: cursor.next_chunk()?.to_vec(),
+    })
+}
+
+fn parse_channel(content: &[u8]) -> Result<Channel, RecordError> {
+    let mut cursor = Cursor::new(content);
+    let id = cursor.next_u16()?;
+    let schema_id = cursor.next_u16()?;
+    let topic = next_str(&mut cursor)?;
+    let message_encoding = next_str(&mut cursor)?;
+    let mut metadata = BTreeMap::new();
+    let mut entries = Cursor::new(cursor.next_chunk()?);
+    while entries.left() > 0 {
+        let key = next_str(&mut entries)?;
+        metadata.insert(key, next_str(&mut entries)?);
+    }
+    Ok(Channel {
+        id,
+        schema_id,
+        topic,
+        message_encoding,
+        metadata,
+    })
+}
+
+fn parse_message(content: &[u8]) -> Result<Message, RecordError> {
+    let mut cursor = Cursor::new(content);
+    let channel_id = cursor.next_u16()?;
+    // Sequence number
+    cursor.next_u32()?;
+    let log_time = cursor.next_u64()?;
+    // Publish time
+    cursor.next_u64()?;
+    Ok(Message {
+        conn_id: channel_id as u32,
+        time: log_time,
+        data: cursor.next_bytes(cursor.left())?.to_vec(),
+    })
+}
+
+fn parse_chunk_index(content: &[u8]) -> Result<ChunkIndex, RecordError> {
+    let mut cursor = Cursor::new(content);
+    let start_time = cursor.next_u64()?;
+    let end_time = cursor.next_u64()?;
+    let chunk_pos = cursor.next_u64()?;
+    let chunk_len = cursor.next_u64()?;
+    let offsets = cursor.next_chunk()?;
+    let message_index_offsets = offsets
+        .chunks_exact(10)
+        .map(|entry| (LE::read_u16(&entry[..2]), LE::read_u64(&entry[2..])))
+        .collect();
+    let message_index_len = cursor.next_u64()?;
+    let compression = next_str(&mut cursor)?;
+    Ok(ChunkIndex {
+        start_time,
+        end_time,
+        chunk_pos,
+        chunk_len,
+        message_index_offsets,
+        message_index_len,
+        compression: McapCompression::from_name(&compression)
+            .ok_or(RecordError::UnsupportedCompression(compression))?,
+        compressed_size: cursor.next_u64()?,
+        uncompressed_size: cursor.next_u64()?,
+    })
+}
+
+/// The decompressed records of a chunk record
+fn chunk_records(content: &[u8]) -> Result<Vec<u8>, RecordError> {
+    let mut cursor = Cursor::new(content);
+    // Start and end time
+    cursor.next_bytes(16)?;
+    let uncompressed_size = cursor.next_u64()?;
+    let crc = cursor.next_u32()?;
+    let compression = next_str(&mut cursor)?;
+    let compression = McapCompression::from_name(&compression)
+        .ok_or(RecordError::UnsupportedCompression(compression))?;
+    let records_len = cursor.next_u64()?;
+    let records = compression.decompress(cursor.next_bytes(records_len)?, uncompressed_size)?;
+    // A CRC of 0 means none was computed
+    if crc != 0 && crc32fast::hash(&records) != crc {
+        return Err(RecordError::Decompression("CRC mismatch".to_string()));
+    }
+    Ok(records)
+}
+
+/// Opcode and content of a record
+type McapRecord<'a> = (u8, Cow<'a, [u8]>);
+
+/// Records of the data section, with the records of chunks in place of the chunks
+struct McapRecords<'a> {
+    data: &'a [u8],
+    pos: u64,
+    chunk: Vec<u8>,
+    chunk_pos: u64,
+}
+
+impl<'a> McapRecords<'a> {
+    fn new(data: &'a [u8], pos: u64) -> Self {
+        Self {
+            data,
+            pos,
+            chunk: vec![],
+            chunk_pos: 0,
+        }
+    }
+
+    /// The next record, `None` at the data end record or the end of the file
+    fn next_record(&mut self) -> Result<Option<McapRecord<'a>>, RecordError> {
+        loop {
+            if self.chunk_pos < self.chunk.len() as u64 {
+                let (op, content, end) = read_mcap_record(&self.chunk, self.chunk_pos)?;
+                self.chunk_pos = end;
+                return Ok(Some((op, Cow::Owned(content.to_vec()))));
+            }
+            if self.pos >= self.data.len() as u64 {
+                return Ok(None);
+            }
+            let (op, content, end) = read_mcap_record(self.data, self.pos)?;
+            self.pos = end;
+            match op {
+                OP_DATA_END | OP_FOOTER => {
+                    self.pos = self.data.len() as u64;
+                    return Ok(None);
+                }
+                OP_CHUNK => {
+                    self.chunk = chunk_records(content)?;
+                    self.chunk_pos = 0;
+                }
+                op => return Ok(Some((op, Cow::Borrowed(content)))),
+            }
+        }
+    }
+}
+
+/// Messages of an MCAP file as bag messages, the channel id is the connection id
+/// and the log time the record time
+pub struct McapMessages<'a> {
+    records: McapRecords<'a>,
+}
+
+impl Iterator for McapMessages<'_> {
+    type Item = Result<Message, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (op, content) = match self.records.next_record() {
+                Ok(record) => record?,
+                Err(e) => {
+                    self.records.pos = self.records.data.len() as u64;
+                    self.records.chunk.clear();
+                    return Some(Err(e));
+                }
+            };
+            if op == OP_MESSAGE {
+                return Some(parse_message(&content));
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::io::{Seek, Write};
+
+use anyhow::Result;
+
+use crate::bag::{Bag, ChunkRecord, Message};
+use crate::indexing::get_connections;
+use crate::record::{Connection, RecordError};
+use crate::selection::TopicSelection;
+use crate::writer::BagWriter;
+
+pub struct MergeInput {
+    pub bag: Bag,
+    /// Prepended to every topic of the bag, e.g. `/base_station`
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub connections: usize,
+    pub messages: u64,
+}
+
+/// Connections are the same if these match, other fields are taken from the first one
+type ConnectionKey = (String, String, String, Option<String>);
+
+type Messages<'a> = Box<dyn Iterator<Item = Result<Message, RecordError>> + 'a>;
+
+/// Merge bags into one, ordered by record time.
+/// Connections with the same topic, type, md5sum and caller id are written once.
+pub fn merge<W: Write + Seek>(
+    inputs: &[MergeInput],
+    writer: &mut BagWriter<W>,
+) -> Result<MergeReport> {
+    let mut conn_ids = HashMap::<ConnectionKey, u32>::new();
+    // Input connection id to output connection id, per input
+    let mut conn_maps = vec![];
+    for input in inputs {
+        let mut conn_map = BTreeMap::new();
+        for connection in get_connections(&input.bag, &TopicSelection::all())? {
+            let connection = Connection {
+                topic: prefix_topic(input.prefix.as_deref(), &connection.topic),
+                ..connection
+            };
+            let key = (
+                connection.topic.clone(),
+                connection.tp.clone(),
+                connection.md5sum.clone(),
+                connection.caller_id.clone(),
+            );
+            let conn_id = *conn_ids
+                .entry(key)
+                .or_insert_with(|| writer.add_connection(&connection));
+            conn_map.insert(connection.id, conn_id);
+        }
+        conn_maps.push(conn_map);
+    }
+
+    // With every input in record time order, merging their next messages keeps that order
+    let mut messages = inputs
+        .iter()
+        .map(|input| time_ordered_messages(&input.bag))
+        .collect::<Result<Vec<_>>>()?;
+    let mut pending: Vec<Option<Message>> = vec![None; inputs.len()];
+    let mut heap = BinaryHeap::new();
+    for (i, messages) in messages.iter_mut().enumerate() {
+        if let Some(message) = messages.next().transpose()? {
+            heap.push(Reverse((message.time, i)));
+            pending[i] = Some(message);
+        }
+    }
+
+    let mut report = MergeReport {
+        connections: conn_ids.len(),
+        messages: 0,
+    };
+    while let Some(Reverse((_, i))) = heap.pop() {
+        let message = pending[i].take().unwrap();
+        if let Some(conn_id) = conn_maps[i].get(&message.conn_id) {
+            writer.write_message(*conn_id, message.time, &message.data)?;
+            report.messages += 1;
+        }
+        if let Some(message) = messages[i].next().transpose()? {
+            heap.push(Reverse((message.time, i)));
+            pending[i] = Some(message);
+        }
+    }
+    Ok(report)
+}
+
+/// Messages of the bag in record time order. Bags are usually recorded in that order and are
+/// read as stored, others are sorted in memory.
+fn time_ordered_messages(bag: &Bag) -> Result<Messages<'_>> {
+    if is_time_ordered(bag)? {
+        return Ok(Box::new(bag.messages()));
+    }
+    let mut messages = bag.messages().collect::<Result<Vec<_>, _>>()?;
+    messages.sort_by_key(|message| message.time);
+    Ok(Box::new(messages.into_iter().map(Ok)))
+}
+
+/// Whether the messages are stored in record time order, checked with the index data of each
+/// chunk which holds the time and chunk offset of every message
+fn is_time_ordered(bag: &Bag) -> Result<bool> {
+    if bag.mcap().is_some() {
+        // The MCAP message indexes are per channel, the messages are read once to compare
+        let mut last_time = 0;
+        for message in bag.messages() {
+            let time = message?.time;
+            if time < last_time {
+                return Ok(false);
+            }
+            last_time = time;
+        }
+        return Ok(true);
+    }
+
+    let mut last_time = 0;
+    let mut entries = vec![];
+    let mut chunk_ordered = |entries: &mut Vec<(u32, u64)>| {
+        entries.sort_unstable();
+        let ordered = entries.iter().all(|&(_, time)| {
+            let ordered = time >= last_time;
+            last_time = time;
+            ordered
+        });
+        entries.clear();
+        ordered
+    };
+    for record in bag.chunk_records() {
+        match record? {
+            ChunkRecord::Chunk(_) => {
+                if !chunk_ordered(&mut entries) {
+                    return Ok(false);
+                }
+            }
+            ChunkRecord::IndexData(index_data) => {
+                entries.extend(index_data.entries().map(|entry| (entry.offset, entry.time)))
+            }
+        }
+    }
+    Ok(chunk_ordered(&mut entries))
+}
+
+fn prefix_topic(prefix: Option<&str>, topic: &str) -> String {
+    let topic = topic.trim_start_matches('/');
+    match prefix.map(|prefix| prefix.trim_matches('/')) {
+        Some(prefix) if !prefix.is_empty() => format!("/{}/{}", prefix, topic),
+        _ => format!("/{}", topic),
+    }
+}
@@ -0,0 +1,529 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use byteorder::{WriteBytesExt, LE};
+
+use crate::message_parser::{normalize_type, parse_message_definition, Field, Repeated};
+use crate::record::{Connection, MessageEncoding};
+
+const HEADER_TYPE: &str = "std_msgs/Header";
+pub(crate) const ROS2_TIME_TYPE: &str = "builtin_interfaces/Time";
+pub(crate) const ROS2_DURATION_TYPE: &str = "builtin_interfaces/Duration";
+
+/// A decoded field value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    /// Nanoseconds since the epoch
+    Time(u64),
+    /// Nanoseconds
+    Duration(i64),
+    /// `uint8`, `byte` and `char` arrays, e.g. image data
+    Bytes(Vec<u8>),
+    Array(Vec<Value>),
+    Message(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Look up a nested field by a path like `header.stamp` or `points.0.x`
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        path.split('.').try_fold(self, |value, name| match value {
+            Value::Message(fields) => fields
+                .iter()
+                .find(|(field_name, _)| field_name == name)
+                .map(|(_, value)| value),
+            Value::Array(values) => values.get(name.parse::<usize>().ok()?),
+            _ => None,
+        })
+    }
+
+    /// Same as `get` for changing the value
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut Value> {
+        path.split('.').try_fold(self, |value, name| match value {
+            Value::Message(fields) => fields
+                .iter_mut()
+                .find(|(field_name, _)| field_name == name)
+                .map(|(_, value)| value),
+            Value::Array(values) => values.get_mut(name.parse::<usize>().ok()?),
+            _ => None,
+        })
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Bool(value) => Some(*value as u8 as f64),
+            Value::Int(value) => Some(*value as f64),
+            Value::UInt(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            Value::Time(value) => Some(*value as f64 / 1e9),
+            Value::Duration(value) => Some(*value as f64 / 1e9),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the serialized messages of one connection
+#[derive(Debug)]
+pub struct MessageDecoder {
+    fields: Vec<Field>,
+    /// Nested message types by full name, field types are resolved to full names as well
+    types: BTreeMap<String, Vec<Field>>,
+    encoding: MessageEncoding,
+}
+
+impl MessageDecoder {
+    /// `tp` is the message type of the connection, needed to resolve types of the same package
+    pub fn new(tp: &str, definition: &str) -> Result<Self> {
+        let (fields, types) = parse_message_definition(definition)?;
+        let names: Vec<String> = types.keys().cloned().collect();
+        let resolve_all = |package: &str, fields: Vec<Field>| -> Result<Vec<Field>> {
+            fields
+                .into_iter()
+                // Constants like `uint8 DEBUG=1` are not serialized
+                .filter(|field| !field.field_name.split('"').next().unwrap().contains('='))
+                .map(|field| {
+                    // ros2msg fields may be followed by a default value
+                    let field_name = field.field_name.split_whitespace().next().unwrap_or("");
+                    Ok(Field {
+                        field_name: field_name.to_string(),
+                        field_type: resolve_type(&field.field_type, package, &names)?,
+                        field_repeat: field.field_repeat,
+                    })
+                })
+                .collect()
+        };
+
+        let fields = resolve_all(package(tp), fields)?;
+        let types = types
+            .into_iter()
+            .map(|(name, fields)| {
+                let fields = resolve_all(package(&name), fields)?;
+                Ok((name, fields))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            fields,
+            types,
+            encoding: MessageEncoding::Ros1,
+        })
+    }
+
+    /// Decoder for the messages of a bag connection or MCAP channel, ROS1 or CDR encoded
+    pub fn for_connection(connection: &Connection) -> Result<Self> {
+        if let MessageEncoding::Other(encoding) = &connection.encoding {
+            bail!(
+                "Cannot decode {} messages of {}",
+                encoding,
+                connection.topic
+            );
+        }
+        Ok(Self {
+            encoding: connection.encoding.clone(),
+            ..Self::new(
+                &normalize_type(&connection.tp),
+                &connection.message_definition,
+            )?
+        })
+    }
+
+    /// Fields of the message type without constants, field types are resolved to full names
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Fields of a nested message type, None for primitives and types decoded as a single value
+    pub fn type_fields(&self, field_type: &str) -> Option<&[Field]> {
+        if self.encoding == MessageEncoding::Cdr
+            && matches!(field_type, ROS2_TIME_TYPE | ROS2_DURATION_TYPE)
+        {
+            return None;
+        }
+        self.types.get(field_type).map(Vec::as_slice)
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<Value> {
+        let mut reader = Reader::new(data, &self.encoding)?;
+        self.decode_fields(&self.fields, &mut reader)
+            .context("Message is shorter than its definition")
+    }
+
+    /// Whether the message starts with a `std_msgs/Header`
+    pub fn has_header(&self) -> bool {
+        self.fields.first().is_some_and(|field| {
+            field.field_type == HEADER_TYPE && field.field_repeat == Repeated::None
+        })
+    }
+
+    /// The `header.stamp` of a message, decoding only the header
+    pub fn header_stamp(&self, data: &[u8]) -> Result<Option<u64>> {
+        if !self.has_header() {
+            return Ok(None);
+        }
+        let mut reader = Reader::new(data, &self.encoding)?;
+        let header = self
+            .decode_field(&self.fields[0], &mut reader)
+            .context("Message is shorter than its header")?;
+        match header.get("stamp") {
+            Some(Value::Time(stamp)) => Ok(Some(*stamp)),
+            _ => bail!("Header has no stamp"),
+        }
+    }
+
+    /// Serialize a message of this type, the inverse of `decode`
+    pub fn encode(&self, value: &Value) -> Result<Vec<u8>> {
+        if self.encoding != MessageEncoding::Ros1 {
+            bail!("Cannot encode {} messages", self.encoding);
+        }
+        let mut data = vec![];
+        self.encode_fields(&self.fields, value, &mut data)?;
+        Ok(data)
+    }
+
+    /// Whether the message contains a `std_msgs/Header` anywhere
+    pub fn uses_header(&self) -> bool {
+        self.types.contains_key(HEADER_TYPE)
+    }
+
+    /// Call `f` with every `std_msgs/Header` in a decoded message, including nested ones
+    pub fn visit_headers<F: FnMut(&mut Value)>(&self, value: &mut Value, mut f: F) {
+        self.visit_fields(&self.fields, value, &mut f);
+    }
+
+    fn visit_fields(&self, fields: &[Field], value: &mut Value, f: &mut dyn FnMut(&mut Value)) {
+        let Value::Message(values) = value else {
+            return;
+        };
+        for (field, (_, value)) in fields.iter().zip(values.iter_mut()) {
+            let visit = |value: &mut Value, f: &mut dyn FnMut(&mut Value)| {
+                if field.field_type == HEADER_TYPE {
+                    f(value);
+                } else if let Some(fields) = self.types.get(&field.field_type) {
+                    self.visit_fields(fields, value, f);
+                }
+            };
+            match value {
+                Value::Array(values) if field.field_repeat != Repeated::None => {
+                    for value in values.iter_mut() {
+                        visit(value, f);
+                    }
+                }
+                value => visit(value, f),
+            }
+        }
+    }
+
+    fn encode_fields(&self, fields: &[Field], value: &Value, data: &mut Vec<u8>) -> Result<()> {
+        let Value::Message(values) = value else {
+            bail!("Expected a message, got {:?}", value);
+        };
+        if values.len() != fields.len() {
+            bail!("Expected {} fields, got {}", fields.len(), values.len());
+        }
+        for (field, (name, value)) in fields.iter().zip(values.iter()) {
+            if *name != field.field_name {
+                bail!("Expected field {}, got {}", field.field_name, name);
+            }
+            self.encode_field(field, value, data)
+                .with_context(|| format!("Cannot encode field {}", name))?;
+        }
+        Ok(())
+    }
+
+    fn encode_field(&self, field: &Field, value: &Value, data: &mut Vec<u8>) -> Result<()> {
+        let len = match (&field.field_repeat, value) {
+            (Repeated::None, value) => return self.encode_value(&field.field_type, value, data),
+            (_, Value::Bytes(bytes)) => bytes.len(),
+            (_, Value::Array(values)) => values.len(),
+            (_, value) => bail!("Expected an array, got {:?}", value),
+        };
+        match &field.field_repeat {
+            Repeated::Variable | Repeated::Fixed(0) => data.write_u32::<LE>(len as u32)?,
+            Repeated::Fixed(fixed) if *fixed as usize != len => {
+                bail!("Expected {} elements, got {}", fixed, len)
+            }
+            _ => {}
+        }
+        match value {
+            Value::Bytes(bytes) => data.extend_from_slice(bytes),
+            Value::Array(values) => {
+                for value in values {
+                    self.encode_value(&field.field_type, value, data)?;
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn encode_value(&self, field_type: &str, value: &Value, data: &mut Vec<u8>) -> Result<()> {
+        match (field_type, value) {
+            ("bool", Value::Bool(value)) => data.write_u8(*value as u8)?,
+            ("int8" | "byte", Value::Int(value)) => data.write_i8(*value as i8)?,
+            ("uint8" | "char", Value::UInt(value)) => data.write_u8(*value as u8)?,
+            ("int16", Value::Int(value)) => data.write_i16::<LE>(*value as i16)?,
+            ("uint16", Value::UInt(value)) => data.write_u16::<LE>(*value as u16)?,
+            ("int32", Value::Int(value)) => data.write_i32::<LE>(*value as i32)?,
+            ("uint32", Value::UInt(value)) => data.write_u32::<LE>(*value as u32)?,
+            ("int64", Value::Int(value)) => data.write_i64::<LE>(*value)?,
+            ("uint64", Value::UInt(value)) => data.write_u64::<LE>(*value)?,
+            ("float32", Value::Float(value)) => data.write_f32::<LE>(*value as f32)?,
+            ("float64", Value::Float(value)) => data.write_f64::<LE>(*value)?,
+            ("string", Value::String(value)) => {
+                data.write_u32::<LE>(value.len() as u32)?;
+                data.extend_from_slice(value.as_bytes());
+            }
+            ("time", Value::Time(value)) => {
+                data.write_u32::<LE>((value / 1_000_000_000) as u32)?;
+                data.write_u32::<LE>((value % 1_000_000_000) as u32)?;
+            }
+            ("duration", Value::Duration(value)) => {
+                data.write_i32::<LE>(value.div_euclid(1_000_000_000) as i32)?;
+                data.write_i32::<LE>(value.rem_euclid(1_000_000_000) as i32)?;
+            }
+            (message_type, value) => match self.types.get(message_type) {
+                Some(fields) => self.encode_fields(fields, value, data)?,
+                None if is_primitive(message_type) => {
+                    bail!("Expected {}, got {:?}", message_type, value)
+                }
+                None => bail!("Unknown message type {}", message_type),
+            },
+        }
+        Ok(())
+    }
+
+    fn decode_fields(&self, fields: &[Field], reader: &mut Reader) -> Result<Value> {
+        let mut values = Vec::with_capacity(fields.len());
+        for field in fields {
+            values.push((field.field_name.clone(), self.decode_field(field, reader)?));
+        }
+        Ok(Value::Message(values))
+    }
+
+    fn decode_field(&self, field: &Field, reader: &mut Reader) -> Result<Value> {
+        let len = match field.field_repeat {
+            Repeated::None => return self.decode_value(&field.field_type, reader),
+            // `[]` is parsed as fixed length 0, both carry their length in the message
+            Repeated::Variable | Repeated::Fixed(0) => reader.u32()? as usize,
+            Repeated::Fixed(len) => len as usize,
+        };
+        if matches!(field.field_type.as_str(), "uint8" | "byte" | "char") {
+            if reader.left() < len {
+                bail!("Array of {} bytes exceeds the message", len);
+            }
+            return Ok(Value::Bytes(reader.bytes(len)?.to_vec()));
+        }
+        let mut values = Vec::with_capacity(len.min(reader.left()));
+        for _ in 0..len {
+            values.push(self.decode_value(&field.field_type, reader)?);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn decode_value(&self, field_type: &str, reader: &mut Reader) -> Result<Value> {
+        Ok(match field_type {
+            "bool" => Value::Bool(reader.u8()? != 0),
+            "int8" | "byte" => Value::Int(reader.u8()? as i8 as i64),
+            "uint8" | "char" => Value::UInt(reader.u8()? as u64),
+            "int16" => Value::Int(reader.i16()? as i64),
+            "uint16" => Value::UInt(reader.u16()? as u64),
+            "int32" => Value::Int(reader.i32()? as i64),
+            "uint32" => Value::UInt(reader.u32()? as u64),
+            "int64" => Value::Int(reader.i64()?),
+            "uint64" => Value::UInt(reader.u64()?),
+            "float32" => Value::Float(reader.f32()? as f64),
+            "float64" => Value::Float(reader.f64()?),
+            "string" => {
+                let len = reader.u32()? as usize;
+                if reader.left() < len {
+                    bail!("String of {} bytes exceeds the message", len);
+                }
+                let mut bytes = reader.bytes(len)?;
+                // CDR strings include their null terminator
+                if reader.cdr {
+                    bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+                }
+                Value::String(String::from_utf8_lossy(bytes).into_owned())
+            }
+            "time" => {
+                let secs = reader.u32()? as u64;
+                let nsecs = reader.u32()? as u64;
+                Value::Time(secs * 1_000_000_000 + nsecs)
+            }
+            "duration" => {
+                let secs = reader.i32()? as i64;
+                let nsecs = reader.i32()? as i64;
+                Value::Duration(secs * 1_000_000_000 + nsecs)
+            }
+            // ROS2 has no time primitives, these are decoded the same way
+            ROS2_TIME_TYPE if reader.cdr => {
+                let secs = reader.i32()?.max(0) as u64;
+                let nsecs = reader.u32()? as u64;
+                Value::Time(secs * 1_000_000_000 + nsecs)
+            }
+            ROS2_DURATION_TYPE if reader.cdr => {
+                let secs = reader.i32()? as i64;
+                let nsecs = reader.u32()? as i64;
+                Value::Duration(secs * 1_000_000_000 + nsecs)
+            }
+            message_type => match self.types.get(message_type) {
+                Some(fields) => self.decode_fields(fields, reader)?,
+                None => bail!("Unknown message type {}", message_type),
+            },
+        })
+    }
+}
+
+/// Decoders of the connections whose messages contain a `std_msgs/Header` anywhere,
+/// see [`MessageDecoder::uses_header`], built on first use. Callers that only want
+/// messages stamped by their first field also check [`MessageDecoder::has_header`].
+#[derive(Debug, Default)]
+pub struct HeaderDecoders {
+    // None for connections without header
+    decoders: BTreeMap<u32, Option<MessageDecoder>>,
+}
+
+impl HeaderDecoders {
+    /// The decoder of the connection, None if its messages have no header.
+    /// Connections that cannot be decoded are treated as having no header.
+    pub fn get(&mut self, connection: &Connection) -> Option<&MessageDecoder> {
+        self.decoders
+            .entry(connection.id)
+            .or_insert_with(|| {
+                MessageDecoder::for_connection(connection)
+                    .ok()
+                    .filter(MessageDecoder::uses_header)
+            })
+            .as_ref()
+    }
+}
+
+/// Reads the primitives of a serialized message.
+/// CDR aligns primitives to their size and may be big endian, ROS1 is packed little endian.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    cdr: bool,
+    big_endian: bool,
+}
+
+macro_rules! read_primitives {
+    ($($name:ident: $tp:ty),*) => {
+        $(fn $name(&mut self) -> Result<$tp> {
+            let bytes = self.primitive()?;
+            Ok(if self.big_endian {
+                <$tp>::from_be_bytes(bytes)
+            } else {
+                <$tp>::from_le_bytes(bytes)
+            })
+        })*
+    };
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], encoding: &MessageEncoding) -> Result<Self> {
+        if *encoding != MessageEncoding::Cdr {
+            return Ok(Self {
+                data,
+                pos: 0,
+                cdr: false,
+                big_endian: false,
+            });
+        }
+        // The encapsulation header, alignment is relative to its end
+        let Some((header, data)) = data.split_first_chunk::<4>() else {
+            bail!("Message is shorter than its CDR header");
+        };
+        let big_endian = match header[..2] {
+            [0, 0] => true,
+            [0, 1] => false,
+            _ => bail!(
+                "Unsupported CDR encapsulation 0x{:02x}{:02x}",
+                header[0],
+                header[1]
+            ),
+        };
+        Ok(Self {
+            data,
+            pos: 0,
+            cdr: true,
+            big_endian,
+        })
+    }
+
+    fn left(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.left() < len {
+            bail!("{} bytes at offset {} exceed the message", len, self.pos);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn primitive<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.cdr {
+            self.pos = self.pos.next_multiple_of(N).min(self.data.len());
+        }
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    read_primitives!(i16: i16, u16: u16, i32: i32, u32: u32, i64: i64, u64: u64, f32: f32, f64: f64);
+}
+
+fn is_primitive(field_type: &str) -> bool {
+    matches!(
+        field_type,
+        "bool"
+            | "int8"
+            | "uint8"
+            | "byte"
+            | "char"
+            | "int16"
+            | "uint16"
+            | "int32"
+            | "uint32"
+            | "int64"
+            | "uint64"
+            | "float32"
+            | "float64"
+            | "string"
+            | "time"
+            | "duration"
+    )
+}
+
+fn package(message_type: &str) -> &str {
+    message_type
+        .split_once('/')
+        .map_or("", |(package, _)| package)
+}
+
+/// Full name of a field type, definitions may leave out the package within the same package
+fn resolve_type(field_type: &str, package: &str, types: &[String]) -> Result<String> {
+    if is_primitive(field_type) || field_type.contains('/') {
+        return Ok(field_type.to_string());
+    }
+    if field_type == "Header" {
+        return Ok(HEADER_TYPE.to_string());
+    }
+    let same_package = format!("{}/{}", package, field_type);
+    if types.contains(&same_package) {
+        return Ok(same_package);
+    }
+    let suffix = format!("/{}", field_type);
+    match types.iter().find(|name| name.ends_with(&suffix)) {
+        Some(name) => Ok(name.clone()),
+        None => bail!("Unknown message type {}", field_type),
+    }
+}
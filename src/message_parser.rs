@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 
+use anyhow::{bail, Result};
 use regex::Regex;
 
 const MESSAGE_SEPARATOR: &str =
@@ -7,9 +8,9 @@ const MESSAGE_SEPARATOR: &str =
 
 #[derive(Debug)]
 pub struct Field {
-    field_name: String,
-    field_type: String,
-    field_repeat: Repeated,
+    pub field_name: String,
+    pub field_type: String,
+    pub field_repeat: Repeated,
 }
 #[derive(Debug, PartialEq)]
 pub enum Repeated {
@@ -18,15 +19,20 @@ pub enum Repeated {
     Variable,
 }
 
+/// ros2msg definitions name types like `std_msgs/msg/Header` and may bound strings and arrays,
+/// as in `string<=8` or `float32[<=3]`. Bounds are dropped and bounded arrays are variable.
 pub fn match_repeat(field_def: &str) -> Option<(&str, Repeated)> {
     let re = Regex::new(
-        r"^(?<type>[a-zA-Z]+[a-zA-Z0-9_]*(?:\/)?[a-zA-Z]+[a-zA-Z0-9_]*)(?<repeat_group>\[(?<repeat>[-]?[0-9]*)\])?$",
+        r"^(?<type>[a-zA-Z]+[a-zA-Z0-9_]*(?:\/(?:msg\/)?[a-zA-Z]+[a-zA-Z0-9_]*)?)(?:<=[0-9]+)?(?<repeat_group>\[(?<bound><=)?(?<repeat>[-]?[0-9]*)\])?$",
     )
     .unwrap();
     match re.captures(field_def) {
         Some(matched) => {
             match matched.name("type") {
                 Some(field_type) => match matched.name("repeat_group") {
+                    Some(_) if matched.name("bound").is_some() => {
+                        Some((field_type.as_str(), Repeated::Variable)) // float[<=3]
+                    }
                     Some(_) => match matched.name("repeat") {
                         Some(repeat) => match str::parse::<u32>(repeat.as_str()) {
                             Ok(repeat) => Some((field_type.as_str(), Repeated::Fixed(repeat))), // float[1]
@@ -43,7 +49,15 @@ pub fn match_repeat(field_def: &str) -> Option<(&str, Repeated)> {
     }
 }
 
-pub fn parse_message_definition(definition: &str) -> (Vec<Field>, BTreeMap<String, Vec<Field>>) {
+/// `std_msgs/msg/Header` is `std_msgs/Header` as in ROS1 definitions
+pub fn normalize_type(message_type: &str) -> String {
+    message_type.replacen("/msg/", "/", 1)
+}
+
+/// Fields of each type a message definition declares after its own fields, by full name
+pub type MessageTypes = BTreeMap<String, Vec<Field>>;
+
+pub fn parse_message_definition(definition: &str) -> Result<(Vec<Field>, MessageTypes)> {
     let mut fields: Vec<Field> = vec![];
     let mut type_def = BTreeMap::new();
 
@@ -62,19 +76,19 @@ pub fn parse_message_definition(definition: &str) -> (Vec<Field>, BTreeMap<Strin
             None => line.trim(),
         };
 
-        match raw_line.split_once(' ') {
+        match raw_line.split_once(char::is_whitespace) {
             Some((field_type, field_name)) => {
                 if let Some((field_type, repeat)) = match_repeat(field_type) {
                     fields.push(Field {
-                        field_name: field_name.to_string(),
-                        field_type: field_type.to_string(),
+                        field_name: field_name.trim().to_string(),
+                        field_type: normalize_type(field_type),
                         field_repeat: repeat,
                     })
                 } else {
-                    panic!("Cannot parse message")
+                    bail!("Cannot parse field type {}", field_type)
                 }
             }
-            None => panic!("Invalid message definition line: {}", raw_line),
+            None => bail!("Invalid message definition line: {}", raw_line),
         }
     }
 
@@ -87,8 +101,8 @@ pub fn parse_message_definition(definition: &str) -> (Vec<Field>, BTreeMap<Strin
             }
 
             if line.starts_with("MSG: ") {
-                field_type = Some(line.trim_start_matches("MSG: "));
-                type_def.insert(field_type.unwrap().to_string(), vec![]);
+                field_type = Some(normalize_type(line.trim_start_matches("MSG: ").trim()));
+                type_def.insert(field_type.clone().unwrap(), vec![]);
                 continue;
             }
 
@@ -98,29 +112,29 @@ pub fn parse_message_definition(definition: &str) -> (Vec<Field>, BTreeMap<Strin
                 None => line.trim(),
             };
 
-            match raw_line.split_once(' ') {
-                Some((sub_field_type, sub_field_name)) => match field_type {
+            match raw_line.split_once(char::is_whitespace) {
+                Some((sub_field_type, sub_field_name)) => match &field_type {
                     Some(ft) => match match_repeat(sub_field_type) {
                         Some((sub_field_type, repeat)) => {
                             let sub_field = Field {
-                                field_name: sub_field_name.to_string(),
-                                field_type: sub_field_type.to_string(),
+                                field_name: sub_field_name.trim().to_string(),
+                                field_type: normalize_type(sub_field_type),
                                 field_repeat: repeat,
                             };
                             type_def.entry(ft.to_string()).and_modify(|fields| {
                                 fields.push(sub_field);
                             });
                         }
-                        None => panic!("Cannot parse message"),
+                        None => bail!("Cannot parse field type {}", sub_field_type),
                     },
-                    None => panic!(
+                    None => bail!(
                         "Message type has to be the first line in a section, beginning with MSG"
                     ),
                 },
-                None => panic!("Invalid message definition line: {}", raw_line),
+                None => bail!("Invalid message definition line: {}", raw_line),
             }
         }
     }
 
-    (fields, type_def)
+    Ok((fields, type_def))
 }
@@ -31,7 +31,7 @@ type FieldType<'a> = &'a str;
 
 pub fn parse_message_definition(
     definition: &str,
-) -> BTreeMap<FieldName, BTreeMap<FieldName, FieldType>> {
+) -> BTreeMap<FieldName<'_>, BTreeMap<FieldName<'_>, FieldType<'_>>> {
     let mut type_map = BTreeMap::new();
 
     let sections = definition.split(MESSAGE_SEPARATOR);
@@ -0,0 +1,378 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::io::{self, Seek, Write};
+
+use anyhow::{bail, Result};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::export::{check_connection, ExportReport};
+use crate::indexing::MessageSource;
+use crate::message_decoder::{MessageDecoder, Value, ROS2_DURATION_TYPE, ROS2_TIME_TYPE};
+use crate::message_parser::{Field, Repeated};
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Element type of an exported array, stored little endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DType {
+    Bool,
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Int64,
+    UInt64,
+    Float32,
+    Float64,
+    /// `datetime64[ns]`
+    Time,
+    /// `timedelta64[ns]`
+    Duration,
+}
+
+impl DType {
+    /// The type of a message field, None if it is not a number
+    pub fn from_field_type(field_type: &str) -> Option<Self> {
+        Some(match field_type {
+            "bool" => DType::Bool,
+            "int8" | "byte" => DType::Int8,
+            "uint8" | "char" => DType::UInt8,
+            "int16" => DType::Int16,
+            "uint16" => DType::UInt16,
+            "int32" => DType::Int32,
+            "uint32" => DType::UInt32,
+            "int64" => DType::Int64,
+            "uint64" => DType::UInt64,
+            "float32" => DType::Float32,
+            "float64" => DType::Float64,
+            "time" | ROS2_TIME_TYPE => DType::Time,
+            "duration" | ROS2_DURATION_TYPE => DType::Duration,
+            _ => return None,
+        })
+    }
+
+    /// The `descr` of the npy header
+    pub fn descr(&self) -> &'static str {
+        match self {
+            DType::Bool => "|b1",
+            DType::Int8 => "|i1",
+            DType::UInt8 => "|u1",
+            DType::Int16 => "<i2",
+            DType::UInt16 => "<u2",
+            DType::Int32 => "<i4",
+            DType::UInt32 => "<u4",
+            DType::Int64 => "<i8",
+            DType::UInt64 => "<u8",
+            DType::Float32 => "<f4",
+            DType::Float64 => "<f8",
+            DType::Time => "<M8[ns]",
+            DType::Duration => "<m8[ns]",
+        }
+    }
+
+    fn push(&self, value: &Value, data: &mut Vec<u8>) -> Result<()> {
+        let int = match value {
+            Value::Bool(value) => *value as i64,
+            Value::Int(value) => *value,
+            Value::UInt(value) => *value as i64,
+            Value::Time(value) => *value as i64,
+            Value::Duration(value) => *value,
+            _ => 0,
+        };
+        match (self, value) {
+            (DType::Float32, Value::Float(value)) => {
+                data.extend_from_slice(&(*value as f32).to_le_bytes())
+            }
+            (DType::Float64, Value::Float(value)) => data.extend_from_slice(&value.to_le_bytes()),
+            (_, Value::Float(_)) => bail!("Float value for a {} array", self.descr()),
+            (DType::Bool | DType::Int8 | DType::UInt8, _) => data.push(int as u8),
+            (DType::Int16 | DType::UInt16, _) => {
+                data.extend_from_slice(&(int as u16).to_le_bytes())
+            }
+            (DType::Int32 | DType::UInt32, _) => {
+                data.extend_from_slice(&(int as u32).to_le_bytes())
+            }
+            (DType::Float32, _) => data.extend_from_slice(&(int as f32).to_le_bytes()),
+            (DType::Float64, _) => data.extend_from_slice(&(int as f64).to_le_bytes()),
+            _ => data.extend_from_slice(&int.to_le_bytes()),
+        }
+        Ok(())
+    }
+}
+
+/// Write an array as npy version 1.0, `data` holds the elements in C order
+pub fn write_npy<W: Write>(
+    writer: &mut W,
+    dtype: DType,
+    shape: &[usize],
+    data: &[u8],
+) -> io::Result<()> {
+    let shape = match shape {
+        [len] => format!("({},)", len),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        dtype.descr(),
+        shape
+    );
+    // Magic, version and header length take 10 bytes, the data starts 64 byte aligned
+    let padding = 63 - (10 + header.len()) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    writer.write_all(NPY_MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    writer.write_all(data)
+}
+
+/// A selected field with its values of all messages so far
+#[derive(Debug)]
+pub struct NumericColumn {
+    /// Dotted path like `linear_acceleration.x` or `orientation_covariance.0`
+    pub path: String,
+    pub dtype: DType,
+    /// Length of a fixed size array, which makes the column 2-D
+    pub len: Option<usize>,
+    pub data: Vec<u8>,
+}
+
+impl NumericColumn {
+    /// Resolve a field path against the message definition. The path may index fixed size
+    /// arrays like `points.0.x` and must end in a number or a fixed size array of numbers.
+    pub fn new(decoder: &MessageDecoder, path: &str) -> Result<Self> {
+        let mut fields = decoder.fields();
+        let mut names = path.split('.').peekable();
+        loop {
+            let name = names.next().unwrap_or_default();
+            let Some(field) = fields.iter().find(|field| field.field_name == name) else {
+                bail!("No field {} in {}", name, path);
+            };
+            let mut len = match field.field_repeat {
+                Repeated::None => None,
+                Repeated::Fixed(len) if len > 0 => Some(len as usize),
+                _ => bail!(
+                    "{} is a variable length array, only fixed size arrays can be exported",
+                    path
+                ),
+            };
+            if let (Some(array_len), Some(index)) = (len, names.peek()) {
+                if let Ok(index) = index.parse::<usize>() {
+                    if index >= array_len {
+                        bail!(
+                            "Index {} of {} is out of its {} elements",
+                            index,
+                            path,
+                            array_len
+                        );
+                    }
+                    names.next();
+                    len = None;
+                }
+            }
+
+            let type_fields = decoder.type_fields(&field.field_type);
+            if names.peek().is_none() {
+                let dtype = match type_fields {
+                    Some(_) => None,
+                    None => DType::from_field_type(&field.field_type),
+                };
+                let Some(dtype) = dtype else {
+                    bail!("{} is a {}, not a number", path, field.field_type);
+                };
+                return Ok(Self {
+                    path: path.to_string(),
+                    dtype,
+                    len,
+                    data: vec![],
+                });
+            }
+            match (len, type_fields) {
+                (None, Some(type_fields)) => fields = type_fields,
+                (Some(_), _) => bail!("{} indexes the array {} without a number", path, name),
+                (None, None) => bail!("{} is a {} without fields", path, field.field_type),
+            }
+        }
+    }
+
+    pub fn shape(&self, rows: usize) -> Vec<usize> {
+        match self.len {
+            Some(len) => vec![rows, len],
+            None => vec![rows],
+        }
+    }
+
+    fn push(&mut self, message: &Value) -> Result<()> {
+        let Some(value) = field_value(message, &self.path) else {
+            bail!("Message has no field {}", self.path);
+        };
+        match (self.len, value.as_ref()) {
+            (None, value) => self.dtype.push(value, &mut self.data),
+            (Some(_), Value::Bytes(bytes)) => {
+                self.data.extend_from_slice(bytes);
+                Ok(())
+            }
+            (Some(_), Value::Array(values)) => values
+                .iter()
+                .try_for_each(|value| self.dtype.push(value, &mut self.data)),
+            _ => bail!("{} is not an array", self.path),
+        }
+    }
+}
+
+/// Same as `Value::get` reaching into byte arrays as well
+fn field_value<'a>(message: &'a Value, path: &str) -> Option<Cow<'a, Value>> {
+    let (parent, name) = match path.rsplit_once('.') {
+        Some((parent, name)) => (message.get(parent)?, name),
+        None => (message, path),
+    };
+    match parent {
+        Value::Bytes(bytes) => Some(Cow::Owned(Value::UInt(
+            *bytes.get(name.parse::<usize>().ok()?)? as u64,
+        ))),
+        parent => parent.get(name).map(Cow::Borrowed),
+    }
+}
+
+/// Expand field lists like `linear_acceleration.x,y,z`, where a name without dots
+/// continues the parent of the previous path unless it is a field of the message itself
+pub fn expand_field_paths(fields: &[Field], paths: &[String]) -> Vec<String> {
+    let mut parent: Option<String> = None;
+    let mut expanded = vec![];
+    for path in paths.iter().flat_map(|paths| paths.split(',')) {
+        let path = path.trim();
+        if path.is_empty() {
+            continue;
+        }
+        let is_field = fields.iter().any(|field| field.field_name == path);
+        let path = match &parent {
+            Some(parent) if !path.contains('.') && !is_field => format!("{}.{}", parent, path),
+            _ => path.to_string(),
+        };
+        parent = path.rsplit_once('.').map(|(parent, _)| parent.to_string());
+        expanded.push(path);
+    }
+    expanded
+}
+
+#[derive(Debug)]
+pub struct NumericColumns {
+    /// Record times in nanoseconds as `datetime64[ns]` bytes
+    pub time: Vec<u8>,
+    pub columns: Vec<NumericColumn>,
+    pub report: ExportReport,
+}
+
+/// Read the selected numeric fields of all messages on `topic`
+pub fn read_numeric_columns(
+    source: MessageSource,
+    topic: &str,
+    paths: &[String],
+) -> Result<NumericColumns> {
+    let mut time = vec![];
+    let mut report = ExportReport::default();
+    let mut columns: Option<(Connection, MessageDecoder, Vec<NumericColumn>)> = None;
+
+    let topics = TopicSelection::parse(&[topic])?;
+    source.read_messages(&topics, |connection, record_time, data| {
+        if columns.is_none() {
+            let decoder = MessageDecoder::for_connection(connection)?;
+            let new = expand_field_paths(decoder.fields(), paths)
+                .iter()
+                .map(|path| NumericColumn::new(&decoder, path))
+                .collect::<Result<Vec<_>>>()?;
+            if new.is_empty() {
+                bail!("Give the fields to export");
+            }
+            // Each column becomes an array named by its path, next to the record time
+            let mut names = BTreeSet::from(["time"]);
+            if let Some(column) = new.iter().find(|column| !names.insert(&column.path)) {
+                bail!(
+                    "{} is selected twice or collides with the record time",
+                    column.path
+                );
+            }
+            columns = Some((connection.clone(), decoder, new));
+        }
+        let (first, decoder, columns) = columns.as_mut().unwrap();
+        check_connection(first, connection)?;
+
+        let message = decoder.decode(data)?;
+        for column in columns.iter_mut() {
+            column.push(&message)?;
+        }
+        time.extend_from_slice(&(record_time as i64).to_le_bytes());
+        report.messages += 1;
+        report.rows += 1;
+        Ok(())
+    })?;
+
+    let Some((_, _, columns)) = columns else {
+        bail!("No messages on {}", topic);
+    };
+    Ok(NumericColumns {
+        time,
+        columns,
+        report,
+    })
+}
+
+/// Write the record time as `time` and the selected fields as arrays named by their path into an
+/// npz archive, as written by `numpy.savez_compressed`. Fixed size arrays become 2-D arrays.
+pub fn export_npz<W: Write + Seek>(
+    source: MessageSource,
+    topic: &str,
+    paths: &[String],
+    writer: W,
+) -> Result<ExportReport> {
+    let NumericColumns {
+        time,
+        columns,
+        report,
+    } = read_numeric_columns(source, topic, paths)?;
+    let rows = report.messages as usize;
+
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("time.npy", options)?;
+    write_npy(&mut zip, DType::Time, &[rows], &time)?;
+    for column in columns.iter() {
+        zip.start_file(format!("{}.npy", column.path), options)?;
+        write_npy(&mut zip, column.dtype, &column.shape(rows), &column.data)?;
+    }
+    zip.finish()?.flush()?;
+    Ok(report)
+}
+
+/// Write a single field as npy, without the record time
+pub fn export_npy<W: Write>(
+    source: MessageSource,
+    topic: &str,
+    paths: &[String],
+    mut writer: W,
+) -> Result<ExportReport> {
+    let NumericColumns {
+        columns, report, ..
+    } = read_numeric_columns(source, topic, paths)?;
+    let [column] = columns.as_slice() else {
+        bail!("An npy file holds one array, write several fields to npz");
+    };
+    let shape = column.shape(report.messages as usize);
+    write_npy(&mut writer, column.dtype, &shape, &column.data)?;
+    writer.flush()?;
+    Ok(report)
+}
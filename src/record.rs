@@ -0,0 +1,272 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str;
+
+use byteorder::{ByteOrder, WriteBytesExt, LE};
+use serde::{Deserialize, Serialize};
+
+use crate::cursor::{Cursor, OutOfBounds};
+
+/// https://wiki.ros.org/Bags/Format/2.0
+pub const BAG_MAGIC: &[u8] = b"#ROSBAG V2.0\n";
+
+pub const OP_MESSAGE_DATA: u8 = 0x02;
+pub const OP_BAG_HEADER: u8 = 0x03;
+pub const OP_INDEX_DATA: u8 = 0x04;
+pub const OP_CHUNK: u8 = 0x05;
+pub const OP_CHUNK_INFO: u8 = 0x06;
+pub const OP_CONNECTION: u8 = 0x07;
+
+#[derive(Debug)]
+pub enum RecordError {
+    OutOfBounds,
+    InvalidHeaderField,
+    MissingField(&'static str),
+    InvalidField(&'static str),
+    UnknownOp(u8),
+    UnsupportedCompression(String),
+    Decompression(String),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::OutOfBounds => write!(f, "record exceeds the available data"),
+            RecordError::InvalidHeaderField => write!(f, "invalid record header field"),
+            RecordError::MissingField(name) => write!(f, "missing header field '{}'", name),
+            RecordError::InvalidField(name) => write!(f, "invalid header field '{}'", name),
+            RecordError::UnknownOp(op) => write!(f, "unknown record op 0x{:02x}", op),
+            RecordError::UnsupportedCompression(compression) => {
+                write!(f, "unsupported chunk compression '{}'", compression)
+            }
+            RecordError::Decompression(reason) => {
+                write!(f, "failed to decompress chunk: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<OutOfBounds> for RecordError {
+    fn from(_: OutOfBounds) -> Self {
+        RecordError::OutOfBounds
+    }
+}
+
+/// The header of a record is a list of <field_len><name>=<value> entries
+#[derive(Debug)]
+pub struct Header<'a> {
+    fields: Vec<(&'a str, &'a [u8])>,
+}
+
+impl<'a> Header<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, RecordError> {
+        let mut cursor = Cursor::new(data);
+        let mut fields = vec![];
+        while cursor.left() > 0 {
+            let field = cursor.next_chunk()?;
+            let separator = field
+                .iter()
+                .position(|&byte| byte == b'=')
+                .ok_or(RecordError::InvalidHeaderField)?;
+            let name =
+                str::from_utf8(&field[..separator]).map_err(|_| RecordError::InvalidHeaderField)?;
+            fields.push((name, &field[separator + 1..]));
+        }
+        Ok(Self { fields })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.fields
+            .iter()
+            .find(|(field_name, _)| *field_name == name)
+            .map(|(_, value)| *value)
+    }
+
+    fn field(&self, name: &'static str) -> Result<&'a [u8], RecordError> {
+        self.get(name).ok_or(RecordError::MissingField(name))
+    }
+
+    pub fn op(&self) -> Result<u8, RecordError> {
+        match self.field("op")? {
+            [op] => Ok(*op),
+            _ => Err(RecordError::InvalidField("op")),
+        }
+    }
+
+    pub fn u32(&self, name: &'static str) -> Result<u32, RecordError> {
+        match self.field(name)? {
+            value if value.len() == 4 => Ok(LE::read_u32(value)),
+            _ => Err(RecordError::InvalidField(name)),
+        }
+    }
+
+    pub fn u64(&self, name: &'static str) -> Result<u64, RecordError> {
+        match self.field(name)? {
+            value if value.len() == 8 => Ok(LE::read_u64(value)),
+            _ => Err(RecordError::InvalidField(name)),
+        }
+    }
+
+    /// Times are stored as <sec><nsec> and returned in nanoseconds
+    pub fn time(&self, name: &'static str) -> Result<u64, RecordError> {
+        match self.field(name)? {
+            value if value.len() == 8 => Cursor::new(value)
+                .next_time()
+                .map_err(|_| RecordError::InvalidField(name)),
+            _ => Err(RecordError::InvalidField(name)),
+        }
+    }
+
+    pub fn str(&self, name: &'static str) -> Result<&'a str, RecordError> {
+        str::from_utf8(self.field(name)?).map_err(|_| RecordError::InvalidField(name))
+    }
+}
+
+/// A record is <header_len><header><data_len><data>
+#[derive(Debug)]
+pub struct Record<'a> {
+    /// Position of the record in the data it was read from
+    pub offset: u64,
+    /// Total number of bytes the record occupies, including the length prefixes
+    pub len: u64,
+    pub op: u8,
+    pub header: Header<'a>,
+    pub data: &'a [u8],
+}
+
+pub fn read_record(bytes: &[u8], offset: u64) -> Result<Record<'_>, RecordError> {
+    let mut cursor = Cursor::new(bytes);
+    cursor.seek(offset)?;
+    let header = Header::parse(cursor.next_chunk()?)?;
+    let op = header.op()?;
+    if !(OP_MESSAGE_DATA..=OP_CONNECTION).contains(&op) {
+        return Err(RecordError::UnknownOp(op));
+    }
+    let data = cursor.next_chunk()?;
+    Ok(Record {
+        offset,
+        len: cursor.pos() - offset,
+        op,
+        header,
+        data,
+    })
+}
+
+/// Serialization of the messages of a connection
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageEncoding {
+    /// ROS1 serialization, the only one bags contain
+    #[default]
+    Ros1,
+    /// ROS2 CDR serialization with `ros2msg` definitions, found in MCAP files
+    Cdr,
+    /// Any other message and schema encoding of an MCAP channel, e.g. `protobuf`
+    Other(String),
+}
+
+impl fmt::Display for MessageEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageEncoding::Ros1 => write!(f, "ros1"),
+            MessageEncoding::Cdr => write!(f, "cdr"),
+            MessageEncoding::Other(encoding) => write!(f, "{}", encoding),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Connection {
+    pub id: u32,
+    pub topic: String,
+    /// Message type, e.g. `sensor_msgs/Imu`
+    pub tp: String,
+    /// MD5 sum of the message definition as hex string
+    pub md5sum: String,
+    pub message_definition: String,
+    /// Name of the node that published the topic
+    pub caller_id: Option<String>,
+    /// Latched topics keep their last message for late subscribers
+    pub latching: bool,
+    /// Always `Ros1` for bags
+    #[serde(default)]
+    pub encoding: MessageEncoding,
+}
+
+impl Connection {
+    pub fn from_record(record: &Record) -> Result<Self, RecordError> {
+        let id = record.header.u32("conn")?;
+        let topic = record.header.str("topic")?;
+        let fields = Header::parse(record.data)?;
+        let caller_id = match fields.get("callerid") {
+            Some(_) => Some(fields.str("callerid")?.to_string()),
+            None => None,
+        };
+        Ok(Self {
+            id,
+            topic: topic.to_string(),
+            tp: fields.str("type")?.to_string(),
+            md5sum: fields.str("md5sum")?.to_string(),
+            message_definition: fields.str("message_definition")?.to_string(),
+            caller_id,
+            latching: fields.get("latching") == Some(b"1"),
+            encoding: MessageEncoding::Ros1,
+        })
+    }
+}
+
+/// Decompress chunk data into `out`.
+/// On failure, `out` keeps everything that could be decompressed before the error.
+pub fn decompress_into(
+    compression: &str,
+    data: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<(), RecordError> {
+    let result = match compression {
+        "none" => {
+            out.extend_from_slice(data);
+            Ok(0)
+        }
+        "bz2" => bzip2::read::BzDecoder::new(data).read_to_end(out),
+        "lz4" => lz4::Decoder::new(data).and_then(|mut decoder| decoder.read_to_end(out)),
+        _ => return Err(RecordError::UnsupportedCompression(compression.to_string())),
+    };
+    result
+        .map(|_| ())
+        .map_err(|e| RecordError::Decompression(e.to_string()))
+}
+
+pub fn encode_header(fields: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut header = vec![];
+    for (name, value) in fields {
+        header
+            .write_u32::<LE>((name.len() + 1 + value.len()) as u32)
+            .unwrap();
+        header.extend_from_slice(name.as_bytes());
+        header.push(b'=');
+        header.extend_from_slice(value);
+    }
+    header
+}
+
+/// Write a record and return the number of bytes written
+pub fn write_record<W: Write>(
+    writer: &mut W,
+    fields: &[(&str, &[u8])],
+    data: &[u8],
+) -> io::Result<u64> {
+    let header = encode_header(fields);
+    writer.write_u32::<LE>(header.len() as u32)?;
+    writer.write_all(&header)?;
+    writer.write_u32::<LE>(data.len() as u32)?;
+    writer.write_all(data)?;
+    Ok(8 + header.len() as u64 + data.len() as u64)
+}
+
+pub fn encode_time(time: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    LE::write_u32(&mut bytes[..4], (time / 1_000_000_000) as u32);
+    LE::write_u32(&mut bytes[4..], (time % 1_000_000_000) as u32);
+    bytes
+}
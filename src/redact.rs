@@ -0,0 +1,342 @@
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Seek, Write};
+
+use anyhow::{bail, Context, Result};
+
+use crate::indexing::MessageSource;
+use crate::message_decoder::{MessageDecoder, Value};
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+use crate::writer::BagWriter;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedactAction {
+    /// Leave out the messages and connections of the topics
+    Drop,
+    /// Set the fields to zero, empty strings and zero bytes, keeping array lengths
+    Zero(Vec<String>),
+    /// Set the fields to a value, or strings to a stable pseudonym like `anon_1` without one
+    Replace(Vec<String>, Option<String>),
+}
+
+/// A redaction rule like `drop /camera/*`, `zero /gps/fix:latitude,longitude`
+/// or `replace frame_id`. Without topics a rule applies to all topics.
+/// A field matches by its path or any trailing part of it, `frame_id` matches `header.frame_id`,
+/// and array elements are matched through the array, `points.x` matches the x of every point.
+#[derive(Debug, Clone)]
+pub struct RedactRule {
+    pub topics: TopicSelection,
+    pub action: RedactAction,
+    source: String,
+}
+
+impl RedactRule {
+    pub fn parse(rule: &str) -> Result<Self> {
+        let (action, args) = rule.trim().split_once(' ').unwrap_or((rule.trim(), ""));
+        let args = args.trim();
+        let parse_fields = |args: &str| -> Result<(TopicSelection, Vec<String>)> {
+            let (topics, fields) = match args.rsplit_once(':') {
+                Some((topics, fields)) => (TopicSelection::parse(&[topics])?, fields),
+                None => (TopicSelection::all(), args),
+            };
+            let fields: Vec<String> = fields
+                .split(',')
+                .map(|field| field.trim().to_string())
+                .filter(|field| !field.is_empty())
+                .collect();
+            if fields.is_empty() {
+                bail!("Rule '{}' names no fields", rule);
+            }
+            Ok((topics, fields))
+        };
+
+        let (topics, action) = match action {
+            "drop" if !args.is_empty() => (TopicSelection::parse(&[args])?, RedactAction::Drop),
+            "drop" => bail!("Rule '{}' names no topics", rule),
+            "zero" => {
+                let (topics, fields) = parse_fields(args)?;
+                (topics, RedactAction::Zero(fields))
+            }
+            "replace" => {
+                let (fields, value) = match args.split_once('=') {
+                    Some((fields, value)) => (fields, Some(value.to_string())),
+                    None => (args, None),
+                };
+                let (topics, fields) = parse_fields(fields)?;
+                (topics, RedactAction::Replace(fields, value))
+            }
+            _ => bail!(
+                "Invalid rule '{}', expected drop TOPICS, zero [TOPICS:]FIELDS or replace [TOPICS:]FIELDS[=VALUE]",
+                rule
+            ),
+        };
+        Ok(Self {
+            topics,
+            action,
+            source: rule.trim().to_string(),
+        })
+    }
+
+    /// Parse one rule per line, skipping empty lines and `#` comments
+    pub fn parse_lines(lines: &str) -> Result<Vec<Self>> {
+        lines
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Self::parse)
+            .collect()
+    }
+}
+
+impl fmt::Display for RedactRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RedactReport {
+    /// Messages written, changed or not
+    pub messages: u64,
+    /// Dropped messages per topic
+    pub dropped: BTreeMap<String, u64>,
+    /// Changed values per topic and field path
+    pub changed: BTreeMap<(String, String), u64>,
+    /// Rules that matched no topic, or no field of any message, likely a typo
+    pub unmatched: Vec<String>,
+}
+
+/// Applies redaction rules to messages, keeping their definitions and md5sums
+pub struct Redactor {
+    rules: Vec<RedactRule>,
+    /// Whether each rule has matched a topic to drop or a field to change
+    matched: Vec<bool>,
+    decoders: BTreeMap<u32, MessageDecoder>,
+    pseudonyms: BTreeMap<String, String>,
+    report: RedactReport,
+}
+
+impl Redactor {
+    pub fn new(rules: Vec<RedactRule>) -> Self {
+        Self {
+            matched: vec![false; rules.len()],
+            rules,
+            decoders: BTreeMap::new(),
+            pseudonyms: BTreeMap::new(),
+            report: RedactReport::default(),
+        }
+    }
+
+    /// Whether a drop rule applies to the topic
+    pub fn drops(&self, topic: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.action == RedactAction::Drop && rule.topics.matches(topic))
+    }
+
+    /// The redacted message, None if it is dropped, borrowed if unchanged
+    pub fn redact<'a>(
+        &mut self,
+        connection: &Connection,
+        data: &'a [u8],
+    ) -> Result<Option<Cow<'a, [u8]>>> {
+        if self.drops(&connection.topic) {
+            for (rule, matched) in self.rules.iter().zip(self.matched.iter_mut()) {
+                *matched |=
+                    rule.action == RedactAction::Drop && rule.topics.matches(&connection.topic);
+            }
+            *self
+                .report
+                .dropped
+                .entry(connection.topic.clone())
+                .or_default() += 1;
+            return Ok(None);
+        }
+        let rules: Vec<(usize, &RedactRule)> = self
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| {
+                rule.action != RedactAction::Drop && rule.topics.matches(&connection.topic)
+            })
+            .collect();
+        if rules.is_empty() {
+            return Ok(Some(Cow::Borrowed(data)));
+        }
+
+        let decoder = match self.decoders.entry(connection.id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(MessageDecoder::for_connection(connection)?),
+        };
+        let mut message = decoder.decode(data)?;
+        let mut changed = vec![];
+        let mut path = vec![];
+        for (index, rule) in rules {
+            self.matched[index] |= redact_value(
+                &mut message,
+                &mut path,
+                &rule.action,
+                &mut self.pseudonyms,
+                &mut changed,
+            )
+            .with_context(|| format!("Cannot apply '{}' to {}", rule, connection.topic))?;
+        }
+        if changed.is_empty() {
+            return Ok(Some(Cow::Borrowed(data)));
+        }
+        for path in changed {
+            *self
+                .report
+                .changed
+                .entry((connection.topic.clone(), path))
+                .or_default() += 1;
+        }
+        Ok(Some(Cow::Owned(decoder.encode(&message)?)))
+    }
+
+    pub fn into_report(self) -> RedactReport {
+        let unmatched = self
+            .rules
+            .iter()
+            .zip(self.matched)
+            .filter(|(_, matched)| !matched)
+            .map(|(rule, _)| rule.to_string())
+            .collect();
+        RedactReport {
+            unmatched,
+            ..self.report
+        }
+    }
+}
+
+/// Whether the field names of `path` end with the names of `field`
+fn matches_field(path: &[String], field: &str) -> bool {
+    let names: Vec<&str> = field.split('.').collect();
+    path.len() >= names.len() && path[path.len() - names.len()..].iter().eq(names.iter())
+}
+
+/// Apply `action` to every matching field below `value`, whose field path is `path`.
+/// Returns whether any field matched, changed or not.
+fn redact_value(
+    value: &mut Value,
+    path: &mut Vec<String>,
+    action: &RedactAction,
+    pseudonyms: &mut BTreeMap<String, String>,
+    changed: &mut Vec<String>,
+) -> Result<bool> {
+    let fields = match action {
+        RedactAction::Drop => return Ok(false),
+        RedactAction::Zero(fields) | RedactAction::Replace(fields, _) => fields,
+    };
+    if !path.is_empty() && fields.iter().any(|field| matches_field(path, field)) {
+        let before = value.clone();
+        match action {
+            RedactAction::Zero(_) => zero(value),
+            RedactAction::Replace(_, replacement) => {
+                replace(value, replacement.as_deref(), pseudonyms)?
+            }
+            RedactAction::Drop => unreachable!(),
+        }
+        if *value != before {
+            changed.push(path.join("."));
+        }
+        return Ok(true);
+    }
+    let mut matched = false;
+    match value {
+        Value::Message(values) => {
+            for (name, value) in values.iter_mut() {
+                path.push(name.clone());
+                matched |= redact_value(value, path, action, pseudonyms, changed)?;
+                path.pop();
+            }
+        }
+        Value::Array(values) => {
+            for value in values.iter_mut() {
+                matched |= redact_value(value, path, action, pseudonyms, changed)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(matched)
+}
+
+fn zero(value: &mut Value) {
+    match value {
+        Value::Bool(value) => *value = false,
+        Value::Int(value) => *value = 0,
+        Value::UInt(value) => *value = 0,
+        Value::Float(value) => *value = 0.0,
+        Value::String(value) => value.clear(),
+        Value::Time(value) => *value = 0,
+        Value::Duration(value) => *value = 0,
+        Value::Bytes(bytes) => bytes.fill(0),
+        Value::Array(values) => values.iter_mut().for_each(zero),
+        Value::Message(values) => values.iter_mut().for_each(|(_, value)| zero(value)),
+    }
+}
+
+fn replace(
+    value: &mut Value,
+    replacement: Option<&str>,
+    pseudonyms: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    let Some(replacement) = replacement else {
+        let Value::String(string) = value else {
+            bail!("Only strings can be replaced without a value");
+        };
+        if !string.is_empty() {
+            let next = pseudonyms.len() + 1;
+            *string = pseudonyms
+                .entry(string.clone())
+                .or_insert_with(|| format!("anon_{}", next))
+                .clone();
+        }
+        return Ok(());
+    };
+    let invalid = || format!("Cannot replace {:?} with '{}'", value, replacement);
+    *value = match value {
+        Value::String(_) => Value::String(replacement.to_string()),
+        Value::Bool(_) => Value::Bool(replacement.parse().with_context(invalid)?),
+        Value::Int(_) => Value::Int(replacement.parse().with_context(invalid)?),
+        Value::UInt(_) => Value::UInt(replacement.parse().with_context(invalid)?),
+        Value::Float(_) => Value::Float(replacement.parse().with_context(invalid)?),
+        Value::Array(values) => {
+            for value in values.iter_mut() {
+                replace(value, Some(replacement), pseudonyms)?;
+            }
+            return Ok(());
+        }
+        _ => bail!(invalid()),
+    };
+    Ok(())
+}
+
+/// Copy the messages of `source` to `writer` with `redactor` applied.
+/// Connections of dropped topics are left out.
+pub fn redact_messages<W: Write + Seek>(
+    source: MessageSource,
+    writer: &mut BagWriter<W>,
+    mut redactor: Redactor,
+) -> Result<RedactReport> {
+    let mut conn_ids = BTreeMap::new();
+    let mut messages = 0;
+    source.read_messages(&TopicSelection::all(), |connection, time, data| {
+        let Some(data) = redactor.redact(connection, data)? else {
+            return Ok(());
+        };
+        let conn_id = *conn_ids
+            .entry(connection.id)
+            .or_insert_with(|| writer.add_connection(connection));
+        writer.write_message(conn_id, time, &data)?;
+        messages += 1;
+        Ok(())
+    })?;
+    Ok(RedactReport {
+        messages,
+        ..redactor.into_report()
+    })
+}
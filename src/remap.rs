@@ -0,0 +1,177 @@
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Seek, Write};
+
+use anyhow::{bail, Context, Result};
+use regex::{NoExpand, Regex};
+
+use crate::indexing::MessageSource;
+use crate::message_decoder::{HeaderDecoders, Value};
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+use crate::writer::BagWriter;
+
+/// Renames a topic, parsed from `/old:=/new` like ROS remapping arguments.
+/// With `re:^/camera/(.*):=/cam/$1` the left side is a regular expression
+/// and the right side may refer to its capture groups.
+#[derive(Debug, Clone)]
+pub struct TopicRule {
+    pattern: Regex,
+    replacement: String,
+    is_regex: bool,
+    source: String,
+}
+
+impl TopicRule {
+    pub fn parse(rule: &str) -> Result<Self> {
+        let Some((from, to)) = rule.split_once(":=") else {
+            bail!("Invalid remapping '{}', expected /old:=/new", rule);
+        };
+        let (pattern, is_regex) = match from.strip_prefix("re:") {
+            Some(regex) => (
+                Regex::new(regex).with_context(|| format!("Invalid topic regex '{}'", regex))?,
+                true,
+            ),
+            None => (Regex::new(&format!("^{}$", regex::escape(from)))?, false),
+        };
+        Ok(Self {
+            pattern,
+            replacement: to.to_string(),
+            is_regex,
+            source: rule.to_string(),
+        })
+    }
+
+    /// The new name of `topic`, None if the rule does not apply
+    pub fn apply(&self, topic: &str) -> Option<String> {
+        if !self.pattern.is_match(topic) {
+            return None;
+        }
+        let renamed = if self.is_regex {
+            self.pattern.replace(topic, self.replacement.as_str())
+        } else {
+            self.pattern.replace(topic, NoExpand(&self.replacement))
+        };
+        Some(renamed.into_owned())
+    }
+}
+
+impl fmt::Display for TopicRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Parse a `frame_id` rename `old:=new`, frame ids are matched exactly
+pub fn parse_frame_rule(rule: &str) -> Result<(String, String)> {
+    match rule.split_once(":=") {
+        Some((from, to)) => Ok((from.to_string(), to.to_string())),
+        None => bail!("Invalid frame remapping '{}', expected old:=new", rule),
+    }
+}
+
+/// Renames topics and the `frame_id` of headers while copying messages.
+/// Types, md5sums and definitions of the connections stay the same.
+pub struct Remapper {
+    /// The first matching rule renames a topic
+    topics: Vec<TopicRule>,
+    frames: BTreeMap<String, String>,
+    decoders: HeaderDecoders,
+}
+
+impl Remapper {
+    pub fn new(topics: Vec<TopicRule>, frames: BTreeMap<String, String>) -> Self {
+        Self {
+            topics,
+            frames,
+            decoders: HeaderDecoders::default(),
+        }
+    }
+
+    pub fn topic(&self, topic: &str) -> String {
+        self.topics
+            .iter()
+            .find_map(|rule| rule.apply(topic))
+            .unwrap_or_else(|| topic.to_string())
+    }
+
+    pub fn connection(&self, connection: &Connection) -> Connection {
+        Connection {
+            topic: self.topic(&connection.topic),
+            ..connection.clone()
+        }
+    }
+
+    /// The message with renamed frame ids, borrowed if nothing changed
+    pub fn message<'a>(
+        &mut self,
+        connection: &Connection,
+        data: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>> {
+        if self.frames.is_empty() {
+            return Ok(Cow::Borrowed(data));
+        }
+        let Some(decoder) = self.decoders.get(connection) else {
+            return Ok(Cow::Borrowed(data));
+        };
+
+        let mut message = decoder.decode(data)?;
+        let mut renamed = false;
+        decoder.visit_headers(&mut message, |header| {
+            if let Some(Value::String(frame_id)) = header.get_mut("frame_id") {
+                if let Some(new) = self.frames.get(frame_id.as_str()) {
+                    *frame_id = new.clone();
+                    renamed = true;
+                }
+            }
+        });
+        if renamed {
+            Ok(Cow::Owned(decoder.encode(&message)?))
+        } else {
+            Ok(Cow::Borrowed(data))
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RemapReport {
+    pub messages: u64,
+    /// Old and new name of every renamed topic
+    pub topics: BTreeMap<String, String>,
+    /// Messages with at least one renamed frame id
+    pub frames: u64,
+}
+
+/// Copy all messages of `source` to `writer`, renamed by `remapper`
+pub fn remap_messages<W: Write + Seek>(
+    source: MessageSource,
+    writer: &mut BagWriter<W>,
+    remapper: &mut Remapper,
+) -> Result<RemapReport> {
+    let mut report = RemapReport::default();
+    let mut conn_ids = BTreeMap::new();
+    source.read_messages(&TopicSelection::all(), |connection, time, data| {
+        let conn_id = match conn_ids.entry(connection.id) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                let remapped = remapper.connection(connection);
+                if remapped.topic != connection.topic {
+                    report
+                        .topics
+                        .insert(connection.topic.clone(), remapped.topic.clone());
+                }
+                *entry.insert(writer.add_connection(&remapped))
+            }
+        };
+        let data = remapper.message(connection, data)?;
+        if matches!(data, Cow::Owned(_)) {
+            report.frames += 1;
+        }
+        writer.write_message(conn_id, time, &data)?;
+        report.messages += 1;
+        Ok(())
+    })?;
+    Ok(report)
+}
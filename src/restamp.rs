@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{Seek, Write};
+
+use anyhow::{Context, Result};
+
+use crate::indexing::MessageSource;
+use crate::message_decoder::{HeaderDecoders, Value};
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+use crate::writer::BagWriter;
+
+#[derive(Debug, Clone, Default)]
+pub struct RestampOptions {
+    /// Added to all record times, in nanoseconds
+    pub offset: i64,
+    /// Shift the stamps of all headers inside the messages by the offset as well.
+    /// Zero stamps mean unset and are left alone.
+    pub shift_headers: bool,
+    /// Replace the record time of messages that start with a header by their `header.stamp`,
+    /// as written, i.e. after `shift_headers`
+    pub use_header_time: bool,
+}
+
+/// Changes record times and header stamps of messages
+pub struct Restamper {
+    options: RestampOptions,
+    decoders: HeaderDecoders,
+}
+
+impl Restamper {
+    pub fn new(options: RestampOptions) -> Self {
+        Self {
+            options,
+            decoders: HeaderDecoders::default(),
+        }
+    }
+
+    /// New record time and data of a message, the data is borrowed if unchanged
+    pub fn restamp<'a>(
+        &mut self,
+        connection: &Connection,
+        time: u64,
+        data: &'a [u8],
+    ) -> Result<(u64, Cow<'a, [u8]>)> {
+        let offset = self.options.offset;
+        let time = shift_time(time, offset)?;
+        let shift_headers = self.options.shift_headers && offset != 0;
+        if !shift_headers && !self.options.use_header_time {
+            return Ok((time, Cow::Borrowed(data)));
+        }
+
+        let Some(decoder) = self.decoders.get(connection) else {
+            return Ok((time, Cow::Borrowed(data)));
+        };
+
+        let mut data = Cow::Borrowed(data);
+        if shift_headers {
+            let mut message = decoder.decode(&data)?;
+            let mut result = Ok(());
+            decoder.visit_headers(&mut message, |header| {
+                if let Some(Value::Time(stamp)) = header.get_mut("stamp") {
+                    if *stamp != 0 && result.is_ok() {
+                        result = shift_time(*stamp, offset).map(|shifted| *stamp = shifted);
+                    }
+                }
+            });
+            result.context("Cannot shift header stamp")?;
+            data = Cow::Owned(decoder.encode(&message)?);
+        }
+        if self.options.use_header_time {
+            if let Some(stamp) = decoder.header_stamp(&data)?.filter(|&stamp| stamp != 0) {
+                return Ok((stamp, data));
+            }
+        }
+        Ok((time, data))
+    }
+}
+
+/// Add a signed offset to a time, failing before the epoch or beyond the range of bag times
+pub fn shift_time(time: u64, offset: i64) -> Result<u64> {
+    time.checked_add_signed(offset)
+        // Bag times are 32 bit seconds and nanoseconds
+        .filter(|&shifted| shifted / 1_000_000_000 <= u32::MAX as u64)
+        .with_context(|| format!("Shifting time {} by {} ns is out of range", time, offset))
+}
+
+#[derive(Debug, Default)]
+pub struct RestampReport {
+    pub messages: u64,
+    /// Messages written with a record time that differs from the shifted one,
+    /// because `use_header_time` replaced it
+    pub header_times: u64,
+}
+
+/// Copy all messages of `source` to `writer` with times changed by `restamper`.
+/// Messages keep their order in the file even if their new times are out of order.
+pub fn restamp_messages<W: Write + Seek>(
+    source: MessageSource,
+    writer: &mut BagWriter<W>,
+    restamper: &mut Restamper,
+) -> Result<RestampReport> {
+    let offset = restamper.options.offset;
+    let mut report = RestampReport::default();
+    let mut conn_ids = BTreeMap::new();
+    source.read_messages(&TopicSelection::all(), |connection, time, data| {
+        let conn_id = *conn_ids
+            .entry(connection.id)
+            .or_insert_with(|| writer.add_connection(connection));
+        let (new_time, data) = restamper.restamp(connection, time, data)?;
+        if new_time != shift_time(time, offset)? {
+            report.header_times += 1;
+        }
+        writer.write_message(conn_id, new_time, &data)?;
+        report.messages += 1;
+        Ok(())
+    })?;
+    Ok(report)
+}
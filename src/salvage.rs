@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::Result;
+use byteorder::{ByteOrder, LE};
+use memmap2::Mmap;
+
+use crate::cursor::Cursor;
+use crate::record::{
+    decompress_into, read_record, Connection, Header, Record, RecordError, BAG_MAGIC,
+    OP_BAG_HEADER, OP_CHUNK, OP_CHUNK_INFO, OP_CONNECTION, OP_INDEX_DATA, OP_MESSAGE_DATA,
+};
+use crate::writer::BagWriter;
+
+#[derive(Debug)]
+pub struct SkippedRecord {
+    /// Offset of the record in the bag, or of the chunk that contains it
+    pub offset: u64,
+    /// Offset inside the decompressed chunk data if the record is part of a chunk
+    pub chunk_offset: Option<u64>,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct SalvageReport {
+    pub connections: usize,
+    pub messages: u64,
+    pub skipped: Vec<SkippedRecord>,
+}
+
+struct Salvager<F> {
+    connections: BTreeMap<u32, Connection>,
+    report: SalvageReport,
+    on_message: F,
+}
+
+/// Read everything that can be parsed from `bytes` and skip what is corrupt.
+/// After a bad record, reading continues at the next valid record header.
+/// `on_message` is called for every recovered message in file order.
+pub fn salvage<F>(bytes: &[u8], on_message: F) -> Result<SalvageReport>
+where
+    F: FnMut(&Connection, u64, &[u8]) -> Result<()>,
+{
+    let mut salvager = Salvager {
+        connections: BTreeMap::new(),
+        report: SalvageReport::default(),
+        on_message,
+    };
+
+    let start = if bytes.starts_with(BAG_MAGIC) {
+        salvager.preload_connections(bytes);
+        BAG_MAGIC.len() as u64
+    } else {
+        salvager.skip(0, None, "missing bag magic".to_string());
+        0
+    };
+    salvager.scan(bytes, start, None)?;
+
+    salvager.report.connections = salvager.connections.len();
+    Ok(salvager.report)
+}
+
+/// Salvage a bag and optionally write the recovered messages to `output`
+pub fn salvage_bag(input: &Path, output: Option<&Path>) -> Result<SalvageReport> {
+    let file = File::open(input)?;
+    // The file is only ever read, same as `Bag::open`, which needs an intact bag
+    let bytes = unsafe { Mmap::map(&file)? };
+    match output {
+        Some(output) => {
+            let mut writer = BagWriter::create(output)?;
+            let mut conn_ids = BTreeMap::new();
+            let report = salvage(&bytes, |connection, time, data| {
+                let conn_id = *conn_ids
+                    .entry(connection.id)
+                    .or_insert_with(|| writer.add_connection(connection));
+                writer.write_message(conn_id, time, data)?;
+                Ok(())
+            })?;
+            writer.finish()?;
+            Ok(report)
+        }
+        None => salvage(&bytes, |_, _, _| Ok(())),
+    }
+}
+
+impl<F> Salvager<F>
+where
+    F: FnMut(&Connection, u64, &[u8]) -> Result<()>,
+{
+    fn skip(&mut self, offset: u64, chunk_offset: Option<u64>, reason: String) {
+        self.report.skipped.push(SkippedRecord {
+            offset,
+            chunk_offset,
+            reason,
+        });
+    }
+
+    /// Connections from an intact index section make messages readable
+    /// even if the connection records inside the chunks are lost
+    fn preload_connections(&mut self, bytes: &[u8]) {
+        let index_pos = match read_record(bytes, BAG_MAGIC.len() as u64) {
+            Ok(record) if record.op == OP_BAG_HEADER => match record.header.u64("index_pos") {
+                Ok(index_pos) if index_pos > 0 => index_pos,
+                _ => return,
+            },
+            _ => return,
+        };
+
+        let mut pos = index_pos;
+        while let Ok(record) = read_record(bytes, pos) {
+            if record.op == OP_CONNECTION {
+                if let Ok(connection) = Connection::from_record(&record) {
+                    self.connections.insert(connection.id, connection);
+                }
+            }
+            pos += record.len;
+        }
+    }
+
+    /// `chunk` is the offset of the chunk record when scanning decompressed chunk data
+    fn scan(&mut self, bytes: &[u8], mut pos: u64, chunk: Option<u64>) -> Result<()> {
+        while pos < bytes.len() as u64 {
+            match read_record(bytes, pos).and_then(validate) {
+                Ok(record) => {
+                    pos += record.len;
+                    self.handle(record, chunk)?;
+                }
+                Err(e) => {
+                    if chunk.is_none() {
+                        if let Some((compression, data)) = truncated_chunk(bytes, pos) {
+                            self.skip(pos, None, format!("truncated chunk: {}", e));
+                            self.scan_chunk(compression, data, pos)?;
+                            break;
+                        }
+                    }
+                    match chunk {
+                        Some(chunk) => self.skip(chunk, Some(pos), e.to_string()),
+                        None => self.skip(pos, None, e.to_string()),
+                    }
+                    pos = resync(bytes, pos + 1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, record: Record, chunk: Option<u64>) -> Result<()> {
+        match record.op {
+            OP_CONNECTION => {
+                let connection = Connection::from_record(&record)?;
+                self.connections.insert(connection.id, connection);
+            }
+            OP_MESSAGE_DATA => {
+                let conn_id = record.header.u32("conn")?;
+                let time = record.header.time("time")?;
+                match self.connections.get(&conn_id) {
+                    Some(connection) => {
+                        (self.on_message)(connection, time, record.data)?;
+                        self.report.messages += 1;
+                    }
+                    None => {
+                        let reason = format!("message on unknown connection {}", conn_id);
+                        match chunk {
+                            Some(chunk) => self.skip(chunk, Some(record.offset), reason),
+                            None => self.skip(record.offset, None, reason),
+                        }
+                    }
+                }
+            }
+            OP_CHUNK => {
+                let compression = record.header.str("compression")?;
+                self.scan_chunk(compression, record.data, record.offset)?;
+            }
+            // The bag header, index data and chunk info records are rebuilt when writing
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn scan_chunk(&mut self, compression: &str, data: &[u8], offset: u64) -> Result<()> {
+        let mut buffer = vec![];
+        // Whatever was decompressed before an error is still worth scanning
+        if let Err(e) = decompress_into(compression, data, &mut buffer) {
+            self.skip(offset, None, e.to_string());
+        }
+        self.scan(&buffer, 0, Some(offset))
+    }
+}
+
+/// Check that a record carries the header fields its op requires
+fn validate(record: Record) -> Result<Record, RecordError> {
+    match record.op {
+        OP_MESSAGE_DATA => {
+            record.header.u32("conn")?;
+            record.header.time("time")?;
+        }
+        OP_BAG_HEADER => {
+            record.header.u64("index_pos")?;
+            record.header.u32("conn_count")?;
+            record.header.u32("chunk_count")?;
+        }
+        OP_INDEX_DATA => {
+            record.header.u32("ver")?;
+            record.header.u32("conn")?;
+            record.header.u32("count")?;
+        }
+        OP_CHUNK => {
+            record.header.str("compression")?;
+            record.header.u32("size")?;
+        }
+        OP_CHUNK_INFO => {
+            record.header.u32("ver")?;
+            record.header.u64("chunk_pos")?;
+            record.header.time("start_time")?;
+            record.header.time("end_time")?;
+            record.header.u32("count")?;
+        }
+        OP_CONNECTION => {
+            Connection::from_record(&record)?;
+        }
+        _ => return Err(RecordError::UnknownOp(record.op)),
+    }
+    Ok(record)
+}
+
+/// Record headers hold a few short fields, a longer one at a candidate position is garbage
+const MAX_HEADER_LEN: usize = 64 * 1024;
+
+/// Find the next position at which a valid record starts.
+/// Only candidates with a plausible header length are parsed, so that each position costs
+/// at most `MAX_HEADER_LEN` and a corrupt region is scanned in linear time.
+fn resync(bytes: &[u8], from: u64) -> u64 {
+    (from..bytes.len() as u64)
+        .find(|&pos| {
+            let pos = pos as usize;
+            bytes.len() - pos >= 4
+                && LE::read_u32(&bytes[pos..pos + 4]) as usize <= MAX_HEADER_LEN
+                && read_record(bytes, pos as u64).and_then(validate).is_ok()
+        })
+        .unwrap_or(bytes.len() as u64)
+}
+
+/// A chunk whose data runs past the end of the file, e.g. from an interrupted recording
+fn truncated_chunk(bytes: &[u8], pos: u64) -> Option<(&str, &[u8])> {
+    let mut cursor = Cursor::new(bytes);
+    cursor.seek(pos).ok()?;
+    let header = Header::parse(cursor.next_chunk().ok()?).ok()?;
+    if header.op().ok()? != OP_CHUNK {
+        return None;
+    }
+    let compression = header.str("compression").ok()?;
+    let data_len = cursor.next_u32().ok()? as u64;
+    if data_len <= cursor.left() {
+        return None;
+    }
+    Some((compression, cursor.next_bytes(cursor.left()).ok()?))
+}
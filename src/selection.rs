@@ -0,0 +1,104 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// A set of topic selectors.
+///
+/// - `/odom` matches exactly that topic
+/// - `/camera/*/image_raw` is a glob, `*` and `?` stay within one name segment,
+///   `**` spans segments and `{a,b}` matches alternatives
+/// - `re:^/perception/.*` is a regular expression
+/// - a leading `!` excludes the topics a selector matches
+///
+/// Without any including selector all topics are selected.
+#[derive(Debug, Clone, Default)]
+pub struct TopicSelection {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    selectors: Vec<String>,
+}
+
+impl TopicSelection {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn parse<S: AsRef<str>>(selectors: &[S]) -> Result<Self> {
+        let mut selection = Self::default();
+        for selector in selectors {
+            let selector = selector.as_ref();
+            let (exclude, pattern) = match selector.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, selector),
+            };
+            let regex = match pattern.strip_prefix("re:") {
+                Some(regex) => {
+                    Regex::new(regex).with_context(|| format!("Invalid topic regex '{}'", regex))?
+                }
+                None => glob_to_regex(pattern)?,
+            };
+            if exclude {
+                selection.exclude.push(regex);
+            } else {
+                selection.include.push(regex);
+            }
+            selection.selectors.push(selector.to_string());
+        }
+        Ok(selection)
+    }
+
+    pub fn matches(&self, topic: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(topic)))
+            && !self.exclude.iter().any(|re| re.is_match(topic))
+    }
+
+    /// The selected topics out of `topics`, e.g. the keys of `get_topics`
+    pub fn resolve<'a, I>(&self, topics: I) -> Vec<&'a str>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        topics
+            .into_iter()
+            .filter(|topic| self.matches(topic))
+            .collect()
+    }
+}
+
+impl fmt::Display for TopicSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.selectors.is_empty() {
+            write!(f, "all topics")
+        } else {
+            write!(f, "{}", self.selectors.join(" "))
+        }
+    }
+}
+
+fn glob_to_regex(glob: &str) -> Result<Regex> {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    let mut in_alternatives = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '{' if !in_alternatives => {
+                in_alternatives = true;
+                re.push_str("(?:");
+            }
+            ',' if in_alternatives => re.push('|'),
+            '}' if in_alternatives => {
+                in_alternatives = false;
+                re.push(')');
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).with_context(|| format!("Invalid topic pattern '{}'", glob))
+}
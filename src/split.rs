@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::indexing::MessageSource;
+use crate::record::Connection;
+use crate::selection::TopicSelection;
+use crate::writer::BagWriter;
+
+/// When to start the next part
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitCriterion {
+    /// Record time span of a part in nanoseconds
+    Duration(u64),
+    /// Size of a part in bytes, parts end up slightly larger by their index section
+    Size(u64),
+    /// Messages per part
+    Count(u64),
+    /// Start a new part at every message on this topic
+    OnTopic(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SplitPart {
+    /// Messages of the input, without the re-emitted latched messages
+    pub messages: u64,
+    /// Latched messages of earlier parts written again at the start of this part
+    pub latched: u64,
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+struct Part<W: Write + Seek> {
+    writer: BagWriter<W>,
+    // Input connection id to the connection id of this part
+    conn_ids: BTreeMap<u32, u32>,
+    report: SplitPart,
+}
+
+impl<W: Write + Seek> Part<W> {
+    fn write(&mut self, connection: &Connection, time: u64, data: &[u8]) -> Result<()> {
+        let writer = &mut self.writer;
+        let conn_id = *self
+            .conn_ids
+            .entry(connection.id)
+            .or_insert_with(|| writer.add_connection(connection));
+        writer.write_message(conn_id, time, data)?;
+        Ok(())
+    }
+}
+
+struct Splitter<'a, W: Write + Seek, F> {
+    criterion: &'a SplitCriterion,
+    create_part: F,
+    part: Option<Part<W>>,
+    // Last message of every latched connection, by input connection id
+    latched: BTreeMap<u32, (Connection, Vec<u8>)>,
+    parts: Vec<SplitPart>,
+}
+
+impl<W, F> Splitter<'_, W, F>
+where
+    W: Write + Seek,
+    F: FnMut(usize) -> Result<BagWriter<W>>,
+{
+    fn add(&mut self, connection: &Connection, time: u64, data: &[u8]) -> Result<()> {
+        if self.part_is_full(connection, time, data.len()) {
+            self.finish_part()?;
+        }
+        if self.part.is_none() {
+            self.part = Some(self.start_part(connection, time)?);
+        }
+        let part = self.part.as_mut().unwrap();
+        part.write(connection, time, data)?;
+        part.report.messages += 1;
+        part.report.end_time = part.report.end_time.max(time);
+
+        if connection.latching {
+            self.latched
+                .insert(connection.id, (connection.clone(), data.to_vec()));
+        }
+        Ok(())
+    }
+
+    /// Whether the message belongs into the next part, a part gets at least one message
+    fn part_is_full(&self, connection: &Connection, time: u64, len: usize) -> bool {
+        let Some(part) = &self.part else {
+            return false;
+        };
+        if part.report.messages == 0 {
+            return false;
+        }
+        match self.criterion {
+            SplitCriterion::Duration(duration) => {
+                time >= part.report.start_time.saturating_add(*duration)
+            }
+            SplitCriterion::Size(size) => part.writer.size() + len as u64 > *size,
+            SplitCriterion::Count(count) => part.report.messages >= *count,
+            SplitCriterion::OnTopic(topic) => connection.topic == *topic,
+        }
+    }
+
+    /// Open the next part and write the latched messages seen so far at its start time
+    fn start_part(&mut self, connection: &Connection, time: u64) -> Result<Part<W>> {
+        let mut part = Part {
+            writer: (self.create_part)(self.parts.len())?,
+            conn_ids: BTreeMap::new(),
+            report: SplitPart {
+                start_time: time,
+                end_time: time,
+                ..Default::default()
+            },
+        };
+        for (latched_connection, data) in self.latched.values() {
+            // Replaced by the message that starts the part
+            if latched_connection.id == connection.id {
+                continue;
+            }
+            part.write(latched_connection, time, data)?;
+            part.report.latched += 1;
+        }
+        Ok(part)
+    }
+
+    fn finish_part(&mut self) -> Result<()> {
+        if let Some(part) = self.part.take() {
+            part.writer.finish()?;
+            self.parts.push(part.report);
+        }
+        Ok(())
+    }
+}
+
+/// Split the messages of `source` into parts, in file order.
+/// `create_part` is called with the index of every new part.
+/// Every part is a complete bag with the connections of its messages.
+pub fn split<W, F>(
+    source: MessageSource,
+    criterion: &SplitCriterion,
+    create_part: F,
+) -> Result<Vec<SplitPart>>
+where
+    W: Write + Seek,
+    F: FnMut(usize) -> Result<BagWriter<W>>,
+{
+    if matches!(
+        criterion,
+        SplitCriterion::Duration(0) | SplitCriterion::Size(0) | SplitCriterion::Count(0)
+    ) {
+        bail!("Cannot split into parts of zero duration, size or messages");
+    }
+    let mut splitter = Splitter {
+        criterion,
+        create_part,
+        part: None,
+        latched: BTreeMap::new(),
+        parts: vec![],
+    };
+    source.read_messages(&TopicSelection::all(), |connection, time, data| {
+        splitter.add(connection, time, data)
+    })?;
+    splitter.finish_part()?;
+    Ok(splitter.parts)
+}
+
+/// Path of a part like rosbag names split bags, `out.bag` becomes `out_0.bag`, `out_1.bag`, ...
+pub fn part_path(output: &Path, index: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{}_{}.bag", stem, index))
+}
+
+/// Parse a size like `500000`, `512KiB`, `100MB` or `1GiB` into bytes
+pub fn parse_size(s: &str) -> Result<u64> {
+    let units = [
+        ("KiB", 1 << 10),
+        ("MiB", 1 << 20),
+        ("GiB", 1 << 30),
+        ("KB", 1_000),
+        ("MB", 1_000_000),
+        ("GB", 1_000_000_000),
+        ("B", 1),
+    ];
+    let (number, factor) = units
+        .iter()
+        .find_map(|&(unit, factor)| s.strip_suffix(unit).map(|number| (number, factor)))
+        .unwrap_or((s, 1));
+    let size = number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|size| size.is_finite() && *size >= 0.0);
+    match size {
+        Some(size) => Ok((size * factor as f64) as u64),
+        None => bail!(
+            "Invalid size '{}', expected bytes or a number with unit KiB, MiB, GiB, KB, MB or GB",
+            s
+        ),
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::time::NANOS_PER_SEC;
+
+/// When the time between two consecutive messages counts as a gap
+#[derive(Debug, Clone, Default)]
+pub struct GapCriteria {
+    /// Periods longer than this many nanoseconds
+    pub max_period: Option<u64>,
+    /// Periods longer than this multiple of the median period
+    pub median_factor: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Gap {
+    /// Record time of the last message before the gap
+    pub start_time: u64,
+    /// Record time of the first message after the gap
+    pub end_time: u64,
+    pub duration: f64,
+}
+
+/// Timing of the messages on one topic, frequencies in Hz and periods in seconds
+#[derive(Debug, Clone, Serialize)]
+pub struct RateStats {
+    pub topic: String,
+    pub message_count: usize,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    pub mean_frequency: Option<f64>,
+    /// From the longest period
+    pub min_frequency: Option<f64>,
+    /// From the shortest period, none if two messages share a record time
+    pub max_frequency: Option<f64>,
+    pub median_period: Option<f64>,
+    /// Standard deviation of the periods
+    pub jitter: Option<f64>,
+    pub longest_gap: Option<Gap>,
+    pub gaps: Vec<Gap>,
+}
+
+impl RateStats {
+    /// `times` are the sorted record times of the messages
+    pub fn new(topic: &str, times: &[u64], criteria: &GapCriteria) -> Self {
+        let periods: Vec<u64> = times.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        let seconds = |nanos: u64| nanos as f64 / NANOS_PER_SEC as f64;
+        let gap = |i: usize| Gap {
+            start_time: times[i],
+            end_time: times[i + 1],
+            duration: seconds(periods[i]),
+        };
+
+        let mut stats = Self {
+            topic: topic.to_string(),
+            message_count: times.len(),
+            start_time: times.first().copied(),
+            end_time: times.last().copied(),
+            mean_frequency: None,
+            min_frequency: None,
+            max_frequency: None,
+            median_period: None,
+            jitter: None,
+            longest_gap: None,
+            gaps: vec![],
+        };
+        if periods.is_empty() {
+            return stats;
+        }
+
+        let duration = seconds(times[times.len() - 1] - times[0]);
+        if duration > 0.0 {
+            stats.mean_frequency = Some(periods.len() as f64 / duration);
+        }
+        let (longest, max_period) = periods
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by_key(|(_, period)| *period)
+            .unwrap();
+        let min_period = periods.iter().copied().min().unwrap();
+        stats.min_frequency = (max_period > 0).then(|| 1.0 / seconds(max_period));
+        stats.max_frequency = (min_period > 0).then(|| 1.0 / seconds(min_period));
+
+        let mut sorted = periods.clone();
+        sorted.sort_unstable();
+        let median = if sorted.len().is_multiple_of(2) {
+            (seconds(sorted[sorted.len() / 2 - 1]) + seconds(sorted[sorted.len() / 2])) / 2.0
+        } else {
+            seconds(sorted[sorted.len() / 2])
+        };
+        stats.median_period = Some(median);
+
+        let mean =
+            periods.iter().map(|&period| seconds(period)).sum::<f64>() / periods.len() as f64;
+        let variance = periods
+            .iter()
+            .map(|&period| (seconds(period) - mean).powi(2))
+            .sum::<f64>()
+            / periods.len() as f64;
+        stats.jitter = Some(variance.sqrt());
+
+        stats.longest_gap = Some(gap(longest));
+        stats.gaps = (0..periods.len())
+            .filter(|&i| {
+                criteria
+                    .max_period
+                    .is_some_and(|max_period| periods[i] > max_period)
+                    || criteria
+                        .median_factor
+                        .is_some_and(|factor| seconds(periods[i]) > factor * median)
+            })
+            .map(gap)
+            .collect();
+        stats
+    }
+}
+
+/// Rate statistics per topic from the record times of `get_message_times`
+pub fn rate_stats(times: &BTreeMap<String, Vec<u64>>, criteria: &GapCriteria) -> Vec<RateStats> {
+    times
+        .iter()
+        .map(|(topic, times)| RateStats::new(topic, times, criteria))
+        .collect()
+}
@@ -1,2 +1,25 @@
+mod sample_bags;
 mod sample_messages;
+mod test_bag;
+mod test_cache;
+mod test_catalog;
+mod test_check;
+mod test_compress;
+mod test_downsample;
+mod test_export;
+mod test_filter;
+mod test_latency;
+mod test_mcap;
+mod test_merge;
+mod test_message_decoder;
 mod test_message_parsing;
+mod test_numpy;
+mod test_redact;
+mod test_remap;
+mod test_restamp;
+mod test_salvage;
+mod test_selection;
+mod test_split;
+mod test_stats;
+mod test_trim;
+mod test_writer;
@@ -0,0 +1,290 @@
+use std::io;
+
+use crate::{
+    bag::Bag,
+    record::{encode_time, Connection, MessageEncoding},
+    tests::sample_messages::{float32::FLOAT32, imu::SENSOR_IMU_MESSAGE},
+    time::NANOS_PER_SEC,
+    writer::{BagWriter, WriterOptions},
+};
+
+/// Start of the shared recordings, 100 s after the epoch
+pub const START: u64 = 100 * NANOS_PER_SEC;
+
+pub type TestWriter = BagWriter<io::Cursor<Vec<u8>>>;
+
+/// An in-memory bag with the connections and messages that `write` adds
+pub fn bag_from(
+    options: WriterOptions,
+    write: impl FnOnce(&mut TestWriter) -> io::Result<()>,
+) -> Bag {
+    let mut writer = BagWriter::with_options(io::Cursor::new(vec![]), options).unwrap();
+    write(&mut writer).unwrap();
+    Bag::from_vec(writer.finish().unwrap().into_inner()).unwrap()
+}
+
+pub fn float32_connection(topic: &str) -> Connection {
+    Connection {
+        id: 0,
+        topic: topic.to_string(),
+        tp: "std_msgs/Float32".to_string(),
+        md5sum: "73fcbf46b49191e672908e50842a83d4".to_string(),
+        message_definition: FLOAT32.to_string(),
+        caller_id: None,
+        latching: false,
+        encoding: MessageEncoding::Ros1,
+    }
+}
+
+/// A bag with `count` float32 messages on `/data`, one nanosecond apart starting at 1s
+pub fn float32_bag(count: u64) -> Vec<u8> {
+    let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+    let conn_id = writer.add_connection(&float32_connection("/data"));
+    for i in 0..count {
+        writer
+            .write_message(conn_id, 1_000_000_000 + i, &(i as f32).to_le_bytes())
+            .unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+/// 10 s of `/data` at 10 Hz from `START` in small chunks, a latched `/map` at 0 s and 1 s,
+/// `/lap` at 3 s and 7 s and an unused `/idle`. Messages hold their index at 10 Hz as float32.
+pub fn recording() -> Bag {
+    let options = WriterOptions {
+        chunk_size: 256,
+        ..Default::default()
+    };
+    bag_from(options, |writer| {
+        let map = writer.add_connection(&Connection {
+            latching: true,
+            ..float32_connection("/map")
+        });
+        let data = writer.add_connection(&float32_connection("/data"));
+        let lap = writer.add_connection(&float32_connection("/lap"));
+        writer.add_connection(&float32_connection("/idle"));
+        for i in 0..=100u64 {
+            let time = START + i * NANOS_PER_SEC / 10;
+            let value = (i as f32).to_le_bytes();
+            if i == 0 || i == 10 {
+                writer.write_message(map, time, &value)?;
+            }
+            if i == 30 || i == 70 {
+                writer.write_message(lap, time, &value)?;
+            }
+            writer.write_message(data, time, &value)?;
+        }
+        Ok(())
+    })
+}
+
+/// Float32 messages on `connection` holding their record time, in small chunks
+pub fn float32_recording(connection: &Connection, times: impl IntoIterator<Item = u64>) -> Bag {
+    let options = WriterOptions {
+        chunk_size: 512,
+        ..Default::default()
+    };
+    bag_from(options, |writer| {
+        let conn_id = writer.add_connection(connection);
+        for time in times {
+            writer.write_message(conn_id, time, &(time as f32).to_le_bytes())?;
+        }
+        Ok(())
+    })
+}
+
+pub fn imu_connection(topic: &str) -> Connection {
+    Connection {
+        id: 0,
+        topic: topic.to_string(),
+        tp: "sensor_msgs/Imu".to_string(),
+        md5sum: "6a62c6daae103f4ff57a132d6f95cec2".to_string(),
+        message_definition: SENSOR_IMU_MESSAGE.to_string(),
+        caller_id: None,
+        latching: false,
+        encoding: MessageEncoding::Ros1,
+    }
+}
+
+/// A serialized `sensor_msgs/Imu` with identity orientation and `linear_acceleration.z = 9.81`
+pub fn imu_message(seq: u32, stamp: u64, frame_id: &str) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend_from_slice(&seq.to_le_bytes());
+    data.extend_from_slice(&encode_time(stamp));
+    data.extend_from_slice(&(frame_id.len() as u32).to_le_bytes());
+    data.extend_from_slice(frame_id.as_bytes());
+    let floats: [f64; 37] = std::array::from_fn(|i| match i {
+        3 => 1.0,
+        27 => 9.81,
+        _ => 0.0,
+    });
+    for float in floats {
+        data.extend_from_slice(&float.to_le_bytes());
+    }
+    data
+}
+
+/// 5 s of `/imu` and `/data` at 1 Hz from `START`. The imu messages are stamped 20 ms
+/// before their record time and alternate between the frames `imu` and `other`,
+/// the last one has no stamp. `/data` is latched and published by `/node`.
+pub fn imu_recording() -> Bag {
+    bag_from(Default::default(), |writer| {
+        let imu = writer.add_connection(&imu_connection("/imu"));
+        let data = writer.add_connection(&Connection {
+            caller_id: Some("/node".to_string()),
+            latching: true,
+            ..float32_connection("/data")
+        });
+        for i in 0..5u64 {
+            let time = START + i * NANOS_PER_SEC;
+            let stamp = if i == 4 { 0 } else { time - 20_000_000 };
+            let frame_id = if i % 2 == 0 { "imu" } else { "other" };
+            writer.write_message(imu, time, &imu_message(i as u32, stamp, frame_id))?;
+            writer.write_message(data, time, &(i as f32).to_le_bytes())?;
+        }
+        Ok(())
+    })
+}
+
+/// A definition with a fixed size array, an array of a nested type and a variable array
+pub const TRACK_DEFINITION: &str = "string name\nfloat64[2] range\nPoint[] points\nint32[] ids\n\
+    ================================================================================\n\
+    MSG: my_msgs/Point\nfloat64 x\nfloat64 y\n";
+
+pub fn track_connection(topic: &str) -> Connection {
+    Connection {
+        tp: "my_msgs/Track".to_string(),
+        md5sum: "0123456789abcdef0123456789abcdef".to_string(),
+        message_definition: TRACK_DEFINITION.to_string(),
+        ..float32_connection(topic)
+    }
+}
+
+/// A serialized `my_msgs/Track` with `range = [0.5, 1.5]`
+pub fn track_message(name: &str, points: &[(f64, f64)], ids: &[i32]) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    data.extend_from_slice(name.as_bytes());
+    for value in [0.5, 1.5f64] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(&(points.len() as u32).to_le_bytes());
+    for (x, y) in points {
+        data.extend_from_slice(&x.to_le_bytes());
+        data.extend_from_slice(&y.to_le_bytes());
+    }
+    data.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+    for id in ids {
+        data.extend_from_slice(&id.to_le_bytes());
+    }
+    data
+}
+
+/// Two `/track` messages at 10 ns and 20 ns, the second one with empty arrays
+pub fn track_recording() -> Bag {
+    bag_from(Default::default(), |writer| {
+        let track = writer.add_connection(&track_connection("/track"));
+        let message = track_message("a,b", &[(1.0, 2.0), (3.0, 4.0)], &[7]);
+        writer.write_message(track, 10, &message)?;
+        writer.write_message(track, 20, &track_message("c", &[], &[]))
+    })
+}
+
+/// A definition with a byte array, a float and the time and duration primitives
+pub const BLOB_DEFINITION: &str = "uint8[] data\nfloat64 value\ntime stamp\nduration delay\n";
+
+/// One `/blob` message at 1.5 s with the bytes `ca fe 01` and `value`
+pub fn blob_recording(value: f64) -> Bag {
+    bag_from(Default::default(), |writer| {
+        let blob = writer.add_connection(&Connection {
+            tp: "my_msgs/Blob".to_string(),
+            md5sum: "0123456789abcdef0123456789abcdef".to_string(),
+            message_definition: BLOB_DEFINITION.to_string(),
+            ..float32_connection("/blob")
+        });
+        let mut data = vec![3, 0, 0, 0, 0xca, 0xfe, 0x01];
+        data.extend_from_slice(&value.to_le_bytes());
+        for value in [1_700_000_000u32, 5, 2, 500_000_000] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        writer.write_message(blob, 1_500_000_000, &data)
+    })
+}
+
+/// One `/stamped` message at START with a field named like the record time column of exports
+pub fn stamped_recording() -> Bag {
+    bag_from(Default::default(), |writer| {
+        let stamped = writer.add_connection(&Connection {
+            tp: "my_msgs/Stamped".to_string(),
+            md5sum: "0123456789abcdef0123456789abcdef".to_string(),
+            message_definition: "time time\nfloat64 value\n".to_string(),
+            ..float32_connection("/stamped")
+        });
+        writer.write_message(stamped, START, &[0; 16])
+    })
+}
+
+/// A ros2msg definition with a ROS2 header, an aligned point, a bounded array and a default value
+pub const ROS2_SAMPLE_DEFINITION: &str = "std_msgs/Header header
+geometry_msgs/Point point
+uint8 KIND_A=1
+float32[<=4] weights
+string<=8 label \"none\"
+================================================================================
+MSG: std_msgs/Header
+builtin_interfaces/Time stamp
+string frame_id
+================================================================================
+MSG: builtin_interfaces/Time
+int32 sec
+uint32 nanosec
+================================================================================
+MSG: geometry_msgs/Point
+float64 x
+float64 y
+float64 z
+";
+
+pub fn ros2_sample_connection(topic: &str) -> Connection {
+    Connection {
+        id: 0,
+        topic: topic.to_string(),
+        tp: "test_msgs/msg/Sample".to_string(),
+        md5sum: "*".to_string(),
+        message_definition: ROS2_SAMPLE_DEFINITION.to_string(),
+        caller_id: None,
+        latching: false,
+        encoding: MessageEncoding::Cdr,
+    }
+}
+
+/// A little endian CDR `test_msgs/msg/Sample` with `point.x = x`, two weights and label `ok`
+pub fn cdr_sample_message(stamp: u64, frame_id: &str, x: f64) -> Vec<u8> {
+    // Alignment is relative to the end of the encapsulation header
+    fn align(data: &mut Vec<u8>, size: usize) {
+        while !(data.len() - 4).is_multiple_of(size) {
+            data.push(0);
+        }
+    }
+    fn put_string(data: &mut Vec<u8>, s: &str) {
+        align(data, 4);
+        data.extend_from_slice(&(s.len() as u32 + 1).to_le_bytes());
+        data.extend_from_slice(s.as_bytes());
+        data.push(0);
+    }
+
+    let mut data = vec![0, 1, 0, 0];
+    data.extend_from_slice(&((stamp / 1_000_000_000) as i32).to_le_bytes());
+    data.extend_from_slice(&((stamp % 1_000_000_000) as u32).to_le_bytes());
+    put_string(&mut data, frame_id);
+    for value in [x, 0.0, 0.0] {
+        align(&mut data, 8);
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(&2u32.to_le_bytes());
+    for weight in [0.5f32, 0.25] {
+        data.extend_from_slice(&weight.to_le_bytes());
+    }
+    put_string(&mut data, "ok");
+    data
+}
@@ -0,0 +1,129 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        bag::{Bag, BagStream},
+        indexing::{
+            get_connections, get_message_count, get_messages, get_stream_connections,
+            get_stream_message_count, get_topics,
+        },
+        record::{Connection, BAG_MAGIC},
+        selection::TopicSelection,
+        tests::{
+            sample_bags::{float32_bag, float32_connection},
+            sample_messages::float32::FLOAT32,
+        },
+        writer::BagWriter,
+    };
+
+    #[test]
+    fn test_bag_from_bytes() {
+        let bag = Bag::from_bytes(&float32_bag(5)).unwrap();
+        assert_eq!(bag.conn_count(), 1);
+        assert_eq!(bag.chunk_count(), 1);
+        assert_eq!(get_topics(&bag).unwrap()["/data"], FLOAT32);
+        assert_eq!(
+            get_message_count(&bag, &TopicSelection::all()).unwrap()["/data"],
+            5
+        );
+        assert_eq!(
+            get_messages(&bag, &TopicSelection::parse(&["/data"]).unwrap()).unwrap()[3],
+            3f32.to_le_bytes().to_vec()
+        );
+        assert!(get_messages(&bag, &TopicSelection::parse(&["/missing"]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_bag_from_reader() {
+        let mut bytes = b"prefix".to_vec();
+        bytes.extend(float32_bag(5));
+        let mut reader = io::Cursor::new(bytes);
+        reader.set_position(6);
+        let bag = Bag::from_reader(reader).unwrap();
+        assert_eq!(
+            get_message_count(&bag, &TopicSelection::all()).unwrap()["/data"],
+            5
+        );
+    }
+
+    #[test]
+    fn test_bag_stream() {
+        let bytes = float32_bag(5);
+        let mut stream = BagStream::new(bytes.as_slice()).unwrap();
+        let first = stream.next_message().unwrap().unwrap();
+        assert_eq!(stream.connection(first.conn_id).unwrap().topic, "/data");
+        assert_eq!(first.time, 1_000_000_000);
+        assert_eq!(stream.count(), 4);
+
+        assert_eq!(
+            get_stream_message_count(bytes.as_slice(), &TopicSelection::all()).unwrap()["/data"],
+            5
+        );
+    }
+
+    #[test]
+    fn test_bag_stream_broken_chunk() {
+        // The connection record in the chunk lacks its id, the stream skips the chunk and ends
+        let mut bytes = float32_bag(5);
+        let field = b"conn=";
+        let pos = bytes.windows(field.len()).position(|w| w == field).unwrap();
+        bytes[pos + 3] = b'X';
+        let results: Vec<_> = BagStream::new(bytes.as_slice()).unwrap().take(10).collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    }
+
+    #[test]
+    fn test_bag_stream_truncated() {
+        // A record claiming 4 GiB of data in a stream that ends after a few bytes
+        let mut bytes = BAG_MAGIC.to_vec();
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+        let mut stream = BagStream::new(bytes.as_slice()).unwrap();
+        assert!(stream.next_message().is_err());
+    }
+
+    #[test]
+    fn test_bag_without_index() {
+        // A bag that is still being recorded has not written its index position yet
+        let mut bytes = float32_bag(5);
+        let field = b"index_pos=";
+        let pos = bytes.windows(field.len()).position(|w| w == field).unwrap() + field.len();
+        bytes[pos..pos + 8].copy_from_slice(&0u64.to_le_bytes());
+        assert!(Bag::from_bytes(&bytes).is_err());
+
+        let stream = BagStream::new(bytes.as_slice()).unwrap();
+        assert_eq!(stream.count(), 5);
+    }
+
+    #[test]
+    fn test_connection_metadata() {
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let latched = Connection {
+            caller_id: Some("/map_server".to_string()),
+            latching: true,
+            ..float32_connection("/map")
+        };
+        writer.add_connection(&latched);
+        let data = writer.add_connection(&float32_connection("/data"));
+        writer.write_message(0, 1, &0f32.to_le_bytes()).unwrap();
+        writer.write_message(data, 2, &1f32.to_le_bytes()).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let bag = Bag::from_bytes(&bytes).unwrap();
+        let connections = get_connections(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0], Connection { id: 0, ..latched });
+        assert_eq!(connections[1].tp, "std_msgs/Float32");
+        assert_eq!(connections[1].md5sum, "73fcbf46b49191e672908e50842a83d4");
+        assert_eq!(connections[1].caller_id, None);
+        assert!(!connections[1].latching);
+
+        let streamed =
+            get_stream_connections(bytes.as_slice(), &TopicSelection::parse(&["/map"]).unwrap())
+                .unwrap();
+        assert_eq!(streamed, vec![connections[0].clone()]);
+    }
+}
@@ -0,0 +1,59 @@
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::{
+        cache::{read_bag_summaries, BagSummary, IndexCache},
+        tests::sample_bags::float32_bag,
+    };
+
+    #[test]
+    fn test_sidecar_cache() {
+        let dir = env::temp_dir().join(format!("rebag-test-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let bag_path = dir.join("run.bag");
+        fs::write(&bag_path, float32_bag(5)).unwrap();
+
+        let summary = IndexCache::Sidecar.summary(&bag_path).unwrap();
+        assert_eq!(summary.get_message_count()["/data"], 5);
+        assert_eq!(summary.topics["/data"].start_time, Some(1_000_000_000));
+        assert_eq!(summary.topics["/data"].end_time, Some(1_000_000_004));
+
+        // A valid cache entry is used without reading the bag
+        let sidecar = dir.join("run.bag.rebag-index.json");
+        let mut cached: BagSummary = serde_json::from_slice(&fs::read(&sidecar).unwrap()).unwrap();
+        cached.topics.get_mut("/data").unwrap().message_count = 99;
+        fs::write(&sidecar, serde_json::to_vec(&cached).unwrap()).unwrap();
+        let summaries = read_bag_summaries(&dir, &IndexCache::Sidecar).unwrap();
+        assert_eq!(summaries["run.bag"].get_message_count()["/data"], 99);
+
+        // Changing the bag invalidates the entry
+        fs::write(&bag_path, float32_bag(7)).unwrap();
+        let summary = IndexCache::Sidecar.summary(&bag_path).unwrap();
+        assert_eq!(summary.get_message_count()["/data"], 7);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_directory_cache() {
+        let dir = env::temp_dir().join(format!("rebag-test-cache-dir-{}", std::process::id()));
+        let cache_dir = dir.join("cache");
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::create_dir_all(&cache_dir).unwrap();
+        // Both paths would be `a%b.bag` if only the separators were replaced
+        fs::write(dir.join("a%b.bag"), float32_bag(5)).unwrap();
+        fs::write(dir.join("a").join("b.bag"), float32_bag(7)).unwrap();
+
+        let cache = IndexCache::Directory(cache_dir.clone());
+        for _ in 0..2 {
+            let summary = cache.summary(&dir.join("a%b.bag")).unwrap();
+            assert_eq!(summary.get_message_count()["/data"], 5);
+            let summary = cache.summary(&dir.join("a").join("b.bag")).unwrap();
+            assert_eq!(summary.get_message_count()["/data"], 7);
+        }
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use crate::{
+        cache::IndexCache,
+        catalog::{Catalog, CatalogQuery},
+        tests::sample_bags::float32_bag,
+        time::{format_time, parse_time},
+    };
+
+    #[test]
+    fn test_catalog_query() {
+        let dir = env::temp_dir().join(format!("rebag-test-catalog-{}", std::process::id()));
+        fs::create_dir_all(dir.join("day2")).unwrap();
+        fs::write(dir.join("a.bag"), float32_bag(5)).unwrap();
+        fs::write(dir.join("day2/b.bag"), float32_bag(20)).unwrap();
+        fs::write(dir.join("notes.txt"), "not a bag").unwrap();
+
+        let catalog = Catalog::build(&dir, &IndexCache::Disabled).unwrap();
+        let paths = |query: CatalogQuery| {
+            catalog
+                .query(&query)
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            paths(CatalogQuery::default()),
+            [PathBuf::from("a.bag"), PathBuf::from("day2/b.bag")]
+        );
+        assert_eq!(
+            paths(CatalogQuery {
+                topic: Some("/data".to_string()),
+                min_count: Some(10),
+                ..Default::default()
+            }),
+            [PathBuf::from("day2/b.bag")]
+        );
+        assert!(paths(CatalogQuery {
+            topic: Some("/other".to_string()),
+            ..Default::default()
+        })
+        .is_empty());
+        assert!(paths(CatalogQuery {
+            start: Some(parse_time("1970-01-01T00:00:02Z").unwrap()),
+            ..Default::default()
+        })
+        .is_empty());
+        assert_eq!(
+            paths(CatalogQuery {
+                start: Some(parse_time("0.5").unwrap()),
+                end: Some(parse_time("1").unwrap()),
+                ..Default::default()
+            })
+            .len(),
+            2
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("1692370845.5").unwrap(),
+            1_692_370_845_500_000_000
+        );
+        assert_eq!(
+            parse_time("2023-08-18T15:00:45.5Z").unwrap(),
+            1_692_370_845_500_000_000
+        );
+        assert_eq!(parse_time("2023-08-18").unwrap(), 1_692_316_800_000_000_000);
+        assert_eq!(
+            format_time(1_692_370_845_500_000_000),
+            "2023-08-18T15:00:45.500Z"
+        );
+        assert!(parse_time("yesterday").is_err());
+        assert!(parse_time("2023-13-01").is_err());
+        assert!(parse_time("2023-01-01T99:99").is_err());
+        assert!(parse_time("2023-01-01T23:59:60").is_err());
+        assert!(parse_time("2023-01-01T23:59:59.5").is_ok());
+        // Non-ASCII digits are not numbers
+        assert!(parse_time("２０２３-01-01").is_err());
+        // Bags store u32 seconds, which end in 2106
+        assert!(parse_time("4294967295").is_ok());
+        assert!(parse_time("4294967296").is_err());
+        assert!(parse_time("2107-01-01").is_err());
+    }
+}
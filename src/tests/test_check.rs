@@ -0,0 +1,113 @@
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use crate::{
+        check::check,
+        record::{read_record, BAG_MAGIC, OP_CHUNK, OP_CHUNK_INFO, OP_INDEX_DATA},
+        tests::sample_bags::float32_bag,
+    };
+
+    /// Range of the data of the first top-level record with `op`
+    fn record_data(bytes: &[u8], op: u8) -> Range<usize> {
+        let mut pos = BAG_MAGIC.len() as u64;
+        loop {
+            let record = read_record(bytes, pos).unwrap();
+            if record.op == op {
+                let end = (record.offset + record.len) as usize;
+                return end - record.data.len()..end;
+            }
+            pos += record.len;
+        }
+    }
+
+    #[test]
+    fn test_check_intact_bag() {
+        let report = check(&float32_bag(5));
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.chunks, 1);
+        assert_eq!(report.messages, 5);
+        assert_eq!(report.connections, 1);
+    }
+
+    #[test]
+    fn test_check_inconsistent_index() {
+        // Chunk info claims 4 messages instead of 5
+        let mut bytes = float32_bag(5);
+        let pos = record_data(&bytes, OP_CHUNK_INFO).start;
+        bytes[pos + 4..pos + 8].copy_from_slice(&4u32.to_le_bytes());
+        let report = check(&bytes);
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].message.starts_with("chunk info counts"));
+
+        // Index data entry points into the middle of a message record
+        let mut bytes = float32_bag(5);
+        let pos = record_data(&bytes, OP_INDEX_DATA).start;
+        bytes[pos + 8..pos + 12].copy_from_slice(&1u32.to_le_bytes());
+        let report = check(&bytes);
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].message.contains("not a message record"));
+
+        // Message on a connection that is never defined
+        let mut bytes = float32_bag(5);
+        let chunk = record_data(&bytes, OP_CHUNK);
+        let field = b"conn=";
+        let conn = bytes[chunk.clone()]
+            .windows(field.len())
+            .rposition(|w| w == field)
+            .unwrap()
+            + chunk.start
+            + field.len();
+        bytes[conn..conn + 4].copy_from_slice(&7u32.to_le_bytes());
+        let report = check(&bytes);
+        assert!(report
+            .problems
+            .iter()
+            .any(|problem| problem.message == "connection 7 is not defined"));
+        assert!(report
+            .problems
+            .iter()
+            .any(|problem| problem.message.starts_with("index entry for connection 0")));
+
+        // Chunk size does not match its data
+        let mut bytes = float32_bag(5);
+        let field = b"size=";
+        let size = bytes.windows(field.len()).position(|w| w == field).unwrap() + field.len();
+        bytes[size..size + 4].copy_from_slice(&1u32.to_le_bytes());
+        let report = check(&bytes);
+        assert!(report.problems[0].message.starts_with("chunk size is 1"));
+
+        // Chunk header without compression, its index data has no messages to point to
+        let mut bytes = float32_bag(5);
+        let field = b"compression=";
+        let compression = bytes.windows(field.len()).position(|w| w == field).unwrap();
+        bytes[compression + field.len() - 2] = b'X';
+        let report = check(&bytes);
+        assert!(report.problems.iter().any(|problem| problem
+            .message
+            .starts_with("index data of the unreadable chunk")));
+    }
+
+    #[test]
+    fn test_check_missing_index() {
+        // Cut off the index section, the index position then points at the end of the file
+        let bytes = float32_bag(5);
+        let field = b"index_pos=";
+        let pos = bytes.windows(field.len()).position(|w| w == field).unwrap() + field.len();
+        let index_pos = u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap());
+        let report = check(&bytes[..index_pos as usize]);
+        let messages: Vec<&str> = report
+            .problems
+            .iter()
+            .map(|problem| problem.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "bag header counts 1 connections but the index has 0",
+                "bag header counts 1 chunks but the index has 0 chunk infos",
+                "chunk has no chunk info"
+            ]
+        );
+    }
+}
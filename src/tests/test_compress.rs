@@ -0,0 +1,92 @@
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io};
+
+    use crate::{
+        bag::{Bag, ChunkRecord},
+        check::check,
+        compress::{recompress, recompress_bag},
+        indexing::{get_message_times, get_messages},
+        selection::TopicSelection,
+        tests::sample_bags::float32_connection,
+        writer::{BagWriter, Compression, WriterOptions},
+    };
+
+    fn chunked_bag() -> Vec<u8> {
+        let options = WriterOptions {
+            chunk_size: 1024,
+            ..Default::default()
+        };
+        let mut writer = BagWriter::with_options(io::Cursor::new(vec![]), options).unwrap();
+        let a = writer.add_connection(&float32_connection("/a"));
+        let b = writer.add_connection(&float32_connection("/b"));
+        for i in 0..300u64 {
+            let conn_id = if i % 3 == 0 { a } else { b };
+            writer
+                .write_message(conn_id, 1_000_000_000 + i, &(i as f32).to_le_bytes())
+                .unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn compressions(bag: &Bag) -> Vec<String> {
+        bag.chunk_records()
+            .filter_map(|record| match record.unwrap() {
+                ChunkRecord::Chunk(chunk) => Some(chunk.compression.to_string()),
+                ChunkRecord::IndexData(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recompress() {
+        let original = chunked_bag();
+        let bag = Bag::from_bytes(&original).unwrap();
+        let all = TopicSelection::all();
+
+        for compression in [Compression::Lz4, Compression::Bz2] {
+            let bytes = recompress(&bag, io::Cursor::new(vec![]), compression)
+                .unwrap()
+                .into_inner();
+            assert!(check(&bytes).is_ok());
+            let compressed = Bag::from_bytes(&bytes).unwrap();
+            assert_eq!(compressed.chunk_count(), bag.chunk_count());
+            assert!(compressions(&compressed)
+                .iter()
+                .all(|name| name == compression.name()));
+            assert_eq!(
+                get_message_times(&compressed, &all).unwrap(),
+                get_message_times(&bag, &all).unwrap()
+            );
+            assert_eq!(
+                get_messages(&compressed, &all).unwrap(),
+                get_messages(&bag, &all).unwrap()
+            );
+
+            // Decompressing restores the layout the writer produced
+            let decompressed = recompress(&compressed, io::Cursor::new(vec![]), Compression::None)
+                .unwrap()
+                .into_inner();
+            assert!(decompressed == original);
+        }
+    }
+
+    #[test]
+    fn test_recompress_bag_in_place() {
+        let dir = env::temp_dir().join(format!("rebag-test-compress-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.bag");
+        fs::write(&path, chunked_bag()).unwrap();
+
+        recompress_bag(&path, None, Compression::Lz4).unwrap();
+        let bag = Bag::open(&path).unwrap();
+        assert!(compressions(&bag).iter().all(|name| name == "lz4"));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let output = dir.join("plain.bag");
+        recompress_bag(&path, Some(&output), Compression::None).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), chunked_bag());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        bag::Bag,
+        downsample::{downsample, Decimation, DownsampleRule, Downsampler},
+        filter::{filter_messages, Filter, MessageFilter},
+        indexing::{get_message_count, MessageSource},
+        selection::TopicSelection,
+        tests::sample_bags::{recording, START},
+        time::NANOS_PER_SEC,
+        writer::BagWriter,
+    };
+
+    #[test]
+    fn test_downsample_rules() {
+        let rule = DownsampleRule::max_rate("/camera/**:2.5").unwrap();
+        assert_eq!(rule.decimation, Decimation::MaxRate(2.5));
+        assert!(rule.topics.matches("/camera/front/image"));
+        let rule = DownsampleRule::every("re:^/(a|b):4").unwrap();
+        assert_eq!(rule.decimation, Decimation::Every(4));
+        assert!(rule.topics.matches("/b"));
+        assert!(DownsampleRule::every("/lidar:0").is_err());
+        assert!(DownsampleRule::max_rate("/camera:fast").is_err());
+        assert!(DownsampleRule::max_rate("/camera").is_err());
+
+        let mut downsampler = Downsampler::new(vec![
+            DownsampleRule::every("/lidar:3").unwrap(),
+            DownsampleRule::every("/**:2").unwrap(),
+        ]);
+        let kept: Vec<bool> = (0..4).map(|i| downsampler.keep("/lidar", i)).collect();
+        assert_eq!(kept, [true, false, false, true]);
+        let kept: Vec<bool> = (0..4).map(|i| downsampler.keep("/odom", i)).collect();
+        assert_eq!(kept, [true, false, true, false]);
+    }
+
+    #[test]
+    fn test_downsample_iterator() {
+        let bag = recording();
+        let downsampler = Downsampler::new(vec![
+            DownsampleRule::max_rate("/data:2").unwrap(),
+            DownsampleRule::every("/lap:2").unwrap(),
+        ]);
+        let messages: Vec<_> = downsample(&bag, downsampler)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let count = |conn_id| {
+            messages
+                .iter()
+                .filter(|message| message.conn_id == conn_id)
+                .count()
+        };
+        assert_eq!((count(0), count(1), count(2)), (2, 21, 1));
+        let data: Vec<u64> = messages
+            .iter()
+            .filter(|message| message.conn_id == 1)
+            .map(|message| message.time)
+            .collect();
+        assert_eq!(data[..2], [START, START + NANOS_PER_SEC / 2]);
+    }
+
+    #[test]
+    fn test_filter_with_downsampling() {
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let mut filter =
+            MessageFilter::new(vec![Filter::parse("/data: $time >= 105").unwrap()], true)
+                .with_downsampler(Downsampler::new(vec![
+                    DownsampleRule::max_rate("/data:1").unwrap()
+                ]));
+        let report =
+            filter_messages(MessageSource::Bag(recording()), &mut writer, &mut filter).unwrap();
+        let bag = Bag::from_vec(writer.finish().unwrap().into_inner()).unwrap();
+        let count = get_message_count(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(count["/data"], 6);
+        assert_eq!(count["/lap"], 2);
+        assert_eq!(report.kept, 10);
+    }
+}
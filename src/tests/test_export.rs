@@ -0,0 +1,272 @@
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io};
+
+    use arrow::array::{
+        Array, AsArray, FixedSizeListArray, ListArray, RecordBatch, StringArray, StructArray,
+    };
+    use arrow::datatypes::{DataType, Float64Type, Int32Type, TimeUnit, TimestampNanosecondType};
+    use arrow::ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::{
+        bag::Bag,
+        columnar::{export_columnar, ColumnarFormat},
+        export::{
+            echo, export_csv, export_jsonl, ArrayMode, BytesFormat, JsonOptions, NonFiniteFormat,
+            TimeFormat,
+        },
+        indexing::MessageSource,
+        selection::TopicSelection,
+        tests::sample_bags::{
+            bag_from, blob_recording, float32_connection, imu_recording, stamped_recording,
+            track_connection, track_message, track_recording,
+        },
+    };
+
+    fn export(bag: Bag, topic: &str, arrays: ArrayMode) -> String {
+        let mut csv = vec![];
+        export_csv(MessageSource::Bag(bag), topic, arrays, &mut csv).unwrap();
+        String::from_utf8(csv).unwrap()
+    }
+
+    #[test]
+    fn test_export_serialized_arrays() {
+        assert_eq!(
+            export(track_recording(), "/track", ArrayMode::Serialize),
+            "time,name,range.0,range.1,points,ids\n\
+             10,\"a,b\",0.5,1.5,\"[{\"\"x\"\":1.0,\"\"y\"\":2.0},{\"\"x\"\":3.0,\"\"y\"\":4.0}]\",[7]\n\
+             20,c,0.5,1.5,[],[]\n"
+        );
+    }
+
+    #[test]
+    fn test_export_exploded_arrays() {
+        assert_eq!(
+            export(track_recording(), "/track", ArrayMode::Explode),
+            "time,name,range.0,range.1,points.x,points.y,ids\n\
+             10,\"a,b\",0.5,1.5,1.0,2.0,7\n\
+             10,\"a,b\",0.5,1.5,3.0,4.0,\n\
+             20,c,0.5,1.5,,,\n"
+        );
+    }
+
+    #[test]
+    fn test_export_nested_fields() {
+        let csv = export(imu_recording(), "/imu", ArrayMode::Serialize);
+        let mut lines = csv.lines();
+        let columns: Vec<&str> = lines.next().unwrap().split(',').collect();
+        let cells: Vec<&str> = lines.nth(1).unwrap().split(',').collect();
+        assert_eq!(lines.count(), 3);
+        assert_eq!(columns.len(), cells.len());
+        let cell = |column: &str| cells[columns.iter().position(|c| *c == column).unwrap()];
+        assert_eq!(cell("time"), "101000000000");
+        assert_eq!(cell("header.seq"), "1");
+        assert_eq!(cell("header.stamp"), "100980000000");
+        assert_eq!(cell("header.frame_id"), "other");
+        assert_eq!(cell("orientation.w"), "1.0");
+        assert_eq!(cell("orientation_covariance.8"), "0.0");
+        assert_eq!(cell("linear_acceleration.z"), "9.81");
+    }
+
+    #[test]
+    fn test_export_needs_one_type() {
+        let bag = bag_from(Default::default(), |writer| {
+            let track = writer.add_connection(&track_connection("/track"));
+            let float = writer.add_connection(&float32_connection("/track"));
+            writer.write_message(track, 1, &track_message("a", &[], &[]))?;
+            writer.write_message(float, 2, &[0; 4])
+        });
+
+        let error = export_csv(
+            MessageSource::Bag(bag),
+            "/track",
+            ArrayMode::Serialize,
+            vec![],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("can only export one type"));
+
+        // Same type on two topics, which CSV rows could not tell apart
+        let bag = bag_from(Default::default(), |writer| {
+            let front = writer.add_connection(&float32_connection("/camera/front"));
+            let rear = writer.add_connection(&float32_connection("/camera/rear"));
+            writer.write_message(front, 1, &[0; 4])?;
+            writer.write_message(rear, 2, &[0; 4])
+        });
+        let error = export_csv(
+            MessageSource::Bag(bag),
+            "/camera/*",
+            ArrayMode::Serialize,
+            vec![],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("can only export one topic"));
+        assert!(export_csv(
+            MessageSource::Bag(track_recording()),
+            "/other",
+            ArrayMode::Serialize,
+            vec![]
+        )
+        .is_err());
+    }
+
+    fn arrow_batch(bag: Bag) -> RecordBatch {
+        let mut ipc = vec![];
+        export_columnar(
+            MessageSource::Bag(bag),
+            "/track",
+            ColumnarFormat::ArrowIpc,
+            &mut ipc,
+        )
+        .unwrap();
+        let mut reader = FileReader::try_new(io::Cursor::new(ipc), None).unwrap();
+        let batch = reader.next().unwrap().unwrap();
+        assert!(reader.next().is_none());
+        batch
+    }
+
+    #[test]
+    fn test_export_arrow_ipc() {
+        let batch = arrow_batch(track_recording());
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(
+            batch.schema().field(0).data_type(),
+            &DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()))
+        );
+        let time = batch.column(0).as_primitive::<TimestampNanosecondType>();
+        assert_eq!(time.values(), &[10, 20]);
+
+        let name = batch.column_by_name("name").unwrap();
+        let name = name.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(name.value(0), "a,b");
+
+        let range = batch.column_by_name("range").unwrap();
+        let range = range.as_any().downcast_ref::<FixedSizeListArray>().unwrap();
+        assert_eq!(range.value_length(), 2);
+        assert_eq!(
+            range.values().as_primitive::<Float64Type>().values(),
+            &[0.5, 1.5, 0.5, 1.5]
+        );
+
+        let points = batch.column_by_name("points").unwrap();
+        let points = points.as_any().downcast_ref::<ListArray>().unwrap();
+        assert_eq!(points.value_offsets(), &[0, 2, 2]);
+        let points = points
+            .values()
+            .as_any()
+            .downcast_ref::<StructArray>()
+            .unwrap();
+        let x = points.column_by_name("x").unwrap();
+        assert_eq!(x.as_primitive::<Float64Type>().values(), &[1.0, 3.0]);
+
+        let ids = batch.column_by_name("ids").unwrap().as_list::<i32>();
+        assert_eq!(ids.value(0).as_primitive::<Int32Type>().values(), &[7]);
+        assert!(ids.value(1).is_empty());
+    }
+
+    #[test]
+    fn test_export_time_field() {
+        let error = export_csv(
+            MessageSource::Bag(stamped_recording()),
+            "/stamped",
+            ArrayMode::Serialize,
+            vec![],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("collides with the record time"));
+        let error = export_columnar(
+            MessageSource::Bag(stamped_recording()),
+            "/stamped",
+            ColumnarFormat::ArrowIpc,
+            vec![],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("collides with the record time"));
+    }
+
+    #[test]
+    fn test_export_parquet() {
+        let path =
+            env::temp_dir().join(format!("rebag-test-export-{}.parquet", std::process::id()));
+        let file = fs::File::create(&path).unwrap();
+        let report = export_columnar(
+            MessageSource::Bag(track_recording()),
+            "/track",
+            ColumnarFormat::Parquet,
+            file,
+        )
+        .unwrap();
+        assert_eq!(report.messages, 2);
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        fs::remove_file(&path).unwrap();
+        // The Arrow schema is stored along, so the batch reads back as written
+        assert_eq!(batches, vec![arrow_batch(track_recording())]);
+    }
+
+    fn jsonl(bag: Bag, options: JsonOptions) -> String {
+        let mut jsonl = vec![];
+        let topics = TopicSelection::all();
+        export_jsonl(MessageSource::Bag(bag), &topics, options, &mut jsonl).unwrap();
+        String::from_utf8(jsonl).unwrap()
+    }
+
+    #[test]
+    fn test_export_jsonl() {
+        assert_eq!(
+            jsonl(blob_recording(f64::NAN), JsonOptions::default()),
+            "{\"topic\":\"/blob\",\"time\":1500000000,\"message\":{\"data\":\"yv4B\",\
+             \"value\":null,\"stamp\":1700000000000000005,\"delay\":2500000000}}\n"
+        );
+        let options = JsonOptions {
+            bytes: BytesFormat::Hex,
+            non_finite: NonFiniteFormat::String,
+            time: TimeFormat::Iso,
+        };
+        assert_eq!(
+            jsonl(blob_recording(f64::NEG_INFINITY), options),
+            "{\"topic\":\"/blob\",\"time\":\"1970-01-01T00:00:01.500000000Z\",\"message\":\
+             {\"data\":\"cafe01\",\"value\":\"-Infinity\",\
+             \"stamp\":\"2023-11-14T22:13:20.000000005Z\",\"delay\":2.5}}\n"
+        );
+        let options = JsonOptions {
+            bytes: BytesFormat::Array,
+            time: TimeFormat::Seconds,
+            ..Default::default()
+        };
+        let line: serde_json::Value =
+            serde_json::from_str(&jsonl(blob_recording(0.25), options)).unwrap();
+        assert_eq!(line["time"], 1.5);
+        assert_eq!(line["message"]["data"], serde_json::json!([202, 254, 1]));
+        assert_eq!(line["message"]["value"], 0.25);
+    }
+
+    #[test]
+    fn test_echo() {
+        let mut text = vec![];
+        echo(
+            MessageSource::Bag(track_recording()),
+            &TopicSelection::all(),
+            &mut text,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "--- /track 1970-01-01T00:00:00.000000010Z\n\
+             name: \"a,b\"\n\
+             range: [0.5, 1.5]\n\
+             points:\n  -\n    x: 1.0\n    y: 2.0\n  -\n    x: 3.0\n    y: 4.0\n\
+             ids: [7]\n\
+             --- /track 1970-01-01T00:00:00.000000020Z\n\
+             name: \"c\"\n\
+             range: [0.5, 1.5]\n\
+             points: []\n\
+             ids: []\n"
+        );
+    }
+}
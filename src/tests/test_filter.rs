@@ -0,0 +1,158 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io;
+
+    use crate::{
+        bag::Bag,
+        filter::{filter_messages, Filter, MessageFilter},
+        indexing::{get_message_count, MessageSource},
+        selection::TopicSelection,
+        tests::sample_bags::{
+            bag_from, float32_bag, float32_connection, imu_connection, imu_message, START,
+        },
+        writer::BagWriter,
+    };
+
+    fn imu_bag() -> Vec<u8> {
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let imu = writer.add_connection(&imu_connection("/imu"));
+        let data = writer.add_connection(&float32_connection("/data"));
+        for i in 0..10u64 {
+            let time = 1_000_000_000 + i * 100_000_000;
+            let frame_id = if i % 2 == 0 { "even" } else { "odd" };
+            writer
+                .write_message(imu, time, &imu_message(i as u32, time, frame_id))
+                .unwrap();
+            writer
+                .write_message(data, time, &(i as f32).to_le_bytes())
+                .unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn filter(bytes: &[u8], expressions: &[&str], keep_other: bool) -> Vec<(String, u64)> {
+        let filters = expressions
+            .iter()
+            .map(|expression| Filter::parse(expression).unwrap())
+            .collect();
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let source = MessageSource::Bag(Bag::from_bytes(bytes).unwrap());
+        let report = filter_messages(
+            source,
+            &mut writer,
+            &mut MessageFilter::new(filters, keep_other),
+        )
+        .unwrap();
+        let output = writer.finish().unwrap().into_inner();
+        let count = get_message_count(&Bag::from_vec(output).unwrap(), &TopicSelection::all())
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            report.kept,
+            count.iter().map(|(_, count)| count).sum::<u64>()
+        );
+        count
+    }
+
+    #[test]
+    fn test_filter_fields() {
+        let bytes = imu_bag();
+        assert_eq!(
+            filter(&bytes, &["/imu: header.seq >= 7"], true),
+            [("/data".to_string(), 10), ("/imu".to_string(), 3)]
+        );
+        assert_eq!(
+            filter(&bytes, &["/imu: header.seq >= 7"], false),
+            [("/imu".to_string(), 3)]
+        );
+        assert_eq!(
+            filter(
+                &bytes,
+                &["/imu: header.frame_id == 'odd' and not (linear_acceleration.z < 9.8)"],
+                false
+            ),
+            [("/imu".to_string(), 5)]
+        );
+        // A message is kept if any of the expressions for its topic matches
+        assert_eq!(
+            filter(
+                &bytes,
+                &["/imu: header.seq == 0", "/i*: header.seq == 9", "data < 2"],
+                false
+            ),
+            [("/data".to_string(), 2), ("/imu".to_string(), 2)]
+        );
+        // Missing fields never match
+        assert_eq!(filter(&bytes, &["/imu: header.missing != 1"], false), []);
+    }
+
+    #[test]
+    fn test_filter_topic_and_time() {
+        let bytes = imu_bag();
+        assert_eq!(
+            filter(&bytes, &["$topic == \"/data\" || $time >= 1.75"], false),
+            [("/data".to_string(), 10), ("/imu".to_string(), 2)]
+        );
+
+        let filter = Filter::parse("$time > 1.5 && $time <= -(-2)").unwrap();
+        assert!(!filter.uses_fields());
+        assert!(filter.matches("/a", 2_000_000_000, None));
+        assert!(!filter.matches("/a", 1_000_000_000, None));
+
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let source = MessageSource::Stream(Box::new(io::Cursor::new(float32_bag(5))));
+        let mut message_filter = MessageFilter::new(vec![filter], true);
+        let report = filter_messages(source, &mut writer, &mut message_filter).unwrap();
+        assert_eq!((report.kept, report.dropped), (0, 5));
+    }
+
+    #[test]
+    fn test_filter_undecodable() {
+        let bag = || {
+            bag_from(Default::default(), |writer| {
+                let imu = writer.add_connection(&imu_connection("/imu"));
+                writer.write_message(imu, START, &imu_message(0, START, "imu"))?;
+                writer.write_message(imu, START + 1, &imu_message(1, START, "imu"))?;
+                writer.write_message(imu, START + 2, &imu_message(2, START, "imu")[..10])
+            })
+        };
+        let run = |expression: &str| {
+            let filters = vec![Filter::parse(expression).unwrap()];
+            let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+            let source = MessageSource::Bag(bag());
+            filter_messages(source, &mut writer, &mut MessageFilter::new(filters, false)).unwrap()
+        };
+        // The cut off message is counted and matches no field filter, even a negated one
+        let report = run("/imu: not (header.seq < 1)");
+        assert_eq!((report.kept, report.dropped), (1, 2));
+        assert_eq!(
+            report.undecodable,
+            BTreeMap::from([("/imu".to_string(), 1)])
+        );
+        let report = run("$topic == '/imu'");
+        assert_eq!((report.kept, report.dropped), (3, 0));
+        assert!(report.undecodable.is_empty());
+    }
+
+    #[test]
+    fn test_parse_filter_errors() {
+        for expression in [
+            "x = 1",
+            "x >",
+            "(x > 1",
+            "x > 'a",
+            "$stamp > 1",
+            "x > 1 y",
+            "x # 1",
+            "",
+        ] {
+            assert!(Filter::parse(expression).is_err(), "{}", expression);
+        }
+        let filter = Filter::parse("/camera/*/image: height > 0").unwrap();
+        assert!(filter.topics.matches("/camera/front/image"));
+        assert!(!filter.topics.matches("/lidar"));
+        assert_eq!(filter.to_string(), "/camera/*/image: height > 0");
+    }
+}
@@ -0,0 +1,91 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io;
+
+    use crate::{
+        bag::Bag,
+        latency::{
+            get_latency_samples, get_stream_latency_samples, latency_stats, write_latency_csv,
+        },
+        record::Connection,
+        selection::TopicSelection,
+        tests::sample_bags::{float32_connection, imu_connection, imu_message},
+        time::NANOS_PER_SEC,
+        writer::BagWriter,
+    };
+
+    #[test]
+    fn test_header_latency() {
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let imu = writer.add_connection(&imu_connection("/imu"));
+        let data = writer.add_connection(&float32_connection("/data"));
+        // A definition that cannot be parsed is skipped like a message without header
+        let broken = writer.add_connection(&Connection {
+            message_definition: "float32".to_string(),
+            ..float32_connection("/broken")
+        });
+        let base = 100 * NANOS_PER_SEC;
+        // 10ms latency, except one message stamped in the future and one 2s late
+        for i in 0..10u64 {
+            let time = base + i * NANOS_PER_SEC / 10;
+            let stamp = match i {
+                3 => time + 1_000_000,
+                7 => time - 2 * NANOS_PER_SEC,
+                _ => time - 10_000_000,
+            };
+            writer
+                .write_message(imu, time, &imu_message(i as u32, stamp, "imu"))
+                .unwrap();
+            writer.write_message(data, time, &[0; 4]).unwrap();
+            writer.write_message(broken, time, &[0; 4]).unwrap();
+        }
+        // A message cut off in its header is counted and skipped
+        let truncated = &imu_message(10, base, "imu")[..10];
+        writer
+            .write_message(imu, base + NANOS_PER_SEC, truncated)
+            .unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let bag = Bag::from_bytes(&bytes).unwrap();
+        let samples = get_latency_samples(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(samples.malformed, BTreeMap::from([("/imu".to_string(), 1)]));
+        assert_eq!(
+            get_stream_latency_samples(bytes.as_slice(), &TopicSelection::all()).unwrap(),
+            samples
+        );
+        let samples = samples.samples;
+        assert_eq!(samples.len(), 10);
+        assert!(samples.iter().all(|sample| sample.topic == "/imu"));
+        assert_eq!(samples[0].latency(), 10_000_000);
+        assert_eq!(samples[3].latency(), -1_000_000);
+
+        let stats = latency_stats(&samples, NANOS_PER_SEC, 4);
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].message_count, 10);
+        assert_eq!(stats[0].future, 1);
+        assert_eq!(stats[0].stale, 1);
+        assert!((stats[0].p50 - 0.01).abs() < 1e-9);
+        assert!((stats[0].min + 0.001).abs() < 1e-9);
+        assert!((stats[0].max - 2.0).abs() < 1e-9);
+        assert_eq!(
+            stats[0]
+                .histogram
+                .iter()
+                .map(|bin| bin.count)
+                .collect::<Vec<_>>(),
+            [9, 0, 0, 1]
+        );
+
+        let mut csv = vec![];
+        write_latency_csv(&mut csv, &samples, NANOS_PER_SEC).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(
+            lines[4],
+            "/imu,100.300000000,100.301000000,-0.001000000,future"
+        );
+        assert!(lines[8].ends_with(",2.000000000,stale"));
+    }
+}
@@ -0,0 +1,335 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use byteorder::{ReadBytesExt, LE};
+
+    use std::collections::btree_map::Entry;
+    use std::collections::BTreeMap;
+
+    use crate::{
+        bag::Bag,
+        convert::bag_to_mcap,
+        indexing::{
+            get_connections, get_message_count, get_message_times, get_messages, get_start_time,
+            read_messages, MessageSource,
+        },
+        mcap::{
+            McapCompression, McapFile, McapIndex, McapOptions, McapWriter, MCAP_MAGIC, OP_CHANNEL,
+            OP_CHUNK, OP_CHUNK_INDEX, OP_FOOTER, OP_MESSAGE, OP_SCHEMA, OP_STATISTICS,
+            OP_SUMMARY_OFFSET,
+        },
+        message_decoder::{MessageDecoder, Value},
+        record::{Connection, MessageEncoding},
+        selection::TopicSelection,
+        tests::sample_bags::{cdr_sample_message, imu_recording, ROS2_SAMPLE_DEFINITION, START},
+        time::NANOS_PER_SEC,
+        writer::BagWriter,
+    };
+
+    fn convert(compression: McapCompression) -> Vec<u8> {
+        let options = McapOptions {
+            compression,
+            chunk_size: 1024,
+            ..Default::default()
+        };
+        let mut writer = McapWriter::new(vec![], options).unwrap();
+        let report = bag_to_mcap(MessageSource::Bag(imu_recording()), &mut writer).unwrap();
+        assert_eq!(report.messages, 10);
+        assert_eq!(report.channels, 2);
+        assert_eq!(report.schemas, 2);
+        writer.finish().unwrap()
+    }
+
+    /// Opcode and content of the record at `pos`
+    fn record_at(mcap: &[u8], pos: usize) -> (u8, &[u8]) {
+        let len = (&mcap[pos + 1..]).read_u64::<LE>().unwrap() as usize;
+        (mcap[pos], &mcap[pos + 9..pos + 9 + len])
+    }
+
+    fn records(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut records = vec![];
+        while !data.is_empty() {
+            let (op, content) = record_at(data, 0);
+            records.push((op, content));
+            data = &data[9 + content.len()..];
+        }
+        records
+    }
+
+    fn read_str(data: &mut &[u8]) -> String {
+        let len = data.read_u32::<LE>().unwrap() as usize;
+        let (s, rest) = data.split_at(len);
+        *data = rest;
+        String::from_utf8(s.to_vec()).unwrap()
+    }
+
+    /// Records of the summary section, found through the footer
+    fn summary(mcap: &[u8]) -> Vec<(u8, &[u8])> {
+        assert!(mcap.starts_with(MCAP_MAGIC));
+        assert!(mcap.ends_with(MCAP_MAGIC));
+        let footer_pos = mcap.len() - MCAP_MAGIC.len() - 29;
+        let (op, mut footer) = record_at(mcap, footer_pos);
+        assert_eq!(op, OP_FOOTER);
+        let summary_start = footer.read_u64::<LE>().unwrap() as usize;
+        let summary_offset_start = footer.read_u64::<LE>().unwrap() as usize;
+        let summary_crc = footer.read_u32::<LE>().unwrap();
+        assert_eq!(
+            crc32fast::hash(&mcap[summary_start..footer_pos + 9 + 16]),
+            summary_crc
+        );
+
+        let offsets = records(&mcap[summary_offset_start..footer_pos]);
+        assert!(offsets.iter().all(|(op, _)| *op == OP_SUMMARY_OFFSET));
+        let ops: Vec<u8> = offsets.iter().map(|(_, content)| content[0]).collect();
+        assert_eq!(ops, [OP_SCHEMA, OP_CHANNEL, OP_STATISTICS, OP_CHUNK_INDEX]);
+        records(&mcap[summary_start..summary_offset_start])
+    }
+
+    #[test]
+    fn test_convert_summary() {
+        let mcap = convert(McapCompression::None);
+        let summary = summary(&mcap);
+
+        let mut schemas = summary.iter().filter(|(op, _)| *op == OP_SCHEMA);
+        let (_, mut schema) = schemas.next().unwrap();
+        assert_eq!(schema.read_u16::<LE>().unwrap(), 1);
+        assert_eq!(read_str(&mut schema), "sensor_msgs/Imu");
+        assert_eq!(read_str(&mut schema), "ros1msg");
+        assert_eq!(schemas.count(), 1);
+
+        let (_, mut channel) = summary.iter().find(|(op, _)| *op == OP_CHANNEL).unwrap();
+        channel = &channel[4..];
+        assert_eq!(read_str(&mut channel), "/imu");
+        assert_eq!(read_str(&mut channel), "ros1");
+        let (_, channel) = summary
+            .iter()
+            .filter(|(op, _)| *op == OP_CHANNEL)
+            .nth(1)
+            .unwrap();
+        let metadata = String::from_utf8_lossy(channel);
+        assert!(metadata.contains("callerid") && metadata.contains("/node"));
+        assert!(metadata.contains("latching"));
+
+        let (_, mut statistics) = summary.iter().find(|(op, _)| *op == OP_STATISTICS).unwrap();
+        assert_eq!(statistics.read_u64::<LE>().unwrap(), 10);
+        assert_eq!(statistics.read_u16::<LE>().unwrap(), 2);
+        assert_eq!(statistics.read_u32::<LE>().unwrap(), 2);
+        statistics = &statistics[8..];
+        let chunk_count = statistics.read_u32::<LE>().unwrap() as usize;
+        assert!(chunk_count > 1);
+        assert_eq!(statistics.read_u64::<LE>().unwrap(), START);
+        assert_eq!(
+            statistics.read_u64::<LE>().unwrap(),
+            START + 4 * NANOS_PER_SEC
+        );
+
+        let chunk_indexes: Vec<_> = summary
+            .iter()
+            .filter(|(op, _)| *op == OP_CHUNK_INDEX)
+            .collect();
+        assert_eq!(chunk_indexes.len(), chunk_count);
+        let mut messages = 0;
+        for (_, mut index) in chunk_indexes {
+            let start_time = index.read_u64::<LE>().unwrap();
+            index.read_u64::<LE>().unwrap();
+            let chunk_pos = index.read_u64::<LE>().unwrap() as usize;
+            let (op, mut chunk) = record_at(&mcap, chunk_pos);
+            assert_eq!(op, OP_CHUNK);
+            assert_eq!(chunk.read_u64::<LE>().unwrap(), start_time);
+            chunk.read_u64::<LE>().unwrap();
+            chunk.read_u64::<LE>().unwrap();
+            let crc = chunk.read_u32::<LE>().unwrap();
+            assert_eq!(read_str(&mut chunk), "");
+            let records_len = chunk.read_u64::<LE>().unwrap() as usize;
+            assert_eq!(crc32fast::hash(&chunk[..records_len]), crc);
+            messages += records(chunk)
+                .iter()
+                .filter(|(op, _)| *op == OP_MESSAGE)
+                .count();
+        }
+        assert_eq!(messages, 10);
+    }
+
+    #[test]
+    fn test_convert_compressed() {
+        for compression in [McapCompression::Lz4, McapCompression::Zstd] {
+            let mcap = convert(compression);
+            let summary = summary(&mcap);
+            let (_, mut index) = summary
+                .iter()
+                .find(|(op, _)| *op == OP_CHUNK_INDEX)
+                .unwrap();
+            index = &index[16..];
+            let chunk_pos = index.read_u64::<LE>().unwrap() as usize;
+            let (_, mut chunk) = record_at(&mcap, chunk_pos);
+            chunk = &chunk[16..];
+            let uncompressed_size = chunk.read_u64::<LE>().unwrap() as usize;
+            let crc = chunk.read_u32::<LE>().unwrap();
+            assert_eq!(read_str(&mut chunk), compression.name());
+            chunk = &chunk[8..];
+            let records = match compression {
+                McapCompression::Lz4 => {
+                    let mut records = vec![];
+                    io::copy(&mut lz4::Decoder::new(chunk).unwrap(), &mut records).unwrap();
+                    records
+                }
+                _ => zstd::decode_all(chunk).unwrap(),
+            };
+            assert_eq!(records.len(), uncompressed_size);
+            assert_eq!(crc32fast::hash(&records), crc);
+        }
+    }
+
+    #[test]
+    fn test_read_converted() {
+        let bag = imu_recording();
+        let mcap = Bag::from_vec(convert(McapCompression::Zstd)).unwrap();
+        assert!(mcap.mcap().is_some());
+        assert_eq!(mcap.conn_count(), 2);
+
+        let all = TopicSelection::all();
+        let connections = get_connections(&bag, &all).unwrap();
+        let mcap_connections = get_connections(&mcap, &all).unwrap();
+        assert_eq!(mcap_connections.len(), 2);
+        for (connection, mcap_connection) in connections.iter().zip(mcap_connections.iter()) {
+            assert_eq!(
+                Connection {
+                    id: connection.id,
+                    ..mcap_connection.clone()
+                },
+                *connection
+            );
+        }
+        assert_eq!(
+            get_message_count(&mcap, &all).unwrap(),
+            get_message_count(&bag, &all).unwrap()
+        );
+        assert_eq!(
+            get_message_times(&mcap, &all).unwrap(),
+            get_message_times(&bag, &all).unwrap()
+        );
+        assert_eq!(get_start_time(&mcap).unwrap(), Some(START));
+        let topics = TopicSelection::parse(&["/imu"]).unwrap();
+        assert_eq!(
+            get_messages(&mcap, &topics).unwrap(),
+            get_messages(&bag, &topics).unwrap()
+        );
+        assert_eq!(mcap.messages().count(), 10);
+    }
+
+    #[test]
+    fn test_read_without_summary() {
+        let mcap = convert(McapCompression::Lz4);
+        let footer_pos = mcap.len() - MCAP_MAGIC.len() - 29;
+        let (_, mut footer) = record_at(&mcap, footer_pos);
+        let summary_start = footer.read_u64::<LE>().unwrap() as usize;
+        let bag = Bag::from_vec(mcap[..summary_start].to_vec()).unwrap();
+        assert_eq!(bag.chunk_count(), 0);
+
+        let all = TopicSelection::all();
+        assert_eq!(get_connections(&bag, &all).unwrap().len(), 2);
+        assert_eq!(
+            get_message_count(&bag, &all).unwrap(),
+            BTreeMap::from([("/data".to_string(), 5), ("/imu".to_string(), 5)])
+        );
+        assert_eq!(get_start_time(&bag).unwrap(), Some(START));
+    }
+
+    #[test]
+    fn test_read_without_message_indexes() {
+        let mcap = convert(McapCompression::Zstd);
+        let mut index = McapIndex::parse(&mcap).unwrap();
+        let indexed = McapFile {
+            index: &index,
+            data: &mcap,
+        }
+        .message_times()
+        .unwrap();
+        assert_eq!(indexed.values().map(Vec::len).sum::<usize>(), 10);
+
+        // Message indexes are optional, the chunks still have the messages
+        for chunk_index in index.chunk_indexes.iter_mut() {
+            chunk_index.message_index_offsets.clear();
+        }
+        let file = McapFile {
+            index: &index,
+            data: &mcap,
+        };
+        assert_eq!(file.message_times().unwrap(), indexed);
+    }
+
+    #[test]
+    fn test_read_oversized_chunk() {
+        let mut mcap = convert(McapCompression::Lz4);
+        let index = McapIndex::parse(&mcap).unwrap();
+        // The uncompressed size follows the opcode, length, start and end time of the chunk
+        let size_pos = index.chunk_indexes[0].chunk_pos as usize + 9 + 16;
+        mcap[size_pos..size_pos + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let bag = Bag::from_vec(mcap).unwrap();
+        let error = bag.messages().find_map(Result::err).unwrap();
+        assert!(error
+            .to_string()
+            .contains("expected 18446744073709551615 bytes"));
+    }
+
+    #[test]
+    fn test_read_cdr() {
+        let mut writer = McapWriter::new(vec![], McapOptions::default()).unwrap();
+        let schema_id = writer.add_schema(
+            "test_msgs/msg/Sample",
+            "ros2msg",
+            ROS2_SAMPLE_DEFINITION.as_bytes(),
+        );
+        let qos = "- history: 3\n  depth: 0\n  reliability: 1\n  durability: 1\n";
+        let sample = writer.add_channel(
+            schema_id,
+            "/sample",
+            "cdr",
+            BTreeMap::from([("offered_qos_profiles".to_string(), qos.to_string())]),
+        );
+        let json = writer.add_channel(0, "/json", "json", BTreeMap::new());
+        for i in 0..3u64 {
+            let time = (i + 1) * 1_000_000_000;
+            let data = cdr_sample_message(time, "base", i as f64);
+            writer
+                .write_message(sample, i as u32, time, time, &data)
+                .unwrap();
+            writer
+                .write_message(json, i as u32, time, time, b"{}")
+                .unwrap();
+        }
+        let bag = Bag::from_vec(writer.finish().unwrap()).unwrap();
+
+        let connections = get_connections(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(connections[0].encoding, MessageEncoding::Cdr);
+        assert_eq!(connections[0].tp, "test_msgs/msg/Sample");
+        assert!(connections[0].latching);
+        assert_eq!(
+            connections[1].encoding,
+            MessageEncoding::Other("json".to_string())
+        );
+
+        let mut values = vec![];
+        let mut decoders = BTreeMap::new();
+        read_messages(&bag, &TopicSelection::all(), |connection, time, data| {
+            if connection.encoding != MessageEncoding::Cdr {
+                return Ok(());
+            }
+            let decoder = match decoders.entry(connection.id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(MessageDecoder::for_connection(connection)?),
+            };
+            assert_eq!(decoder.header_stamp(data)?, Some(time));
+            values.push(decoder.decode(data)?.get("point.x").cloned());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(values, [0.0, 1.0, 2.0].map(|x| Some(Value::Float(x))));
+
+        // CDR messages cannot go into a bag
+        let mut bag_writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let conn_id = bag_writer.add_connection(&connections[0]);
+        assert!(bag_writer.write_message(conn_id, 0, &[]).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        bag::Bag,
+        check::check,
+        indexing::{get_connections, get_message_count},
+        merge::{merge, MergeInput},
+        record::Connection,
+        selection::TopicSelection,
+        tests::sample_bags::{float32_connection, float32_recording},
+        writer::BagWriter,
+    };
+
+    fn merged(inputs: &[MergeInput]) -> Bag {
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        merge(inputs, &mut writer).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+        assert!(check(&bytes).is_ok());
+        Bag::from_vec(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_merge_time_ordered() {
+        let vehicle = Connection {
+            caller_id: Some("/vehicle".to_string()),
+            ..float32_connection("/data")
+        };
+        let inputs = [
+            MergeInput {
+                bag: float32_recording(&float32_connection("/data"), (0..100).map(|i| 2 * i)),
+                prefix: None,
+            },
+            MergeInput {
+                bag: float32_recording(&float32_connection("/data"), (0..100).map(|i| 2 * i + 1)),
+                prefix: None,
+            },
+            MergeInput {
+                bag: float32_recording(&vehicle, (0..10).map(|i| 50 * i)),
+                prefix: None,
+            },
+        ];
+        let bag = merged(&inputs);
+        let connections = get_connections(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[1].caller_id.as_deref(), Some("/vehicle"));
+        assert_eq!(
+            get_message_count(&bag, &TopicSelection::all()).unwrap()["/data"],
+            210
+        );
+
+        let messages: Vec<_> = bag.messages().map(Result::unwrap).collect();
+        assert!(messages.windows(2).all(|pair| pair[0].time <= pair[1].time));
+        assert_eq!(messages[4].time, 3);
+        assert_eq!(messages[4].data, 3f32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_merge_unordered_input() {
+        // Recorded back to front, the file order is the reverse of the time order
+        let inputs = [
+            MergeInput {
+                bag: float32_recording(&float32_connection("/data"), (0..100).rev().map(|i| 2 * i)),
+                prefix: None,
+            },
+            MergeInput {
+                bag: float32_recording(&float32_connection("/data"), (0..100).map(|i| 2 * i + 1)),
+                prefix: None,
+            },
+        ];
+        let bag = merged(&inputs);
+        let times: Vec<u64> = bag
+            .messages()
+            .map(|message| message.unwrap().time)
+            .collect();
+        assert_eq!(times, (0..200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_merge_with_prefix() {
+        let inputs = [
+            MergeInput {
+                bag: float32_recording(&float32_connection("/gps"), 0..5),
+                prefix: Some("/vehicle/".to_string()),
+            },
+            MergeInput {
+                bag: float32_recording(&float32_connection("gps"), 0..3),
+                prefix: Some("base".to_string()),
+            },
+        ];
+        let count = get_message_count(&merged(&inputs), &TopicSelection::all()).unwrap();
+        assert_eq!(count["/vehicle/gps"], 5);
+        assert_eq!(count["/base/gps"], 3);
+    }
+}
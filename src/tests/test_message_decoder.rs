@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use crate::{
+        message_decoder::{MessageDecoder, Value},
+        record::{Connection, MessageEncoding},
+        tests::{
+            sample_bags::{cdr_sample_message, imu_message, ros2_sample_connection},
+            sample_messages::{float32::FLOAT32, imu::SENSOR_IMU_MESSAGE},
+        },
+    };
+
+    #[test]
+    fn test_decode_imu() {
+        let decoder = MessageDecoder::new("sensor_msgs/Imu", SENSOR_IMU_MESSAGE).unwrap();
+        assert!(decoder.has_header());
+        let data = imu_message(7, 1_500_000_000, "imu_link");
+        let message = decoder.decode(&data).unwrap();
+        assert_eq!(message.get("header.seq"), Some(&Value::UInt(7)));
+        assert_eq!(
+            message.get("header.stamp"),
+            Some(&Value::Time(1_500_000_000))
+        );
+        assert_eq!(
+            message.get("header.frame_id"),
+            Some(&Value::String("imu_link".to_string()))
+        );
+        assert_eq!(message.get("orientation.w"), Some(&Value::Float(1.0)));
+        assert_eq!(
+            message.get("linear_acceleration_covariance.8"),
+            Some(&Value::Float(0.0))
+        );
+        assert_eq!(
+            message.get("linear_acceleration.z").and_then(Value::as_f64),
+            Some(9.81)
+        );
+        assert_eq!(message.get("orientation.v"), None);
+        assert_eq!(decoder.header_stamp(&data).unwrap(), Some(1_500_000_000));
+        assert!(decoder.decode(&data[..data.len() - 1]).is_err());
+
+        let decoder = MessageDecoder::new("std_msgs/Float32", FLOAT32).unwrap();
+        assert!(!decoder.has_header());
+        assert_eq!(
+            decoder.decode(&2f32.to_le_bytes()).unwrap(),
+            Value::Message(vec![("data".to_string(), Value::Float(2.0))])
+        );
+    }
+
+    #[test]
+    fn test_decode_arrays_and_constants() {
+        let definition = "uint8 RAW=0\nPoint[] points\nuint8[] data\nstring[2] names\n\
+            ================================================================================\n\
+            MSG: my_msgs/Point\nfloat32 x\n";
+        let decoder = MessageDecoder::new("my_msgs/Cloud", definition).unwrap();
+        let mut data = vec![];
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&1f32.to_le_bytes());
+        data.extend_from_slice(&2f32.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(&[1, 2, 3]);
+        for name in ["a", "bc"] {
+            data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+        }
+        let message = decoder.decode(&data).unwrap();
+        assert_eq!(message.get("points.1.x"), Some(&Value::Float(2.0)));
+        assert_eq!(message.get("data"), Some(&Value::Bytes(vec![1, 2, 3])));
+        assert_eq!(
+            message.get("names.1"),
+            Some(&Value::String("bc".to_string()))
+        );
+        assert_eq!(message.get("RAW=0"), None);
+        assert_eq!(decoder.encode(&message).unwrap(), data);
+    }
+
+    #[test]
+    fn test_encode_and_visit_headers() {
+        let decoder = MessageDecoder::new("sensor_msgs/Imu", SENSOR_IMU_MESSAGE).unwrap();
+        let data = imu_message(7, 1_500_000_000, "imu_link");
+        let mut message = decoder.decode(&data).unwrap();
+        assert_eq!(decoder.encode(&message).unwrap(), data);
+
+        *message.get_mut("header.frame_id").unwrap() = Value::String("base_link".to_string());
+        assert_eq!(
+            decoder.encode(&message).unwrap(),
+            imu_message(7, 1_500_000_000, "base_link")
+        );
+        *message.get_mut("orientation.w").unwrap() = Value::Int(1);
+        assert!(decoder.encode(&message).is_err());
+
+        let definition = "Stamped[] poses\n\
+            ================================================================================\n\
+            MSG: my_msgs/Stamped\nHeader header\n\
+            ================================================================================\n\
+            MSG: std_msgs/Header\nuint32 seq\ntime stamp\nstring frame_id\n";
+        let decoder = MessageDecoder::new("my_msgs/Path", definition).unwrap();
+        assert!(decoder.uses_header() && !decoder.has_header());
+        let mut data = 2u32.to_le_bytes().to_vec();
+        for seq in 0..2u32 {
+            data.extend_from_slice(&imu_message(seq, 0, "map")[..19]);
+        }
+        let mut message = decoder.decode(&data).unwrap();
+        let mut seqs = vec![];
+        decoder.visit_headers(&mut message, |header| {
+            seqs.push(header.get("seq").cloned());
+            *header.get_mut("stamp").unwrap() = Value::Time(5);
+        });
+        assert_eq!(seqs, [Some(Value::UInt(0)), Some(Value::UInt(1))]);
+        assert_eq!(message.get("poses.1.header.stamp"), Some(&Value::Time(5)));
+    }
+
+    #[test]
+    fn test_decode_cdr() {
+        let connection = ros2_sample_connection("/sample");
+        let decoder = MessageDecoder::for_connection(&connection).unwrap();
+        assert!(decoder.has_header());
+        let data = cdr_sample_message(1_500_000_000, "base", 2.5);
+        assert_eq!(decoder.header_stamp(&data).unwrap(), Some(1_500_000_000));
+        let message = decoder.decode(&data).unwrap();
+        assert_eq!(
+            message.get("header.frame_id"),
+            Some(&Value::String("base".to_string()))
+        );
+        assert_eq!(message.get("point.x"), Some(&Value::Float(2.5)));
+        assert_eq!(message.get("weights.1"), Some(&Value::Float(0.25)));
+        assert_eq!(message.get("label"), Some(&Value::String("ok".to_string())));
+        assert!(decoder.encode(&message).is_err());
+
+        // Big endian, the float64 is aligned to 8 bytes after the int32
+        let decoder = MessageDecoder::for_connection(&Connection {
+            tp: "test_msgs/msg/Pair".to_string(),
+            message_definition: "int32 a\nfloat64 b\n".to_string(),
+            ..connection.clone()
+        })
+        .unwrap();
+        let mut data = vec![0, 0, 0, 0];
+        data.extend_from_slice(&(-3i32).to_be_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&1.5f64.to_be_bytes());
+        let message = decoder.decode(&data).unwrap();
+        assert_eq!(message.get("a"), Some(&Value::Int(-3)));
+        assert_eq!(message.get("b"), Some(&Value::Float(1.5)));
+        assert!(decoder.decode(&data[..12]).is_err());
+
+        let connection = Connection {
+            encoding: MessageEncoding::Other("protobuf".to_string()),
+            ..connection
+        };
+        assert!(MessageDecoder::for_connection(&connection).is_err());
+    }
+}
@@ -1,7 +1,5 @@
 #[cfg(test)]
 mod tests {
-    use regex::Regex;
-
     use crate::{
         message_parser::{match_repeat, parse_message_definition, Repeated},
         tests::sample_messages::{float32::FLOAT32, imu::SENSOR_IMU_MESSAGE},
@@ -9,10 +7,38 @@ mod tests {
 
     #[test]
     fn test_parse_std_message() {
-        let msg = FLOAT32;
-        let parsed = parse_message_definition(SENSOR_IMU_MESSAGE);
-        // println!("{:#?}", parsed);
-        assert!(true);
+        let (fields, type_def) = parse_message_definition(FLOAT32).unwrap();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].field_type, "float32");
+        assert!(type_def.is_empty());
+
+        let (fields, type_def) = parse_message_definition(SENSOR_IMU_MESSAGE).unwrap();
+        assert_eq!(fields.len(), 7);
+        assert_eq!(fields[2].field_name, "orientation_covariance");
+        assert_eq!(fields[2].field_repeat, Repeated::Fixed(9));
+        assert_eq!(
+            type_def.keys().collect::<Vec<_>>(),
+            [
+                "geometry_msgs/Quaternion",
+                "geometry_msgs/Vector3",
+                "std_msgs/Header"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_definition() {
+        let (fields, _) = parse_message_definition("float32\tdata").unwrap();
+        assert_eq!(fields[0].field_name, "data");
+        // The type of a section is missing
+        let nested = format!("float32 data\n{}\nfloat32 data", "=".repeat(80));
+        for definition in ["float32", "float32[x] data", &nested] {
+            assert!(
+                parse_message_definition(definition).is_err(),
+                "{}",
+                definition
+            );
+        }
     }
 
     #[test]
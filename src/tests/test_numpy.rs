@@ -0,0 +1,174 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use zip::ZipArchive;
+
+    use crate::{
+        indexing::MessageSource,
+        message_decoder::MessageDecoder,
+        numpy::{expand_field_paths, export_npy, export_npz, write_npy, DType, NumericColumn},
+        tests::sample_bags::{imu_connection, imu_recording, stamped_recording, START},
+        time::NANOS_PER_SEC,
+    };
+
+    fn imu_decoder() -> MessageDecoder {
+        MessageDecoder::for_connection(&imu_connection("/imu")).unwrap()
+    }
+
+    /// Header dict and data of an npy file
+    fn read_npy(npy: &[u8]) -> (String, &[u8]) {
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        let len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + len) % 64, 0);
+        let header = String::from_utf8(npy[10..10 + len].to_vec()).unwrap();
+        (header.trim_end().to_string(), &npy[10 + len..])
+    }
+
+    #[test]
+    fn test_write_npy() {
+        let mut npy = vec![];
+        write_npy(&mut npy, DType::UInt16, &[2, 3], &[0; 12]).unwrap();
+        let (header, data) = read_npy(&npy);
+        assert_eq!(
+            header,
+            "{'descr': '<u2', 'fortran_order': False, 'shape': (2, 3), }"
+        );
+        assert_eq!(data.len(), 12);
+    }
+
+    #[test]
+    fn test_expand_field_paths() {
+        let decoder = imu_decoder();
+        let paths = |paths: &[&str]| {
+            let paths: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+            expand_field_paths(decoder.fields(), &paths)
+        };
+        assert_eq!(
+            paths(&["linear_acceleration.x,y,z"]),
+            [
+                "linear_acceleration.x",
+                "linear_acceleration.y",
+                "linear_acceleration.z"
+            ]
+        );
+        assert_eq!(
+            paths(&["header.stamp,orientation_covariance", "orientation.w"]),
+            ["header.stamp", "orientation_covariance", "orientation.w"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_numeric_fields() {
+        let decoder = imu_decoder();
+        let column = NumericColumn::new(&decoder, "orientation_covariance").unwrap();
+        assert_eq!((column.dtype, column.len), (DType::Float64, Some(9)));
+        let column = NumericColumn::new(&decoder, "orientation_covariance.4").unwrap();
+        assert_eq!((column.dtype, column.len), (DType::Float64, None));
+        let column = NumericColumn::new(&decoder, "header.stamp").unwrap();
+        assert_eq!(column.dtype, DType::Time);
+
+        for path in [
+            "header",
+            "header.frame_id",
+            "orientation.v",
+            "orientation_covariance.9",
+        ] {
+            assert!(NumericColumn::new(&decoder, path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_export_npz() {
+        let paths = vec![
+            "linear_acceleration.z,x".to_string(),
+            "angular_velocity_covariance".to_string(),
+            "header.seq".to_string(),
+        ];
+        let mut npz = io::Cursor::new(vec![]);
+        let report = export_npz(
+            MessageSource::Bag(imu_recording()),
+            "/imu",
+            &paths,
+            &mut npz,
+        )
+        .unwrap();
+        assert_eq!(report.messages, 5);
+
+        let mut archive = ZipArchive::new(npz).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert_eq!(names.len(), 5);
+        let mut read = |name: &str| {
+            let mut npy = vec![];
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_end(&mut npy)
+                .unwrap();
+            let (header, data) = read_npy(&npy);
+            (header, data.to_vec())
+        };
+
+        let (header, data) = read("time.npy");
+        assert!(header.contains("'descr': '<M8[ns]'") && header.contains("'shape': (5,)"));
+        let times: Vec<i64> = data
+            .chunks(8)
+            .map(|time| i64::from_le_bytes(time.try_into().unwrap()))
+            .collect();
+        let seconds = |i: u64| (START + i * NANOS_PER_SEC) as i64;
+        assert_eq!(times, (0..5).map(seconds).collect::<Vec<_>>());
+
+        let (header, data) = read("linear_acceleration.z.npy");
+        assert!(header.contains("'descr': '<f8'"));
+        assert_eq!(&data[..8], &9.81f64.to_le_bytes());
+
+        let (header, data) = read("angular_velocity_covariance.npy");
+        assert!(header.contains("'shape': (5, 9)"));
+        assert_eq!(data.len(), 5 * 9 * 8);
+
+        let (header, data) = read("header.seq.npy");
+        assert!(header.contains("'descr': '<u4'"));
+        let seqs: Vec<u8> = (0..5u32).flat_map(u32::to_le_bytes).collect();
+        assert_eq!(data, seqs);
+        assert!(archive.by_name("linear_acceleration.x.npy").is_ok());
+    }
+
+    #[test]
+    fn test_export_npz_names() {
+        // A field named `time` would overwrite the record time in the archive
+        let paths = vec!["time,value".to_string()];
+        let mut npz = io::Cursor::new(vec![]);
+        let source = MessageSource::Bag(stamped_recording());
+        let error = export_npz(source, "/stamped", &paths, &mut npz).unwrap_err();
+        assert!(error.to_string().starts_with("time is selected twice"));
+
+        let paths = vec!["header.seq".to_string(), "header.seq".to_string()];
+        let mut npz = io::Cursor::new(vec![]);
+        assert!(export_npz(
+            MessageSource::Bag(imu_recording()),
+            "/imu",
+            &paths,
+            &mut npz
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_export_npy() {
+        let mut npy = vec![];
+        let paths = vec!["header.seq".to_string()];
+        export_npy(
+            MessageSource::Bag(imu_recording()),
+            "/imu",
+            &paths,
+            &mut npy,
+        )
+        .unwrap();
+        let (header, data) = read_npy(&npy);
+        assert!(header.contains("'shape': (5,)"));
+        assert_eq!(data.len(), 20);
+
+        let paths = vec!["header.seq,stamp".to_string()];
+        assert!(export_npy(MessageSource::Bag(imu_recording()), "/imu", &paths, vec![]).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        bag::Bag,
+        check::check,
+        indexing::{get_connections, get_messages, MessageSource},
+        message_decoder::{MessageDecoder, Value},
+        record::Connection,
+        redact::{redact_messages, RedactAction, RedactRule, Redactor},
+        selection::TopicSelection,
+        tests::sample_bags::{float32_connection, imu_connection, imu_message, imu_recording},
+        writer::BagWriter,
+    };
+
+    const FIX: &str = "Header header\nfloat64 latitude\nfloat64 longitude\nfloat64 altitude\n\
+        ================================================================================\n\
+        MSG: std_msgs/Header\nuint32 seq\ntime stamp\nstring frame_id\n";
+
+    fn fix_connection() -> Connection {
+        Connection {
+            tp: "my_msgs/Fix".to_string(),
+            md5sum: "0123456789abcdef0123456789abcdef".to_string(),
+            message_definition: FIX.to_string(),
+            ..float32_connection("/gps/fix")
+        }
+    }
+
+    fn fix_message(seq: u32) -> Vec<u8> {
+        let mut data = imu_message(seq, 5, "gps")[..19].to_vec();
+        for value in [48.1, 11.6, 520.0f64] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rule = RedactRule::parse("zero /gps/fix:latitude, longitude").unwrap();
+        assert!(rule.topics.matches("/gps/fix") && !rule.topics.matches("/imu"));
+        assert_eq!(
+            rule.action,
+            RedactAction::Zero(vec!["latitude".to_string(), "longitude".to_string()])
+        );
+        let rule = RedactRule::parse("replace frame_id").unwrap();
+        assert!(rule.topics.matches("/imu"));
+        assert_eq!(
+            rule.action,
+            RedactAction::Replace(vec!["frame_id".to_string()], None)
+        );
+        let rule = RedactRule::parse("replace /imu:header.frame_id=base:link").unwrap();
+        assert_eq!(
+            rule.action,
+            RedactAction::Replace(
+                vec!["header.frame_id".to_string()],
+                Some("base:link".to_string())
+            )
+        );
+        assert!(RedactRule::parse("drop").is_err());
+        assert!(RedactRule::parse("zero /gps/fix:").is_err());
+        assert!(RedactRule::parse("blur /camera").is_err());
+        let rules = RedactRule::parse_lines("# sponsor release\n\ndrop /camera/*\nzero latitude\n");
+        assert_eq!(rules.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_redact_messages() {
+        let rules = [
+            "drop /data",
+            "zero /imu:linear_acceleration.z",
+            "replace frame_id",
+        ]
+        .iter()
+        .map(|rule| RedactRule::parse(rule).unwrap())
+        .collect();
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let report = redact_messages(
+            MessageSource::Bag(imu_recording()),
+            &mut writer,
+            Redactor::new(rules),
+        )
+        .unwrap();
+        assert_eq!(report.messages, 5);
+        assert_eq!(report.dropped["/data"], 5);
+        let changed =
+            |topic: &str, field: &str| report.changed[&(topic.to_string(), field.to_string())];
+        assert_eq!(changed("/imu", "linear_acceleration.z"), 5);
+        assert_eq!(changed("/imu", "header.frame_id"), 5);
+        assert!(report.unmatched.is_empty());
+
+        let bytes = writer.finish().unwrap().into_inner();
+        assert!(check(&bytes).is_ok());
+        let bag = Bag::from_vec(bytes).unwrap();
+        let connections = get_connections(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].md5sum, imu_connection("/imu").md5sum);
+
+        let decoder = MessageDecoder::for_connection(&imu_connection("/imu")).unwrap();
+        let imu = get_messages(&bag, &TopicSelection::parse(&["/imu"]).unwrap()).unwrap();
+        let message = decoder.decode(&imu[1]).unwrap();
+        assert_eq!(
+            message.get("linear_acceleration.z"),
+            Some(&Value::Float(0.0))
+        );
+        assert_eq!(message.get("orientation.w"), Some(&Value::Float(1.0)));
+        assert_eq!(message.get("header.seq"), Some(&Value::UInt(1)));
+        // Pseudonyms are stable across messages
+        let frame_ids: Vec<_> = imu
+            .iter()
+            .map(|data| {
+                decoder
+                    .decode(data)
+                    .unwrap()
+                    .get("header.frame_id")
+                    .cloned()
+            })
+            .collect();
+        let anon = |name: &str| Some(Value::String(name.to_string()));
+        assert_eq!(
+            frame_ids,
+            [
+                anon("anon_1"),
+                anon("anon_2"),
+                anon("anon_1"),
+                anon("anon_2"),
+                anon("anon_1")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replace_values() {
+        let rules = vec![
+            RedactRule::parse("replace /gps/fix:altitude=0.5").unwrap(),
+            RedactRule::parse("replace /gps/fix:frame_id=map").unwrap(),
+        ];
+        let mut redactor = Redactor::new(rules);
+        let message = fix_message(0);
+        let data = redactor
+            .redact(&fix_connection(), &message)
+            .unwrap()
+            .unwrap();
+        let fix = MessageDecoder::new("my_msgs/Fix", FIX)
+            .unwrap()
+            .decode(&data)
+            .unwrap();
+        assert_eq!(fix.get("altitude"), Some(&Value::Float(0.5)));
+        assert_eq!(
+            fix.get("header.frame_id"),
+            Some(&Value::String("map".to_string()))
+        );
+
+        let mut redactor =
+            Redactor::new(vec![RedactRule::parse("replace latitude=north").unwrap()]);
+        assert!(redactor.redact(&fix_connection(), &fix_message(0)).is_err());
+        let mut redactor = Redactor::new(vec![RedactRule::parse("replace latitude").unwrap()]);
+        assert!(redactor.redact(&fix_connection(), &fix_message(0)).is_err());
+    }
+
+    #[test]
+    fn test_unmatched_rules() {
+        let rules = [
+            "zero /gps/fix:lattitude",
+            "zero /gps/fix:longitude",
+            "replace /gps/fox:frame_id",
+            "drop /camera/*",
+        ]
+        .iter()
+        .map(|rule| RedactRule::parse(rule).unwrap())
+        .collect();
+        let mut redactor = Redactor::new(rules);
+        redactor.redact(&fix_connection(), &fix_message(0)).unwrap();
+        assert_eq!(
+            redactor.into_report().unmatched,
+            [
+                "zero /gps/fix:lattitude",
+                "replace /gps/fox:frame_id",
+                "drop /camera/*"
+            ]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::io;
+
+    use crate::{
+        bag::Bag,
+        indexing::{get_connections, get_messages, MessageSource},
+        remap::{parse_frame_rule, remap_messages, Remapper, TopicRule},
+        selection::TopicSelection,
+        tests::sample_bags::{imu_connection, imu_message, imu_recording, START},
+        time::NANOS_PER_SEC,
+        writer::BagWriter,
+    };
+
+    #[test]
+    fn test_topic_rules() {
+        let rules = [
+            "/old:=/new",
+            "re:^/camera_(\\w+)/(.*):=/cam/$1/$2",
+            "/a.b:=/$1",
+        ]
+        .iter()
+        .map(|rule| TopicRule::parse(rule).unwrap())
+        .collect();
+        let remapper = Remapper::new(rules, BTreeMap::new());
+        assert_eq!(remapper.topic("/old"), "/new");
+        assert_eq!(remapper.topic("/old/sub"), "/old/sub");
+        assert_eq!(remapper.topic("/camera_front/image"), "/cam/front/image");
+        assert_eq!(remapper.topic("/a.b"), "/$1");
+        assert_eq!(remapper.topic("/axb"), "/axb");
+        assert!(TopicRule::parse("/old=/new").is_err());
+        assert!(TopicRule::parse("re:(:=/new").is_err());
+        assert_eq!(
+            parse_frame_rule("imu:=imu_link").unwrap(),
+            ("imu".to_string(), "imu_link".to_string())
+        );
+    }
+
+    #[test]
+    fn test_remap_messages() {
+        let rules = vec![TopicRule::parse("re:^/(imu|gps)$:=/sensors/$1").unwrap()];
+        let frames = BTreeMap::from([("imu".to_string(), "imu_link".to_string())]);
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let report = remap_messages(
+            MessageSource::Bag(imu_recording()),
+            &mut writer,
+            &mut Remapper::new(rules, frames),
+        )
+        .unwrap();
+        assert_eq!(report.messages, 10);
+        assert_eq!(report.frames, 3);
+        assert_eq!(report.topics["/imu"], "/sensors/imu");
+
+        let bag = Bag::from_vec(writer.finish().unwrap().into_inner()).unwrap();
+        let connections = get_connections(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(connections[0].topic, "/sensors/imu");
+        assert_eq!(connections[0].md5sum, imu_connection("").md5sum);
+        assert_eq!(connections[1].topic, "/data");
+
+        let messages =
+            get_messages(&bag, &TopicSelection::parse(&["/sensors/imu"]).unwrap()).unwrap();
+        let stamp = |i: u64| START + i * NANOS_PER_SEC - 20_000_000;
+        assert_eq!(messages[2], imu_message(2, stamp(2), "imu_link"));
+        assert_eq!(messages[3], imu_message(3, stamp(3), "other"));
+    }
+}
@@ -0,0 +1,86 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        bag::Bag,
+        indexing::{get_message_times, get_messages, get_start_time, MessageSource},
+        restamp::{restamp_messages, shift_time, RestampOptions, Restamper},
+        selection::TopicSelection,
+        tests::sample_bags::{float32_connection, imu_message, imu_recording, START},
+        time::{parse_offset, NANOS_PER_SEC},
+        writer::BagWriter,
+    };
+
+    fn restamp(options: RestampOptions) -> Bag {
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        restamp_messages(
+            MessageSource::Bag(imu_recording()),
+            &mut writer,
+            &mut Restamper::new(options),
+        )
+        .unwrap();
+        Bag::from_vec(writer.finish().unwrap().into_inner()).unwrap()
+    }
+
+    fn imu_messages(bag: &Bag) -> Vec<Vec<u8>> {
+        get_messages(bag, &TopicSelection::parse(&["/imu"]).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_shift_record_times() {
+        let bag = restamp(RestampOptions {
+            offset: -10 * NANOS_PER_SEC as i64,
+            ..Default::default()
+        });
+        assert_eq!(
+            get_start_time(&bag).unwrap(),
+            Some(START - 10 * NANOS_PER_SEC)
+        );
+        assert_eq!(
+            imu_messages(&bag)[1],
+            imu_message(1, START + 980_000_000, "other")
+        );
+
+        let bag = restamp(RestampOptions {
+            offset: -10 * NANOS_PER_SEC as i64,
+            shift_headers: true,
+            ..Default::default()
+        });
+        let messages = imu_messages(&bag);
+        assert_eq!(messages[1], imu_message(1, START - 9_020_000_000, "other"));
+        assert_eq!(messages[4], imu_message(4, 0, "imu"));
+
+        let mut restamper = Restamper::new(RestampOptions {
+            offset: -2 * START as i64,
+            ..Default::default()
+        });
+        assert!(restamper
+            .restamp(&float32_connection("/data"), START, &[0; 4])
+            .is_err());
+    }
+
+    #[test]
+    fn test_use_header_time() {
+        let bag = restamp(RestampOptions {
+            use_header_time: true,
+            ..Default::default()
+        });
+        let times = get_message_times(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(times["/imu"][0], START - 20_000_000);
+        // Without stamp the record time stays
+        assert_eq!(times["/imu"][4], START + 4 * NANOS_PER_SEC);
+        assert_eq!(times["/data"][0], START);
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("-3.5").unwrap(), -3_500_000_000);
+        assert_eq!(parse_offset("+250ms").unwrap(), 250_000_000);
+        assert_eq!(parse_offset("2h").unwrap(), 7200 * NANOS_PER_SEC as i64);
+        assert!(parse_offset("--1").is_err());
+        assert_eq!(shift_time(5, -5).unwrap(), 0);
+        assert!(shift_time(5, -6).is_err());
+        assert!(shift_time(u32::MAX as u64 * NANOS_PER_SEC, NANOS_PER_SEC as i64).is_err());
+    }
+}
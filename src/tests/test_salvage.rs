@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::{
+        record::BAG_MAGIC,
+        salvage::{salvage, salvage_bag},
+        tests::sample_bags::float32_bag,
+        writer::BAG_HEADER_LEN,
+    };
+
+    fn find(bag: &[u8], needle: &[u8]) -> usize {
+        bag.windows(needle.len())
+            .position(|window| window == needle)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_salvage_intact_bag() {
+        let bag = float32_bag(10);
+        let mut values = vec![];
+        let report = salvage(&bag, |connection, _, data| {
+            assert_eq!(connection.topic, "/data");
+            values.push(f32::from_le_bytes(data.try_into().unwrap()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(report.messages, 10);
+        assert!(report.skipped.is_empty());
+        assert_eq!(values, (0..10).map(|i| i as f32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_salvage_truncated_bag() {
+        let bag = float32_bag(10);
+        // Cut the chunk right after the payload of the sixth message
+        let end = find(&bag, &5f32.to_le_bytes()) + 4;
+        let report = salvage(&bag[..end], |_, _, _| Ok(())).unwrap();
+        assert_eq!(report.messages, 6);
+        assert_eq!(report.skipped.len(), 1);
+    }
+
+    #[test]
+    fn test_salvage_corrupt_record() {
+        let mut bag = float32_bag(10);
+        // Break the data length of the fourth message
+        let data_len = find(&bag, &3f32.to_le_bytes()) - 4;
+        bag[data_len..data_len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut times = vec![];
+        let report = salvage(&bag, |_, time, _| {
+            times.push(time - 1_000_000_000);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(report.messages, 9);
+        assert_eq!(times, vec![0, 1, 2, 4, 5, 6, 7, 8, 9]);
+        assert!(report.skipped[0].chunk_offset.is_some());
+    }
+
+    #[test]
+    fn test_salvage_garbage_region() {
+        let bag = float32_bag(10);
+        // Header lengths that fit into the file but not into a record header
+        let chunks_pos = BAG_MAGIC.len() + BAG_HEADER_LEN;
+        let mut corrupt = bag[..chunks_pos].to_vec();
+        corrupt.extend([0, 0, 2, 0].repeat(64 * 1024));
+        corrupt.extend_from_slice(&bag[chunks_pos..]);
+
+        let dir = env::temp_dir().join(format!("rebag-test-salvage-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("corrupt.bag");
+        let output = dir.join("salvaged.bag");
+        fs::write(&input, &corrupt).unwrap();
+        let report = salvage_bag(&input, Some(&output)).unwrap();
+        assert_eq!(report.messages, 10);
+        assert_eq!(report.skipped.len(), 1);
+        let report = salvage_bag(&output, None).unwrap();
+        assert_eq!(report.messages, 10);
+        assert!(report.skipped.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
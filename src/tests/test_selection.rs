@@ -0,0 +1,44 @@
+#[cfg(test)]
+mod tests {
+    use crate::selection::TopicSelection;
+
+    const TOPICS: [&str; 7] = [
+        "/camera/front/image_raw",
+        "/camera/front/camera_info",
+        "/camera/rear/image_raw",
+        "/perception/cones",
+        "/perception/lidar/points",
+        "/odom",
+        "/tf",
+    ];
+
+    fn resolve(selectors: &[&str]) -> Vec<&'static str> {
+        TopicSelection::parse(selectors).unwrap().resolve(TOPICS)
+    }
+
+    #[test]
+    fn test_topic_selection() {
+        assert_eq!(resolve(&[]), TOPICS);
+        assert_eq!(resolve(&["/odom"]), ["/odom"]);
+        assert_eq!(
+            resolve(&["/perception/**"]),
+            ["/perception/cones", "/perception/lidar/points"]
+        );
+        assert_eq!(resolve(&["/perception/*"]), ["/perception/cones"]);
+        assert_eq!(
+            resolve(&["/camera/**", "!/camera/*/image_raw"]),
+            ["/camera/front/camera_info"]
+        );
+        assert_eq!(
+            resolve(&["!/camera/**", "!/perception/**"]),
+            ["/odom", "/tf"]
+        );
+        assert_eq!(resolve(&["/{odom,tf}"]), ["/odom", "/tf"]);
+        assert_eq!(
+            resolve(&["re:image_raw$"]),
+            ["/camera/front/image_raw", "/camera/rear/image_raw"]
+        );
+        assert_eq!(resolve(&["/od?m", "/t?"]), ["/odom", "/tf"]);
+        assert!(TopicSelection::parse(&["re:("]).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io, path::Path};
+
+    use crate::{
+        bag::Bag,
+        check::check,
+        indexing::{get_message_count, MessageSource},
+        selection::TopicSelection,
+        split::{parse_size, part_path, split, SplitCriterion, SplitPart},
+        tests::sample_bags::{recording, START},
+        time::{parse_duration, NANOS_PER_SEC},
+        writer::BagWriter,
+    };
+
+    fn split_recording(name: &str, criterion: SplitCriterion) -> (Vec<SplitPart>, Vec<Vec<u8>>) {
+        let dir = env::temp_dir().join(format!("rebag-test-split-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("run.bag");
+        let parts = split(MessageSource::Bag(recording()), &criterion, |index| {
+            Ok(BagWriter::create(&part_path(&output, index))?)
+        })
+        .unwrap();
+        let bytes = (0..parts.len())
+            .map(|index| fs::read(part_path(&output, index)).unwrap())
+            .collect();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), parts.len());
+        fs::remove_dir_all(&dir).unwrap();
+        (parts, bytes)
+    }
+
+    fn topic_count(bytes: &[u8], topic: &str) -> u64 {
+        let bag = Bag::from_vec(bytes.to_vec()).unwrap();
+        get_message_count(&bag, &TopicSelection::all())
+            .unwrap()
+            .get(topic)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn test_split_by_duration() {
+        let (parts, bytes) =
+            split_recording("duration", SplitCriterion::Duration(4 * NANOS_PER_SEC));
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[1].start_time, START + 4 * NANOS_PER_SEC);
+        assert_eq!(parts[1].end_time, START + 79 * NANOS_PER_SEC / 10);
+        assert_eq!(parts.iter().map(|part| part.messages).sum::<u64>(), 105);
+
+        assert_eq!(topic_count(&bytes[0], "/map"), 2);
+        for (part, bytes) in parts.iter().zip(bytes.iter()) {
+            assert!(check(bytes).is_ok());
+            // The latest latched map is re-emitted at the start of every later part
+            if part.latched > 0 {
+                assert_eq!(topic_count(bytes, "/map"), 1);
+            }
+            let total = ["/map", "/data", "/lap"]
+                .iter()
+                .map(|topic| topic_count(bytes, topic))
+                .sum::<u64>();
+            assert_eq!(total, part.messages + part.latched);
+        }
+        assert_eq!(topic_count(&bytes[1], "/data"), 40);
+        assert_eq!(topic_count(&bytes[2], "/data"), 21);
+        assert_eq!(parts[0].latched, 0);
+        assert_eq!(parts[2].latched, 1);
+    }
+
+    #[test]
+    fn test_split_on_topic() {
+        let (parts, bytes) = split_recording("topic", SplitCriterion::OnTopic("/lap".to_string()));
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[1].start_time, START + 3 * NANOS_PER_SEC);
+        assert_eq!(topic_count(&bytes[0], "/lap"), 0);
+        assert_eq!(topic_count(&bytes[1], "/lap"), 1);
+        assert_eq!(topic_count(&bytes[1], "/data"), 40);
+        assert_eq!(topic_count(&bytes[2], "/map"), 1);
+    }
+
+    #[test]
+    fn test_split_by_size_and_count() {
+        let (parts, bytes) = split_recording("size", SplitCriterion::Size(6000));
+        assert!(parts.len() > 1);
+        for bytes in bytes.iter() {
+            assert!(check(bytes).is_ok());
+            assert!(bytes.len() < 8000);
+        }
+
+        let (parts, _) = split_recording("count", SplitCriterion::Count(25));
+        assert_eq!(
+            parts.iter().map(|part| part.messages).collect::<Vec<_>>(),
+            [25, 25, 25, 25, 5]
+        );
+        assert!(split(
+            MessageSource::Bag(recording()),
+            &SplitCriterion::Count(0),
+            |_| Ok(BagWriter::new(io::Cursor::new(vec![]))?)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_size_and_duration() {
+        assert_eq!(parse_size("1GiB").unwrap(), 1 << 30);
+        assert_eq!(parse_size("1.5MB").unwrap(), 1_500_000);
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert!(parse_size("1 TB").is_err());
+        assert_eq!(parse_duration("60s").unwrap(), 60 * NANOS_PER_SEC);
+        assert_eq!(parse_duration("10min").unwrap(), 600 * NANOS_PER_SEC);
+        assert_eq!(parse_duration("250ms").unwrap(), NANOS_PER_SEC / 4);
+        assert_eq!(parse_duration("0.5").unwrap(), NANOS_PER_SEC / 2);
+        assert_eq!(
+            part_path(Path::new("/data/run.bag"), 3),
+            Path::new("/data/run_3.bag")
+        );
+    }
+}
@@ -0,0 +1,70 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        bag::Bag,
+        indexing::{get_message_times, get_stream_message_times},
+        selection::TopicSelection,
+        stats::{GapCriteria, RateStats},
+        tests::sample_bags::float32_connection,
+        time::NANOS_PER_SEC,
+        writer::BagWriter,
+    };
+
+    #[test]
+    fn test_rate_stats() {
+        // 10 Hz with one dropout of a second after the fifth message
+        let mut times: Vec<u64> = (0..10).map(|i| i * NANOS_PER_SEC / 10).collect();
+        times[5..]
+            .iter_mut()
+            .for_each(|time| *time += NANOS_PER_SEC);
+
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let conn_id = writer.add_connection(&float32_connection("/lidar"));
+        for time in times.iter().rev() {
+            writer.write_message(conn_id, *time, &[0; 4]).unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+        let bag = Bag::from_bytes(&bytes).unwrap();
+        let index_times = get_message_times(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(index_times["/lidar"], times);
+        assert_eq!(
+            get_stream_message_times(bytes.as_slice(), &TopicSelection::all()).unwrap(),
+            index_times
+        );
+
+        let stats = RateStats::new(
+            "/lidar",
+            &times,
+            &GapCriteria {
+                max_period: None,
+                median_factor: Some(3.0),
+            },
+        );
+        assert_eq!(stats.message_count, 10);
+        assert!((stats.median_period.unwrap() - 0.1).abs() < 1e-9);
+        assert!((stats.max_frequency.unwrap() - 10.0).abs() < 1e-6);
+        assert!((stats.min_frequency.unwrap() - 1.0 / 1.1).abs() < 1e-6);
+        assert!((stats.mean_frequency.unwrap() - 9.0 / 1.9).abs() < 1e-6);
+        assert!(stats.jitter.unwrap() > 0.3);
+        assert_eq!(stats.gaps.len(), 1);
+        assert_eq!(stats.longest_gap.as_ref(), Some(&stats.gaps[0]));
+        assert_eq!(stats.gaps[0].start_time, times[4]);
+        assert_eq!(stats.gaps[0].end_time, times[5]);
+
+        let stats = RateStats::new(
+            "/lidar",
+            &times,
+            &GapCriteria {
+                max_period: Some(2 * NANOS_PER_SEC),
+                median_factor: None,
+            },
+        );
+        assert!(stats.gaps.is_empty());
+
+        let single = RateStats::new("/lidar", &times[..1], &GapCriteria::default());
+        assert_eq!(single.mean_frequency, None);
+        assert_eq!(single.longest_gap, None);
+    }
+}
@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        bag::Bag,
+        check::check,
+        indexing::{get_connections, get_message_times, get_messages},
+        selection::TopicSelection,
+        tests::sample_bags::{recording, START},
+        time::NANOS_PER_SEC,
+        trim::{trim, TimeBound, TrimReport},
+        writer::BagWriter,
+    };
+
+    fn trimmed(start: Option<&str>, end: Option<&str>) -> (TrimReport, Bag) {
+        let start = start.map(|start| TimeBound::parse(start).unwrap());
+        let end = end.map(|end| TimeBound::parse(end).unwrap());
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let report = trim(&recording(), start, end, &mut writer).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+        assert!(check(&bytes).is_ok());
+        (report, Bag::from_vec(bytes).unwrap())
+    }
+
+    #[test]
+    fn test_trim_relative() {
+        let (report, bag) = trimmed(Some("+4s"), Some("-4s"));
+        assert_eq!(report.start_time, START + 4 * NANOS_PER_SEC);
+        assert_eq!(report.end_time, START + 6 * NANOS_PER_SEC);
+        assert_eq!(report.messages, 21);
+        assert_eq!(report.latched, 1);
+        assert!(report.skipped_chunks > 0);
+
+        assert_eq!(
+            get_connections(&bag, &TopicSelection::all()).unwrap().len(),
+            4
+        );
+        let times = get_message_times(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(times["/map"], [report.start_time]);
+        assert_eq!(times["/data"].first(), Some(&report.start_time));
+        assert_eq!(times["/data"].last(), Some(&report.end_time));
+        let map = get_messages(&bag, &TopicSelection::parse(&["/map"]).unwrap()).unwrap();
+        assert_eq!(map, [10f32.to_le_bytes()]);
+    }
+
+    #[test]
+    fn test_trim_absolute_and_open() {
+        let (report, bag) = trimmed(Some("100.5"), None);
+        assert_eq!(report.messages, 99);
+        assert_eq!(report.latched, 1);
+        let times = get_message_times(&bag, &TopicSelection::all()).unwrap();
+        assert_eq!(
+            times["/map"],
+            [START + NANOS_PER_SEC / 2, START + NANOS_PER_SEC]
+        );
+
+        // The latched state is kept even without messages in the window
+        let (report, _) = trimmed(Some("105.01"), Some("105.05"));
+        assert_eq!((report.messages, report.latched), (0, 1));
+
+        let mut writer = BagWriter::new(io::Cursor::new(vec![])).unwrap();
+        let start = TimeBound::parse("+5s").unwrap();
+        let end = TimeBound::parse("+1s").unwrap();
+        assert!(trim(&recording(), Some(start), Some(end), &mut writer).is_err());
+        assert_eq!(
+            TimeBound::parse("2023-08-18T17:00:45Z").unwrap(),
+            TimeBound::Absolute(1_692_378_045 * NANOS_PER_SEC)
+        );
+    }
+}
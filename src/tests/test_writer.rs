@@ -0,0 +1,82 @@
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::{
+        bag::{Bag, BagStream, ChunkRecord},
+        check::check,
+        indexing::{get_message_count, get_messages},
+        record::Connection,
+        selection::TopicSelection,
+        tests::sample_bags::float32_connection,
+        writer::{BagWriter, Compression, WriterOptions},
+    };
+
+    fn write_bag(options: WriterOptions) -> Vec<u8> {
+        let mut writer = BagWriter::with_options(io::Cursor::new(vec![]), options).unwrap();
+        let data = writer.add_connection(&float32_connection("/data"));
+        let latched = writer.add_connection(&Connection {
+            latching: true,
+            caller_id: Some("/node".to_string()),
+            ..float32_connection("/latched")
+        });
+        writer
+            .write_message(latched, 1, &0f32.to_le_bytes())
+            .unwrap();
+        for i in 0..1000u64 {
+            writer
+                .write_message(data, 1_000_000_000 + i, &(i as f32).to_le_bytes())
+                .unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_write_compressed_chunks() {
+        for compression in [Compression::None, Compression::Bz2, Compression::Lz4] {
+            let bytes = write_bag(WriterOptions {
+                compression,
+                chunk_size: 4096,
+            });
+            let report = check(&bytes);
+            assert!(report.is_ok(), "{}: {:?}", compression, report.problems);
+            assert_eq!(report.messages, 1001);
+
+            let bag = Bag::from_bytes(&bytes).unwrap();
+            assert!(bag.chunk_count() > 1);
+            assert_eq!(bag.conn_count(), 2);
+            let count = get_message_count(&bag, &TopicSelection::all()).unwrap();
+            assert_eq!(count["/data"], 1000);
+            assert_eq!(count["/latched"], 1);
+            let messages = get_messages(&bag, &TopicSelection::parse(&["/data"]).unwrap()).unwrap();
+            assert_eq!(messages[999], 999f32.to_le_bytes());
+            assert_eq!(BagStream::new(bytes.as_slice()).unwrap().count(), 1001);
+
+            for record in bag.chunk_records() {
+                if let ChunkRecord::Chunk(chunk) = record.unwrap() {
+                    assert_eq!(chunk.compression, compression.name());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_lz4_frame_readable_by_roslz4() {
+        let bytes = write_bag(WriterOptions {
+            compression: Compression::Lz4,
+            ..Default::default()
+        });
+        let magic = 0x184D2204u32.to_le_bytes();
+        let frame = bytes.windows(4).position(|w| w == magic).unwrap();
+        // Version 1, independent blocks, no block checksum, no content size, content checksum
+        assert_eq!(bytes[frame + 4], 0b0110_0100);
+    }
+
+    #[test]
+    fn test_parse_compression() {
+        assert_eq!("bz2".parse::<Compression>().unwrap(), Compression::Bz2);
+        assert_eq!("lz4".parse::<Compression>().unwrap(), Compression::Lz4);
+        assert_eq!("none".parse::<Compression>().unwrap(), Compression::None);
+        assert!("zstd".parse::<Compression>().is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+
+/// Record times are nanoseconds since the epoch
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Parse non-negative decimal seconds like `12.5` into nanoseconds without float rounding
+pub fn parse_seconds(s: &str) -> Option<u64> {
+    let (secs, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if secs.is_empty() && fraction.is_empty()
+        || fraction.len() > 9
+        || !secs
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let secs = if secs.is_empty() {
+        0
+    } else {
+        secs.parse::<u64>().ok()?
+    };
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<9}", fraction).parse::<u64>().ok()?
+    };
+    secs.checked_mul(NANOS_PER_SEC)?.checked_add(nanos)
+}
+
+/// Parse a duration given in seconds like `0.5`, or with a unit like `500ms`, `60s`, `10min` or `2h`
+pub fn parse_duration(s: &str) -> Result<u64> {
+    // Suffix, multiplier and divisor of the seconds
+    let units = [
+        ("ms", 1, 1000),
+        ("min", 60, 1),
+        ("s", 1, 1),
+        ("m", 60, 1),
+        ("h", 3600, 1),
+    ];
+    let (number, mul, div) = units
+        .iter()
+        .find_map(|&(unit, mul, div)| s.strip_suffix(unit).map(|number| (number, mul, div)))
+        .unwrap_or((s, 1, 1));
+    let nanos = parse_seconds(number)
+        .and_then(|nanos| nanos.checked_mul(mul))
+        .map(|nanos| nanos / div);
+    nanos.with_context(|| {
+        format!(
+            "Invalid duration '{}', expected seconds or a number with unit ms, s, min or h",
+            s
+        )
+    })
+}
+
+/// Parse a time offset like `-3.5` or `+2h` into signed nanoseconds, see `parse_duration`
+pub fn parse_offset(s: &str) -> Result<i64> {
+    let (sign, duration) = match s.strip_prefix('-') {
+        Some(duration) => (-1, duration),
+        None => (1, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let nanos = i64::try_from(parse_duration(duration)?)
+        .with_context(|| format!("Offset '{}' is too large", s))?;
+    Ok(sign * nanos)
+}
+
+/// Parse an absolute time given either as seconds since the epoch (`1692370845.5`)
+/// or as UTC date and time (`2023-08-18T17:00:45Z`, `2023-08-18 17:00:45.5`)
+pub fn parse_time(s: &str) -> Result<u64> {
+    let time = match parse_seconds(s) {
+        Some(time) => time,
+        None => parse_date_time(s)?,
+    };
+    // Bag records store the seconds of a time as u32
+    if time / NANOS_PER_SEC > u32::MAX as u64 {
+        bail!("Time '{}' is after the last time a bag can store", s);
+    }
+    Ok(time)
+}
+
+fn parse_date_time(s: &str) -> Result<u64> {
+    let re = Regex::new(
+        r"^(?<year>[0-9]{4})-(?<month>[0-9]{2})-(?<day>[0-9]{2})(?:[T ](?<hour>[0-9]{2}):(?<minute>[0-9]{2})(?::(?<second>[0-9]{2}(?:\.[0-9]{1,9})?))?)?Z?$",
+    )
+    .unwrap();
+    let Some(captures) = re.captures(s) else {
+        bail!(
+            "Invalid time '{}', expected seconds since the epoch or YYYY-MM-DDTHH:MM:SS",
+            s
+        )
+    };
+    let number = |name: &str| -> Result<u64> {
+        captures.name(name).map_or(Ok(0), |value| {
+            value
+                .as_str()
+                .parse()
+                .with_context(|| format!("Invalid time '{}'", s))
+        })
+    };
+
+    let (month, day) = (number("month")?, number("day")?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        bail!("Invalid date '{}'", s);
+    }
+    let (hour, minute) = (number("hour")?, number("minute")?);
+    let seconds = captures
+        .name("second")
+        .map_or(Some(0), |second| parse_seconds(second.as_str()))
+        .context("Invalid seconds")?;
+    if hour > 23 || minute > 59 || seconds >= 60 * NANOS_PER_SEC {
+        bail!("Invalid time of day '{}'", s);
+    }
+    let days = days_from_civil(number("year")? as i64, month, day);
+    if days < 0 {
+        bail!("Time '{}' is before the epoch", s);
+    }
+
+    (days as u64)
+        .checked_mul(86_400)
+        .and_then(|secs| secs.checked_add(hour * 3600 + minute * 60))
+        .and_then(|secs| secs.checked_mul(NANOS_PER_SEC))
+        .and_then(|nanos| nanos.checked_add(seconds))
+        .with_context(|| format!("Time '{}' is too large", s))
+}
+
+/// Format a time as UTC date and time with millisecond precision
+pub fn format_time(time: u64) -> String {
+    format!(
+        "{}.{:03}Z",
+        format_seconds(time),
+        time % NANOS_PER_SEC / 1_000_000
+    )
+}
+
+/// Format a time as RFC 3339 UTC date and time with nanosecond precision
+pub fn format_time_nanos(time: u64) -> String {
+    format!("{}.{:09}Z", format_seconds(time), time % NANOS_PER_SEC)
+}
+
+fn format_seconds(time: u64) -> String {
+    let secs = time / NANOS_PER_SEC;
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u64;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u64;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Seek, Write};
+
+use anyhow::{bail, Result};
+
+use crate::bag::{Bag, ChunkRecord, IndexRecord, MessageRecord};
+use crate::time::{format_time, parse_duration, parse_time};
+use crate::writer::BagWriter;
+
+/// One end of the time window, parsed from `+12.5s` relative to the bag start,
+/// `-30s` relative to the bag end or an absolute time as accepted by `parse_time`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBound {
+    Absolute(u64),
+    FromStart(u64),
+    FromEnd(u64),
+}
+
+impl TimeBound {
+    pub fn parse(s: &str) -> Result<Self> {
+        if let Some(offset) = s.strip_prefix('+') {
+            Ok(TimeBound::FromStart(parse_duration(offset)?))
+        } else if let Some(offset) = s.strip_prefix('-') {
+            Ok(TimeBound::FromEnd(parse_duration(offset)?))
+        } else {
+            Ok(TimeBound::Absolute(parse_time(s)?))
+        }
+    }
+
+    /// The absolute time for a bag from `start_time` to `end_time`
+    pub fn resolve(&self, start_time: u64, end_time: u64) -> u64 {
+        match self {
+            TimeBound::Absolute(time) => *time,
+            TimeBound::FromStart(offset) => start_time.saturating_add(*offset),
+            TimeBound::FromEnd(offset) => end_time.saturating_sub(*offset),
+        }
+    }
+}
+
+impl fmt::Display for TimeBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = |nanos: u64| nanos as f64 / 1e9;
+        match self {
+            TimeBound::Absolute(time) => write!(f, "{}", format_time(*time)),
+            TimeBound::FromStart(offset) => write!(f, "+{}s", seconds(*offset)),
+            TimeBound::FromEnd(offset) => write!(f, "-{}s", seconds(*offset)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TrimReport {
+    /// Absolute window the bounds resolved to, both ends included
+    pub start_time: u64,
+    pub end_time: u64,
+    /// Messages within the window
+    pub messages: u64,
+    /// Latched messages from before the window, written at its start
+    pub latched: u64,
+    /// Chunks that were not decompressed because the window does not need them
+    pub skipped_chunks: u64,
+}
+
+/// Copy the messages between `start` and `end` to `writer`, all bounds default to the whole bag.
+/// All connections are kept, and the last message before the window of every latched
+/// connection is written at the start of the window.
+pub fn trim<W: Write + Seek>(
+    bag: &Bag,
+    start: Option<TimeBound>,
+    end: Option<TimeBound>,
+    writer: &mut BagWriter<W>,
+) -> Result<TrimReport> {
+    if bag.mcap().is_some() {
+        bail!("Can only trim ROS1 bags, not MCAP files");
+    }
+    let mut conn_ids = BTreeMap::new();
+    let mut latching = vec![];
+    // Time range and whether it has latched messages, by chunk position
+    let mut chunk_infos = BTreeMap::new();
+    for record in bag.index_records() {
+        match record? {
+            IndexRecord::Connection(connection) => {
+                if connection.latching {
+                    latching.push(connection.id);
+                }
+                conn_ids.insert(connection.id, writer.add_connection(&connection));
+            }
+            IndexRecord::ChunkInfo(chunk_info) => {
+                let has_latched = chunk_info
+                    .entries()
+                    .any(|entry| latching.contains(&entry.conn_id));
+                chunk_infos.insert(
+                    chunk_info.chunk_pos,
+                    (chunk_info.start_time, chunk_info.end_time, has_latched),
+                );
+            }
+        }
+    }
+
+    let bag_start = chunk_infos.values().map(|info| info.0).min().unwrap_or(0);
+    let bag_end = chunk_infos.values().map(|info| info.1).max().unwrap_or(0);
+    let mut report = TrimReport {
+        start_time: start.map_or(bag_start, |start| start.resolve(bag_start, bag_end)),
+        end_time: end.map_or(bag_end, |end| end.resolve(bag_start, bag_end)),
+        ..Default::default()
+    };
+    if report.start_time > report.end_time {
+        bail!(
+            "Window starts at {} after it ends at {}",
+            format_time(report.start_time),
+            format_time(report.end_time)
+        );
+    }
+
+    // Latched messages before the window until the first message in it
+    let mut latched = Some(BTreeMap::<u32, Vec<u8>>::new());
+    for record in bag.chunk_records() {
+        let ChunkRecord::Chunk(chunk) = record? else {
+            continue;
+        };
+        let needed = match chunk_infos.get(&chunk.offset) {
+            Some(&(start_time, end_time, has_latched)) => {
+                start_time <= report.end_time
+                    && (end_time >= report.start_time || has_latched && latched.is_some())
+            }
+            // Read chunks without chunk info rather than losing messages
+            None => true,
+        };
+        if !needed {
+            report.skipped_chunks += 1;
+            continue;
+        }
+
+        let chunk = chunk.decompress()?;
+        for message in chunk.messages() {
+            let MessageRecord::MessageData(message) = message? else {
+                continue;
+            };
+            let Some(&conn_id) = conn_ids.get(&message.conn_id) else {
+                bail!("Message on unknown connection {}", message.conn_id);
+            };
+            if message.time < report.start_time {
+                if let Some(latched) = latched.as_mut() {
+                    if latching.contains(&message.conn_id) {
+                        latched.insert(conn_id, message.data.to_vec());
+                    }
+                }
+            } else if message.time <= report.end_time {
+                if let Some(latched) = latched.take() {
+                    for (conn_id, data) in latched {
+                        writer.write_message(conn_id, report.start_time, &data)?;
+                        report.latched += 1;
+                    }
+                }
+                writer.write_message(conn_id, message.time, message.data)?;
+                report.messages += 1;
+            }
+        }
+    }
+    // Keep the latched state even if the window has no messages
+    for (conn_id, data) in latched.into_iter().flatten() {
+        writer.write_message(conn_id, report.start_time, &data)?;
+        report.latched += 1;
+    }
+    Ok(report)
+}
@@ -0,0 +1,380 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::bail;
+use byteorder::{WriteBytesExt, LE};
+
+use crate::record::{
+    encode_header, encode_time, write_record, Connection, MessageEncoding, BAG_MAGIC,
+    OP_BAG_HEADER, OP_CHUNK, OP_CHUNK_INFO, OP_CONNECTION, OP_INDEX_DATA, OP_MESSAGE_DATA,
+};
+
+/// The bag header record is padded so it can be rewritten in place once the index is known
+pub(crate) const BAG_HEADER_LEN: usize = 4096;
+/// Same threshold as the rosbag default
+const CHUNK_THRESHOLD: usize = 768 * 1024;
+
+/// Compression of the chunks, named as in the `compression` field of chunk records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Bz2,
+    Lz4,
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Bz2 => "bz2",
+            Compression::Lz4 => "lz4",
+        }
+    }
+
+    pub(crate) fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Bz2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::best());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Compression::Lz4 => {
+                // roslz4 only reads frames with independent blocks and without block checksums
+                let mut encoder = lz4::EncoderBuilder::new()
+                    .block_size(lz4::BlockSize::Max1MB)
+                    .block_mode(lz4::BlockMode::Independent)
+                    .block_checksum(lz4::liblz4::BlockChecksum::NoBlockChecksum)
+                    .checksum(lz4::ContentChecksum::ChecksumEnabled)
+                    .build(vec![])?;
+                encoder.write_all(data)?;
+                let (compressed, result) = encoder.finish();
+                result.map(|_| compressed)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "none" => Compression::None,
+            "bz2" => Compression::Bz2,
+            "lz4" => Compression::Lz4,
+            _ => bail!("Unknown compression '{}', expected none, bz2 or lz4", s),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WriterOptions {
+    pub compression: Compression,
+    /// A chunk is written once its uncompressed size reaches this many bytes
+    pub chunk_size: usize,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::None,
+            chunk_size: CHUNK_THRESHOLD,
+        }
+    }
+}
+
+/// Position, time range and message count per connection of a written chunk
+pub(crate) struct WrittenChunk {
+    pub(crate) pos: u64,
+    pub(crate) start_time: u64,
+    pub(crate) end_time: u64,
+    pub(crate) counts: BTreeMap<u32, u32>,
+}
+
+/// Writes a ROS1 bag 2.0 file with chunks, index data and the index section
+pub struct BagWriter<W: Write + Seek> {
+    writer: W,
+    options: WriterOptions,
+    pos: u64,
+    connections: Vec<Connection>,
+    // Connection records are written into the first chunk that uses them
+    connection_written: Vec<bool>,
+    chunk: Vec<u8>,
+    chunk_index: BTreeMap<u32, Vec<(u64, u32)>>,
+    chunk_start_time: u64,
+    chunk_end_time: u64,
+    chunk_infos: Vec<WrittenChunk>,
+}
+
+impl BagWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::create_with_options(path, WriterOptions::default())
+    }
+
+    pub fn create_with_options(path: &Path, options: WriterOptions) -> io::Result<Self> {
+        BagWriter::with_options(BufWriter::new(File::create(path)?), options)
+    }
+}
+
+impl<W: Write + Seek> BagWriter<W> {
+    pub fn new(writer: W) -> io::Result<Self> {
+        Self::with_options(writer, WriterOptions::default())
+    }
+
+    pub fn with_options(mut writer: W, options: WriterOptions) -> io::Result<Self> {
+        writer.write_all(BAG_MAGIC)?;
+        write_bag_header(&mut writer, 0, 0, 0)?;
+        Ok(Self {
+            writer,
+            options,
+            pos: (BAG_MAGIC.len() + BAG_HEADER_LEN) as u64,
+            connections: vec![],
+            connection_written: vec![],
+            chunk: vec![],
+            chunk_index: BTreeMap::new(),
+            chunk_start_time: u64::MAX,
+            chunk_end_time: 0,
+            chunk_infos: vec![],
+        })
+    }
+
+    /// Register a connection and return the id to write its messages with.
+    /// Ids are assigned in order, the id of `connection` is ignored.
+    pub fn add_connection(&mut self, connection: &Connection) -> u32 {
+        let id = self.connections.len() as u32;
+        self.connections.push(Connection {
+            id,
+            ..connection.clone()
+        });
+        self.connection_written.push(false);
+        id
+    }
+
+    pub fn write_message(&mut self, conn_id: u32, time: u64, data: &[u8]) -> io::Result<()> {
+        let connection = self.connections.get(conn_id as usize).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown connection id {}", conn_id),
+            )
+        })?;
+        if connection.encoding != MessageEncoding::Ros1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cannot write {} messages of {} to a bag",
+                    connection.encoding, connection.topic
+                ),
+            ));
+        }
+
+        if !self.connection_written[conn_id as usize] {
+            write_connection(&mut self.chunk, connection)?;
+            self.connection_written[conn_id as usize] = true;
+        }
+
+        let offset = self.chunk.len() as u32;
+        write_record(
+            &mut self.chunk,
+            &[
+                ("op", &[OP_MESSAGE_DATA]),
+                ("conn", &conn_id.to_le_bytes()),
+                ("time", &encode_time(time)),
+            ],
+            data,
+        )?;
+        self.chunk_index
+            .entry(conn_id)
+            .or_default()
+            .push((time, offset));
+        self.chunk_start_time = self.chunk_start_time.min(time);
+        self.chunk_end_time = self.chunk_end_time.max(time);
+
+        if self.chunk.len() >= self.options.chunk_size {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    /// Bytes written so far plus the open chunk before compression, without the index section
+    pub fn size(&self) -> u64 {
+        self.pos + self.chunk.len() as u64
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        if self.chunk_index.is_empty() {
+            return Ok(());
+        }
+
+        let chunk_pos = self.pos;
+        self.pos += write_chunk(&mut self.writer, self.options.compression, &self.chunk)?;
+
+        let mut counts = BTreeMap::new();
+        for (conn_id, entries) in self.chunk_index.iter() {
+            self.pos += write_index_data(&mut self.writer, *conn_id, entries)?;
+            counts.insert(*conn_id, entries.len() as u32);
+        }
+
+        self.chunk_infos.push(WrittenChunk {
+            pos: chunk_pos,
+            start_time: self.chunk_start_time,
+            end_time: self.chunk_end_time,
+            counts,
+        });
+        self.chunk.clear();
+        self.chunk_index.clear();
+        self.chunk_start_time = u64::MAX;
+        self.chunk_end_time = 0;
+        Ok(())
+    }
+
+    /// Write the index section, update the bag header and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_chunk()?;
+
+        let index_pos = self.pos;
+        for connection in self.connections.iter() {
+            self.pos += write_connection(&mut self.writer, connection)?;
+        }
+        for chunk_info in self.chunk_infos.iter() {
+            self.pos += write_chunk_info(&mut self.writer, chunk_info)?;
+        }
+
+        self.writer.seek(SeekFrom::Start(BAG_MAGIC.len() as u64))?;
+        write_bag_header(
+            &mut self.writer,
+            index_pos,
+            self.connections.len() as u32,
+            self.chunk_infos.len() as u32,
+        )?;
+        self.writer.seek(SeekFrom::Start(self.pos))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Write `data` as a chunk record with `compression` and return the record length
+pub(crate) fn write_chunk<W: Write>(
+    writer: &mut W,
+    compression: Compression,
+    data: &[u8],
+) -> io::Result<u64> {
+    let compressed = match compression {
+        Compression::None => Cow::Borrowed(data),
+        _ => Cow::Owned(compression.compress(data)?),
+    };
+    write_record(
+        writer,
+        &[
+            ("op", &[OP_CHUNK]),
+            ("compression", compression.name().as_bytes()),
+            ("size", &(data.len() as u32).to_le_bytes()),
+        ],
+        &compressed,
+    )
+}
+
+/// Write the index data record of a connection with the time and chunk offset of its messages
+pub(crate) fn write_index_data<W: Write>(
+    writer: &mut W,
+    conn_id: u32,
+    entries: &[(u64, u32)],
+) -> io::Result<u64> {
+    let mut data = Vec::with_capacity(entries.len() * 12);
+    for (time, offset) in entries {
+        data.extend_from_slice(&encode_time(*time));
+        data.write_u32::<LE>(*offset)?;
+    }
+    write_record(
+        writer,
+        &[
+            ("op", &[OP_INDEX_DATA]),
+            ("ver", &1u32.to_le_bytes()),
+            ("conn", &conn_id.to_le_bytes()),
+            ("count", &(entries.len() as u32).to_le_bytes()),
+        ],
+        &data,
+    )
+}
+
+pub(crate) fn write_chunk_info<W: Write>(
+    writer: &mut W,
+    chunk_info: &WrittenChunk,
+) -> io::Result<u64> {
+    let mut data = Vec::with_capacity(chunk_info.counts.len() * 8);
+    for (conn_id, count) in chunk_info.counts.iter() {
+        data.write_u32::<LE>(*conn_id)?;
+        data.write_u32::<LE>(*count)?;
+    }
+    write_record(
+        writer,
+        &[
+            ("op", &[OP_CHUNK_INFO]),
+            ("ver", &1u32.to_le_bytes()),
+            ("chunk_pos", &chunk_info.pos.to_le_bytes()),
+            ("start_time", &encode_time(chunk_info.start_time)),
+            ("end_time", &encode_time(chunk_info.end_time)),
+            ("count", &(chunk_info.counts.len() as u32).to_le_bytes()),
+        ],
+        &data,
+    )
+}
+
+pub(crate) fn write_bag_header<W: Write>(
+    writer: &mut W,
+    index_pos: u64,
+    conn_count: u32,
+    chunk_count: u32,
+) -> io::Result<()> {
+    let fields: [(&str, &[u8]); 4] = [
+        ("op", &[OP_BAG_HEADER]),
+        ("index_pos", &index_pos.to_le_bytes()),
+        ("conn_count", &conn_count.to_le_bytes()),
+        ("chunk_count", &chunk_count.to_le_bytes()),
+    ];
+    let padding = vec![b' '; BAG_HEADER_LEN - 8 - encode_header(&fields).len()];
+    write_record(writer, &fields, &padding)?;
+    Ok(())
+}
+
+pub(crate) fn write_connection<W: Write>(
+    writer: &mut W,
+    connection: &Connection,
+) -> io::Result<u64> {
+    let mut fields: Vec<(&str, &[u8])> = vec![
+        ("topic", connection.topic.as_bytes()),
+        ("type", connection.tp.as_bytes()),
+        ("md5sum", connection.md5sum.as_bytes()),
+        (
+            "message_definition",
+            connection.message_definition.as_bytes(),
+        ),
+    ];
+    if let Some(caller_id) = &connection.caller_id {
+        fields.push(("callerid", caller_id.as_bytes()));
+    }
+    if connection.latching {
+        fields.push(("latching", b"1"));
+    }
+    write_record(
+        writer,
+        &[
+            ("op", &[OP_CONNECTION]),
+            ("conn", &connection.id.to_le_bytes()),
+            ("topic", connection.topic.as_bytes()),
+        ],
+        &encode_header(&fields),
+    )
+}